@@ -0,0 +1,116 @@
+use binary_search_tree::avl_tree::AVLTreeMap;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+const N: u64 = 100_000;
+
+// 再現性のために固定のシードで生成する
+fn random_keys(n: u64) -> Vec<u64> {
+    let mut x: u64 = 88172645463325252;
+    (0..n)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        })
+        .collect()
+}
+
+// 同じ操作を非再帰版 (insert / remove) と再帰版 (insert_recursive / remove_recursive) で比べる
+// 再帰版は bench フィーチャーで公開されるので、cargo bench --features bench で実行する
+type Insert = fn(&mut AVLTreeMap<u64, u64>, u64, u64) -> bool;
+type Remove = fn(&mut AVLTreeMap<u64, u64>, &u64) -> Option<u64>;
+const VARIANTS: [(&str, Insert, Remove); 2] = [
+    ("iterative", AVLTreeMap::insert, AVLTreeMap::remove),
+    (
+        "recursive",
+        AVLTreeMap::insert_recursive,
+        AVLTreeMap::remove_recursive,
+    ),
+];
+
+fn insert_random(c: &mut Criterion) {
+    let keys = random_keys(N);
+    let mut group = c.benchmark_group("insert_random");
+    for (name, insert, _) in VARIANTS {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut tree = AVLTreeMap::new();
+                for &key in &keys {
+                    insert(&mut tree, key, key);
+                }
+                black_box(tree.size())
+            })
+        });
+    }
+    group.finish();
+}
+
+fn insert_sorted(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_sorted");
+    for (name, insert, _) in VARIANTS {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut tree = AVLTreeMap::new();
+                for key in 0..N {
+                    insert(&mut tree, key, key);
+                }
+                black_box(tree.size())
+            })
+        });
+    }
+    group.finish();
+}
+
+fn remove_random(c: &mut Criterion) {
+    let keys = random_keys(N);
+    let tree: AVLTreeMap<_, _> = keys.iter().map(|&key| (key, key)).collect();
+    let mut order = keys.clone();
+    order.reverse();
+    let mut group = c.benchmark_group("remove_random");
+    for (name, _, remove) in VARIANTS {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    tree.into_iter()
+                        .map(|(&k, &v)| (k, v))
+                        .collect::<AVLTreeMap<_, _>>()
+                },
+                |mut tree| {
+                    for key in &order {
+                        remove(&mut tree, key);
+                    }
+                    black_box(tree.size())
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn mixed(c: &mut Criterion) {
+    let keys = random_keys(N);
+    let mut group = c.benchmark_group("mixed");
+    for (name, insert, remove) in VARIANTS {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut tree = AVLTreeMap::new();
+                for &key in &keys {
+                    // 上位ビットで挿入と削除を振り分け、キーの範囲を狭めて衝突させる
+                    let k = key % 4096;
+                    if key >> 62 == 0 {
+                        remove(&mut tree, &k);
+                    } else {
+                        insert(&mut tree, k, key);
+                    }
+                }
+                black_box(tree.size())
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, insert_random, insert_sorted, remove_random, mixed);
+criterion_main!(benches);
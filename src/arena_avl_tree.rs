@@ -0,0 +1,711 @@
+use std::iter::FromIterator;
+
+use crate::avl_tree::ThreeWay;
+use crate::invariant::{check_balance, check_order, check_size_sum, InvariantViolation};
+use crate::stats::{BalanceCounts, TreeStats};
+
+// ノードは Box ではなく配列に置き、子は配列の添字で指す
+type Index = u32;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: Option<Index>,
+    right: Option<Index>,
+    size: u32,
+    state: ThreeWay,
+}
+
+enum Slot<K, V> {
+    Occupied(Node<K, V>),
+    // 空いている添字は連結リストでつなぎ、次の挿入で再利用する
+    Vacant { next_free: Option<Index> },
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Self {
+        Node {
+            key,
+            value,
+            left: None,
+            right: None,
+            size: 1,
+            state: ThreeWay::Equal,
+        }
+    }
+}
+
+/**
+ * ノードを 1 つの Vec にまとめて確保する AVL 木
+ * 子は u32 の添字で指し、削除で空いた場所は次の挿入で再利用する
+ * 回転と平衡状態の調整は avl_tree の grown_left などと同じ手順を添字の上で書き直している
+ * 共通の関数は &mut N だけで子をたどり、回転では親と子の中身を入れ替える (NodeTrait::rotate_right) が、
+ * このノードは子を持たず配列の添字を持つだけなので、配列を借りずには子に触れられない
+ * 共通の関数に配列を渡す引数を足すと Box で子を持つすべての木の経路に影響するため、ここでは別に持ち、
+ * 同じ操作列で AVLTreeMap と同じ形の木になることをテストで確かめている
+ * 不変条件の検査は invariant の関数を共有する
+ * 公開している操作は AVLTreeMap のうち、挿入・削除・検索、min / max、順序統計量、keys / values、
+ * validate と stats、それに容量を扱う with_capacity / capacity / reserve / shrink_to_fit に限る
+ * カーソル、retain、merge_with、diff、スナップショット、to_dot / fmt_tree などは持たない
+ */
+pub struct ArenaAVLTreeMap<K: Ord, V> {
+    slots: Vec<Slot<K, V>>,
+    free_head: Option<Index>,
+    root: Option<Index>,
+}
+
+impl<K: Ord, V> ArenaAVLTreeMap<K, V> {
+    fn node(&self, idx: Index) -> &Node<K, V> {
+        match &self.slots[idx as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant { .. } => unreachable!(),
+        }
+    }
+
+    fn node_mut(&mut self, idx: Index) -> &mut Node<K, V> {
+        match &mut self.slots[idx as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant { .. } => unreachable!(),
+        }
+    }
+
+    fn alloc(&mut self, node: Node<K, V>) -> Index {
+        match self.free_head {
+            Some(idx) => {
+                self.free_head = match self.slots[idx as usize] {
+                    Slot::Vacant { next_free } => next_free,
+                    Slot::Occupied(_) => unreachable!(),
+                };
+                self.slots[idx as usize] = Slot::Occupied(node);
+                idx
+            }
+            None => {
+                let idx = Index::try_from(self.slots.len()).expect("too many nodes");
+                self.slots.push(Slot::Occupied(node));
+                idx
+            }
+        }
+    }
+
+    fn dealloc(&mut self, idx: Index) -> Node<K, V> {
+        let slot = std::mem::replace(
+            &mut self.slots[idx as usize],
+            Slot::Vacant {
+                next_free: self.free_head,
+            },
+        );
+        self.free_head = Some(idx);
+        match slot {
+            Slot::Occupied(node) => node,
+            Slot::Vacant { .. } => unreachable!(),
+        }
+    }
+
+    fn size_of(&self, ptr: Option<Index>) -> u32 {
+        ptr.map_or(0, |idx| self.node(idx).size)
+    }
+
+    fn update(&mut self, idx: Index) {
+        let node = self.node(idx);
+        let size = self.size_of(node.left) + self.size_of(node.right) + 1;
+        self.node_mut(idx).size = size;
+    }
+
+    fn state(&self, idx: Index) -> ThreeWay {
+        self.node(idx).state
+    }
+
+    fn set_state(&mut self, idx: Index, state: ThreeWay) {
+        self.node_mut(idx).state = state;
+    }
+
+    // 回転後の部分木の根を返す
+    fn rotate_right(&mut self, idx: Index) -> Index {
+        let left = self.node(idx).left.unwrap();
+        self.node_mut(idx).left = self.node(left).right;
+        self.update(idx);
+        self.node_mut(left).right = Some(idx);
+        self.update(left);
+        left
+    }
+
+    fn rotate_left(&mut self, idx: Index) -> Index {
+        let right = self.node(idx).right.unwrap();
+        self.node_mut(idx).right = self.node(right).left;
+        self.update(idx);
+        self.node_mut(right).left = Some(idx);
+        self.update(right);
+        right
+    }
+
+    // 二重回転のあと、新しい根の元の状態から子の状態を決める
+    fn fix_double_rotated(&mut self, idx: Index, state: ThreeWay) {
+        self.set_state(idx, ThreeWay::Equal);
+        let node = self.node(idx);
+        let (left, right) = (node.left.unwrap(), node.right.unwrap());
+        self.set_state(
+            left,
+            match state {
+                ThreeWay::Left | ThreeWay::Equal => ThreeWay::Equal,
+                ThreeWay::Right => ThreeWay::Left,
+            },
+        );
+        self.set_state(
+            right,
+            match state {
+                ThreeWay::Left => ThreeWay::Right,
+                ThreeWay::Right | ThreeWay::Equal => ThreeWay::Equal,
+            },
+        );
+    }
+
+    // 左の子の右の子を根に持ってくる二重回転
+    fn double_rotate_right(&mut self, idx: Index) -> Index {
+        let left = self.node(idx).left.unwrap();
+        let state = self.state(self.node(left).right.unwrap());
+        self.node_mut(idx).left = Some(self.rotate_left(left));
+        let root = self.rotate_right(idx);
+        self.fix_double_rotated(root, state);
+        root
+    }
+
+    // 右の子の左の子を根に持ってくる二重回転
+    fn double_rotate_left(&mut self, idx: Index) -> Index {
+        let right = self.node(idx).right.unwrap();
+        let state = self.state(self.node(right).left.unwrap());
+        self.node_mut(idx).right = Some(self.rotate_right(right));
+        let root = self.rotate_left(idx);
+        self.fix_double_rotated(root, state);
+        root
+    }
+
+    // 挿入によって左の子の高さが 1 増えたときに呼ぶ
+    // 返り値は (部分木の根, 木の高さが増えたかどうか)
+    fn grown_left(&mut self, idx: Index) -> (Index, bool) {
+        match self.state(idx) {
+            ThreeWay::Equal => {
+                self.set_state(idx, ThreeWay::Left);
+                (idx, true)
+            }
+            ThreeWay::Right => {
+                self.set_state(idx, ThreeWay::Equal);
+                (idx, false)
+            }
+            ThreeWay::Left => {
+                let left = self.node(idx).left.unwrap();
+                let root = match self.state(left) {
+                    ThreeWay::Left => {
+                        let root = self.rotate_right(idx);
+                        self.set_state(root, ThreeWay::Equal);
+                        self.set_state(idx, ThreeWay::Equal);
+                        root
+                    }
+                    ThreeWay::Right => self.double_rotate_right(idx),
+                    ThreeWay::Equal => unreachable!(),
+                };
+                (root, false)
+            }
+        }
+    }
+
+    // 挿入によって右の子の高さが 1 増えたときに呼ぶ
+    // 返り値は (部分木の根, 木の高さが増えたかどうか)
+    fn grown_right(&mut self, idx: Index) -> (Index, bool) {
+        match self.state(idx) {
+            ThreeWay::Equal => {
+                self.set_state(idx, ThreeWay::Right);
+                (idx, true)
+            }
+            ThreeWay::Left => {
+                self.set_state(idx, ThreeWay::Equal);
+                (idx, false)
+            }
+            ThreeWay::Right => {
+                let right = self.node(idx).right.unwrap();
+                let root = match self.state(right) {
+                    ThreeWay::Right => {
+                        let root = self.rotate_left(idx);
+                        self.set_state(root, ThreeWay::Equal);
+                        self.set_state(idx, ThreeWay::Equal);
+                        root
+                    }
+                    ThreeWay::Left => self.double_rotate_left(idx),
+                    ThreeWay::Equal => unreachable!(),
+                };
+                (root, false)
+            }
+        }
+    }
+
+    // 削除によって左の子の高さが 1 減ったときに呼ぶ
+    // 返り値は (部分木の根, 木の高さが減ったかどうか)
+    fn shrunk_left(&mut self, idx: Index) -> (Index, bool) {
+        match self.state(idx) {
+            ThreeWay::Equal => {
+                self.set_state(idx, ThreeWay::Right);
+                (idx, false)
+            }
+            ThreeWay::Left => {
+                self.set_state(idx, ThreeWay::Equal);
+                (idx, true)
+            }
+            ThreeWay::Right => {
+                let right = self.node(idx).right.unwrap();
+                match self.state(right) {
+                    ThreeWay::Equal => {
+                        let root = self.rotate_left(idx);
+                        self.set_state(root, ThreeWay::Left);
+                        (root, false)
+                    }
+                    ThreeWay::Right => {
+                        let root = self.rotate_left(idx);
+                        self.set_state(root, ThreeWay::Equal);
+                        self.set_state(idx, ThreeWay::Equal);
+                        (root, true)
+                    }
+                    ThreeWay::Left => (self.double_rotate_left(idx), true),
+                }
+            }
+        }
+    }
+
+    // 削除によって右の子の高さが 1 減ったときに呼ぶ
+    // 返り値は (部分木の根, 木の高さが減ったかどうか)
+    fn shrunk_right(&mut self, idx: Index) -> (Index, bool) {
+        match self.state(idx) {
+            ThreeWay::Equal => {
+                self.set_state(idx, ThreeWay::Left);
+                (idx, false)
+            }
+            ThreeWay::Right => {
+                self.set_state(idx, ThreeWay::Equal);
+                (idx, true)
+            }
+            ThreeWay::Left => {
+                let left = self.node(idx).left.unwrap();
+                match self.state(left) {
+                    ThreeWay::Equal => {
+                        let root = self.rotate_right(idx);
+                        self.set_state(root, ThreeWay::Right);
+                        (root, false)
+                    }
+                    ThreeWay::Left => {
+                        let root = self.rotate_right(idx);
+                        self.set_state(root, ThreeWay::Equal);
+                        self.set_state(idx, ThreeWay::Equal);
+                        (root, true)
+                    }
+                    ThreeWay::Right => (self.double_rotate_right(idx), true),
+                }
+            }
+        }
+    }
+
+    /**
+     * 返り値は (部分木の根, 挿入されたかどうか, 木の高さが増えたかどうか)
+     */
+    fn insert_rec(&mut self, ptr: Option<Index>, key: K, value: V) -> (Index, bool, bool) {
+        let idx = match ptr {
+            Some(idx) => idx,
+            None => return (self.alloc(Node::new(key, value)), true, true),
+        };
+
+        match key.cmp(&self.node(idx).key) {
+            std::cmp::Ordering::Less => {
+                let (left, inserted, increased) = self.insert_rec(self.node(idx).left, key, value);
+                self.node_mut(idx).left = Some(left);
+                self.update(idx);
+                if !increased {
+                    return (idx, inserted, false);
+                }
+                let (root, increased) = self.grown_left(idx);
+                (root, true, increased)
+            }
+            std::cmp::Ordering::Equal => {
+                self.node_mut(idx).value = value;
+                (idx, false, false)
+            }
+            std::cmp::Ordering::Greater => {
+                let (right, inserted, increased) =
+                    self.insert_rec(self.node(idx).right, key, value);
+                self.node_mut(idx).right = Some(right);
+                self.update(idx);
+                if !increased {
+                    return (idx, inserted, false);
+                }
+                let (root, increased) = self.grown_right(idx);
+                (root, true, increased)
+            }
+        }
+    }
+
+    /**
+     * 返り値は (部分木の根, 取り外したノードの添字, 木の高さが減ったかどうか)
+     */
+    fn remove_rec(&mut self, ptr: Option<Index>, key: &K) -> (Option<Index>, Option<Index>, bool) {
+        let idx = match ptr {
+            Some(idx) => idx,
+            None => return (None, None, false),
+        };
+
+        match key.cmp(&self.node(idx).key) {
+            std::cmp::Ordering::Equal => {
+                let node = self.node(idx);
+                match (node.left, node.right) {
+                    (None, None) => (None, Some(idx), true),
+                    (Some(child), None) | (None, Some(child)) => (Some(child), Some(idx), true),
+                    (Some(left), Some(right)) => {
+                        // 削除したいノードが子を2つ持っている場合
+                        // 左の子の最大値を取り外して 削除したいノードの位置に置く
+                        let state = node.state;
+                        let (left, max, decreased) = self.remove_max_rec(left);
+                        let max_node = self.node_mut(max);
+                        max_node.left = left;
+                        max_node.right = Some(right);
+                        max_node.state = state;
+                        self.update(max);
+                        if !decreased {
+                            return (Some(max), Some(idx), false);
+                        }
+                        let (root, decreased) = self.shrunk_left(max);
+                        (Some(root), Some(idx), decreased)
+                    }
+                }
+            }
+            std::cmp::Ordering::Greater => {
+                let (right, removed, decreased) = self.remove_rec(self.node(idx).right, key);
+                self.node_mut(idx).right = right;
+                if removed.is_none() {
+                    return (Some(idx), None, false);
+                }
+                self.update(idx);
+                if !decreased {
+                    return (Some(idx), removed, false);
+                }
+                let (root, decreased) = self.shrunk_right(idx);
+                (Some(root), removed, decreased)
+            }
+            std::cmp::Ordering::Less => {
+                let (left, removed, decreased) = self.remove_rec(self.node(idx).left, key);
+                self.node_mut(idx).left = left;
+                if removed.is_none() {
+                    return (Some(idx), None, false);
+                }
+                self.update(idx);
+                if !decreased {
+                    return (Some(idx), removed, false);
+                }
+                let (root, decreased) = self.shrunk_left(idx);
+                (Some(root), removed, decreased)
+            }
+        }
+    }
+
+    /**
+     * 返り値は (部分木の根, 取り外したノードの添字, 木の高さが減ったかどうか)
+     */
+    fn remove_max_rec(&mut self, idx: Index) -> (Option<Index>, Index, bool) {
+        let right = match self.node(idx).right {
+            Some(right) => right,
+            None => return (self.node(idx).left, idx, true),
+        };
+
+        let (right, removed, decreased) = self.remove_max_rec(right);
+        self.node_mut(idx).right = right;
+        self.update(idx);
+        if !decreased {
+            return (Some(idx), removed, false);
+        }
+        let (root, decreased) = self.shrunk_right(idx);
+        (Some(root), removed, decreased)
+    }
+
+    fn search(&self, key: &K) -> Option<Index> {
+        let mut idx = self.root?;
+        loop {
+            let node = self.node(idx);
+            idx = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left?,
+                std::cmp::Ordering::Greater => node.right?,
+                std::cmp::Ordering::Equal => break Some(idx),
+            };
+        }
+    }
+
+    fn collect<'a, T>(
+        &'a self,
+        ptr: Option<Index>,
+        f: &impl Fn(&'a Node<K, V>) -> T,
+        vec: &mut Vec<T>,
+    ) {
+        if let Some(idx) = ptr {
+            let node = self.node(idx);
+            self.collect(node.left, f, vec);
+            vec.push(f(node));
+            self.collect(node.right, f, vec);
+        }
+    }
+
+    // 部分木の高さを返し、深さの和と平衡状態ごとのノードの個数を数える
+    fn walk_stats(
+        &self,
+        ptr: Option<Index>,
+        depth: usize,
+        depth_sum: &mut usize,
+        balance: &mut BalanceCounts,
+    ) -> usize {
+        let node = match ptr {
+            Some(idx) => self.node(idx),
+            None => return 0,
+        };
+        *depth_sum += depth;
+        balance.add(node.state);
+        let left = self.walk_stats(node.left, depth + 1, depth_sum, balance);
+        let right = self.walk_stats(node.right, depth + 1, depth_sum, balance);
+        left.max(right) + 1
+    }
+
+    // validate_avl と同じ検査を添字でたどって行い、部分木の高さを返す
+    // 各検査は invariant の関数を共有する
+    fn validate_rec(
+        &self,
+        ptr: Option<Index>,
+        lower: Option<&K>,
+        upper: Option<&K>,
+        path: &mut Vec<ThreeWay>,
+    ) -> Result<usize, InvariantViolation> {
+        let node = match ptr {
+            Some(idx) => self.node(idx),
+            None => return Ok(0),
+        };
+        check_order(&node.key, lower, upper, path)?;
+
+        path.push(ThreeWay::Left);
+        let left_height = self.validate_rec(node.left, lower, Some(&node.key), path)?;
+        path.pop();
+        path.push(ThreeWay::Right);
+        let right_height = self.validate_rec(node.right, Some(&node.key), upper, path)?;
+        path.pop();
+
+        let expected = self.size_of(node.left) as usize + self.size_of(node.right) as usize + 1;
+        check_size_sum(node.size as usize, expected, path)?;
+        check_balance(node.state, left_height, right_height, path)
+    }
+
+    // 生きているノードを帰りがけ順に new_slots へ移し、移した先の添字を返す
+    fn compact(&mut self, idx: Index, new_slots: &mut Vec<Slot<K, V>>) -> Index {
+        let mut node = self.dealloc(idx);
+        node.left = node.left.map(|left| self.compact(left, new_slots));
+        node.right = node.right.map(|right| self.compact(right, new_slots));
+        new_slots.push(Slot::Occupied(node));
+        (new_slots.len() - 1) as Index
+    }
+
+    pub fn new() -> Self {
+        ArenaAVLTreeMap {
+            slots: Vec::new(),
+            free_head: None,
+            root: None,
+        }
+    }
+
+    /**
+     * capacity 個の要素を再確保なしで持てる木を作る
+     */
+    pub fn with_capacity(capacity: usize) -> Self {
+        ArenaAVLTreeMap {
+            slots: Vec::with_capacity(capacity),
+            free_head: None,
+            root: None,
+        }
+    }
+
+    /**
+     * 再確保なしで持てる要素の個数を返す
+     */
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /**
+     * 少なくとも additional 個の要素を再確保なしで追加できるようにする
+     * 削除で空いた場所も数に入れる
+     */
+    pub fn reserve(&mut self, additional: usize) {
+        let vacant = self.slots.len() - self.size();
+        self.slots.reserve(additional.saturating_sub(vacant));
+    }
+
+    /**
+     * 削除で空いた場所を詰めてから、余分な領域を解放する
+     */
+    pub fn shrink_to_fit(&mut self) {
+        let mut new_slots = Vec::with_capacity(self.size());
+        self.root = self.root.map(|root| self.compact(root, &mut new_slots));
+        self.slots = new_slots;
+        self.free_head = None;
+        self.slots.shrink_to_fit();
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        let (root, inserted, _) = self.insert_rec(self.root, key, value);
+        self.root = Some(root);
+        inserted
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (root, removed, _) = self.remove_rec(self.root, key);
+        self.root = root;
+        removed.map(|idx| self.dealloc(idx).value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.search(key).map(|idx| &self.node(idx).value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.search(key).map(|idx| &mut self.node_mut(idx).value)
+    }
+
+    pub fn min(&self) -> Option<(&K, &V)> {
+        let mut node = self.node(self.root?);
+        while let Some(nxt) = node.left {
+            node = self.node(nxt);
+        }
+        Some((&node.key, &node.value))
+    }
+
+    pub fn max(&self) -> Option<(&K, &V)> {
+        let mut node = self.node(self.root?);
+        while let Some(nxt) = node.right {
+            node = self.node(nxt);
+        }
+        Some((&node.key, &node.value))
+    }
+
+    pub fn size(&self) -> usize {
+        self.size_of(self.root) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order as u32;
+        let mut node = self.node(self.root.unwrap());
+        loop {
+            let left_size = self.size_of(node.left);
+            match left_size.cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= left_size + 1;
+                    node = self.node(node.right.unwrap());
+                }
+                std::cmp::Ordering::Greater => {
+                    node = self.node(node.left.unwrap());
+                }
+                std::cmp::Ordering::Equal => {
+                    break Some((&node.key, &node.value));
+                }
+            }
+        }
+    }
+
+    /**
+     * key 未満である要素の個数を返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        let mut order = 0;
+        let mut node = match self.root {
+            None => return 0,
+            Some(idx) => self.node(idx),
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left {
+                        Some(idx) => self.node(idx),
+                        None => break order as usize,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    order += self.size_of(node.left) + 1;
+                    node = match node.right {
+                        Some(idx) => self.node(idx),
+                        None => break order as usize,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break (order + self.size_of(node.left)) as usize;
+                }
+            }
+        }
+    }
+
+    pub fn keys(&self) -> Vec<&K> {
+        let mut vec = Vec::with_capacity(self.size());
+        self.collect(self.root, &|node| &node.key, &mut vec);
+        vec
+    }
+
+    pub fn values(&self) -> Vec<&V> {
+        let mut vec = Vec::with_capacity(self.size());
+        self.collect(self.root, &|node| &node.value, &mut vec);
+        vec
+    }
+
+    /**
+     * 木の不変条件がすべて成り立っているかを確かめる
+     * 成り立っていない場合は、違反のあったノードの根からの経路とその内容を返す
+     */
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        self.validate_rec(self.root, None, None, &mut Vec::new())
+            .map(|_| ())
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     * 領域は削除で空いた場所や未使用の容量も含めた、配列全体の大きさで見積もる
+     */
+    pub fn stats(&self) -> TreeStats {
+        let mut depth_sum = 0;
+        let mut balance = BalanceCounts::default();
+        let height = self.walk_stats(self.root, 0, &mut depth_sum, &mut balance);
+        TreeStats::new(
+            self.size(),
+            depth_sum,
+            height,
+            Some(balance),
+            self.slots.capacity() * std::mem::size_of::<Slot<K, V>>(),
+        )
+    }
+}
+impl<'a, K: Ord, V> IntoIterator for &'a ArenaAVLTreeMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut vec = Vec::with_capacity(self.size());
+        self.collect(self.root, &|node| (&node.key, &node.value), &mut vec);
+        vec.into_iter()
+    }
+}
+impl<K: Ord, V> Default for ArenaAVLTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord, V> FromIterator<(K, V)> for ArenaAVLTreeMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let iter = iter.into_iter();
+        let mut tree = Self::with_capacity(iter.size_hint().0);
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::VecDeque;
+use std::iter::FromIterator;
+use std::ops::{Bound, RangeBounds};
+
+use crate::avl_tree::{insert_rec, remove_rec, AVLNode, ThreeWay};
+use crate::bound::{satisfies_lower, satisfies_upper};
+use crate::node::NodeTrait;
+use crate::pretty::fmt_tree;
+use crate::stats::{avl_tree_stats, TreeStats};
+
+type NodePtrInner<K, V> = Option<Box<Node<K, V>>>;
+struct NodePtr<K, V>(NodePtrInner<K, V>);
+struct Node<K, V> {
+    key: K,
+    // 挿入された順に並ぶ
+    values: VecDeque<V>,
+    left: NodePtr<K, V>,
+    right: NodePtr<K, V>,
+    // 部分木に含まれる (キー, 値) の組の個数
+    size: usize,
+    state: ThreeWay,
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Self {
+        Node {
+            key,
+            values: VecDeque::from(vec![value]),
+            left: NodePtr(None),
+            right: NodePtr(None),
+            size: 1,
+            state: ThreeWay::Equal,
+        }
+    }
+}
+
+// fmt_tree で各ノードに表示する内容
+// 最初の行がキーで、残りの行が補足の情報になる
+fn node_label<K: std::fmt::Debug, V>(node: &Node<K, V>) -> Vec<String> {
+    vec![
+        format!("{:?}", node.key),
+        format!("values: {}", node.values.len()),
+        format!("size: {}", node.size),
+        format!("state: {:?}", node.state),
+    ]
+}
+
+impl<K, V> NodeTrait for Node<K, V> {
+    type Ptr = Box<Self>;
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn size_mut(&mut self) -> &mut usize {
+        &mut self.size
+    }
+
+    fn left(&self) -> Option<&Self> {
+        self.left.0.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.0.as_deref()
+    }
+
+    fn left_mut(&mut self) -> Option<&mut Self> {
+        self.left.0.as_deref_mut()
+    }
+
+    fn right_mut(&mut self) -> Option<&mut Self> {
+        self.right.0.as_deref_mut()
+    }
+
+    fn take_left(&mut self) -> Option<Box<Self>> {
+        self.left.0.take()
+    }
+
+    fn take_right(&mut self) -> Option<Box<Self>> {
+        self.right.0.take()
+    }
+
+    fn set_left(&mut self, node: Option<Box<Self>>) {
+        self.left.0 = node;
+    }
+
+    fn set_right(&mut self, node: Option<Box<Self>>) {
+        self.right.0 = node;
+    }
+
+    fn update(&mut self) {
+        self.size = self.left.size() + self.right.size() + self.values.len();
+    }
+}
+impl<K: Ord, V> AVLNode for Node<K, V> {
+    type Key = K;
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn state(&self) -> ThreeWay {
+        self.state
+    }
+
+    fn state_mut(&mut self) -> &mut ThreeWay {
+        &mut self.state
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a NodePtr<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(&node.left);
+            vec.extend(node.values.iter().map(|value| (&node.key, value)));
+            vec.extend(&node.right);
+        }
+        vec.into_iter()
+    }
+}
+impl<K, V> NodePtr<K, V> {
+    fn size(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.size)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn keys(&self) -> Vec<&K> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.keys());
+            vec.push(&node.key);
+            vec.extend(node.right.keys());
+        }
+        vec
+    }
+
+    fn values(&self) -> Vec<&V> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.values());
+            vec.extend(node.values.iter());
+            vec.extend(node.right.values());
+        }
+        vec
+    }
+}
+
+impl<K: Ord, V> NodePtr<K, V> {
+    fn insert(&mut self, key: K, value: V) -> bool {
+        insert_rec(&mut self.0, key, |key, node| match node {
+            Some(node) => {
+                node.values.push_back(value);
+                None
+            }
+            None => Some(Node::new(key, value)),
+        })
+        .0
+    }
+
+    fn remove(&mut self, key: &K) -> VecDeque<V> {
+        remove_rec(&mut self.0, key)
+            .0
+            .map_or_else(VecDeque::new, |node| node.values)
+    }
+
+    // 最初に挿入された値を取り出したあと、根までの size を計算し直す
+    // 値が 1 つしかないノードは木から取り除かなければならないため、ここでは扱わない
+    fn pop_front(&mut self, key: &K) -> Option<V> {
+        let node = self.0.as_mut()?;
+        let ret = match key.cmp(&node.key) {
+            std::cmp::Ordering::Less => node.left.pop_front(key),
+            std::cmp::Ordering::Greater => node.right.pop_front(key),
+            std::cmp::Ordering::Equal => node.values.pop_front(),
+        };
+        node.update();
+        ret
+    }
+
+    fn search(&self, key: &K) -> Option<&VecDeque<V>> {
+        let mut node = self.0.as_ref()?;
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_ref()?,
+                std::cmp::Ordering::Greater => node.right.0.as_ref()?,
+                std::cmp::Ordering::Equal => break Some(&node.values),
+            };
+        }
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.left.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, node.values.front()?))
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.right.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, node.values.back()?))
+    }
+
+    fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
+        let mut node = self.0.as_ref().unwrap();
+        loop {
+            if rest < node.left.size() {
+                node = node.left.0.as_ref().unwrap();
+                continue;
+            }
+            rest -= node.left.size();
+            if rest < node.values.len() {
+                break Some((&node.key, &node.values[rest]));
+            }
+            rest -= node.values.len();
+            node = node.right.0.as_ref().unwrap();
+        }
+    }
+
+    fn order_of_key(&self, key: &K) -> usize {
+        let mut order = 0;
+        let mut node = match self.0.as_ref() {
+            None => return 0,
+            Some(node) => node,
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    order += node.left.size() + node.values.len();
+                    node = match node.right.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break order + node.left.size();
+                }
+            }
+        }
+    }
+
+    fn range<'a>(&'a self, lower: Bound<&K>, upper: Bound<&K>, vec: &mut Vec<(&'a K, &'a V)>) {
+        if let Some(node) = &self.0 {
+            let above_lower = satisfies_lower(&node.key, lower);
+            let below_upper = satisfies_upper(&node.key, upper);
+            if above_lower {
+                node.left.range(lower, upper, vec);
+            }
+            if above_lower && below_upper {
+                vec.extend(node.values.iter().map(|value| (&node.key, value)));
+            }
+            if below_upper {
+                node.right.range(lower, upper, vec);
+            }
+        }
+    }
+}
+
+/**
+ * 同じキーに複数の値を持てる AVL 木
+ * 同じキーの値は挿入された順に並び、順序統計量は (キー, 値) の組ごとに数える
+ */
+pub struct AVLTreeMultiMap<K: Ord, V> {
+    root: NodePtr<K, V>,
+}
+impl<K: Ord, V> AVLTreeMultiMap<K, V> {
+    pub fn new() -> Self {
+        AVLTreeMultiMap {
+            root: NodePtr(None),
+        }
+    }
+
+    /**
+     * key の値の末尾に value を追加する
+     * key がそれまで存在しなかった場合は true を返す
+     */
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        self.root.insert(key, value)
+    }
+
+    /**
+     * key の値を挿入された順にすべて返す
+     */
+    pub fn get_all(&self, key: &K) -> Vec<&V> {
+        self.root
+            .search(key)
+            .map_or_else(Vec::new, |values| values.iter().collect())
+    }
+
+    /**
+     * key の値のうち最初に挿入されたものを返す
+     */
+    pub fn get_first(&self, key: &K) -> Option<&V> {
+        self.root.search(key)?.front()
+    }
+
+    /**
+     * key の値のうち最初に挿入されたものを取り除いて返す
+     */
+    pub fn remove_first(&mut self, key: &K) -> Option<V> {
+        match self.root.search(key)?.len() {
+            1 => self.root.remove(key).pop_front(),
+            _ => self.root.pop_front(key),
+        }
+    }
+
+    /**
+     * key の値をすべて取り除き、挿入された順に返す
+     */
+    pub fn remove_all(&mut self, key: &K) -> Vec<V> {
+        self.root.remove(key).into()
+    }
+
+    /**
+     * key の値の個数を返す
+     */
+    pub fn count(&self, key: &K) -> usize {
+        self.root.search(key).map_or(0, |values| values.len())
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.root.search(key).is_some()
+    }
+
+    /**
+     * 最小のキーのうち最初に挿入された値を返す
+     */
+    pub fn min(&self) -> Option<(&K, &V)> {
+        self.root.min()
+    }
+
+    /**
+     * 最大のキーのうち最後に挿入された値を返す
+     */
+    pub fn max(&self) -> Option<(&K, &V)> {
+        self.root.max()
+    }
+
+    /**
+     * (キー, 値) の組の個数を返す
+     */
+    pub fn size(&self) -> usize {
+        self.root.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    /**
+     * (キー, 値) の組をキーの昇順、同じキーは挿入順に並べたときの order 番目を返す
+     */
+    pub fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        self.root.find_by_order(order)
+    }
+
+    /**
+     * キーが key 未満である (キー, 値) の組の個数を返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        self.root.order_of_key(key)
+    }
+
+    /**
+     * キーが range に含まれる (キー, 値) の組をキーの昇順、同じキーは挿入順に返す
+     */
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(&K, &V)> {
+        let mut vec = Vec::new();
+        self.root
+            .range(range.start_bound(), range.end_bound(), &mut vec);
+        vec
+    }
+
+    /**
+     * 異なるキーを昇順に返す
+     */
+    pub fn keys(&self) -> Vec<&K> {
+        self.root.keys()
+    }
+
+    pub fn values(&self) -> Vec<&V> {
+        self.root.values()
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     */
+    pub fn stats(&self) -> TreeStats {
+        avl_tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + std::fmt::Debug, V> AVLTreeMultiMap<K, V> {
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに値の個数、部分木の大きさと平衡状態を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
+}
+impl<'a, K: Ord, V> IntoIterator for &'a AVLTreeMultiMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.root.into_iter()
+    }
+}
+impl<K: Ord, V> Default for AVLTreeMultiMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord, V> FromIterator<(K, V)> for AVLTreeMultiMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
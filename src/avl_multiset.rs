@@ -0,0 +1,463 @@
+use std::iter::FromIterator;
+
+use crate::avl_tree::{insert_rec, remove_rec, AVLNode, ThreeWay};
+use crate::node::NodeTrait;
+use crate::pretty::fmt_tree;
+use crate::stats::{avl_tree_stats, TreeStats};
+
+type NodePtrInner<K> = Option<Box<Node<K>>>;
+struct NodePtr<K>(NodePtrInner<K>);
+struct Node<K> {
+    key: K,
+    count: usize,
+    left: NodePtr<K>,
+    right: NodePtr<K>,
+    // 部分木に含まれる要素の個数 (重複を含む)
+    size: usize,
+    state: ThreeWay,
+}
+
+impl<K> Node<K> {
+    fn new(key: K, count: usize) -> Self {
+        Node {
+            key,
+            count,
+            left: NodePtr(None),
+            right: NodePtr(None),
+            size: count,
+            state: ThreeWay::Equal,
+        }
+    }
+}
+
+// fmt_tree で各ノードに表示する内容
+// 最初の行がキーで、残りの行が補足の情報になる
+fn node_label<K: std::fmt::Debug>(node: &Node<K>) -> Vec<String> {
+    vec![
+        format!("{:?}", node.key),
+        format!("count: {}", node.count),
+        format!("size: {}", node.size),
+        format!("state: {:?}", node.state),
+    ]
+}
+
+impl<K> NodeTrait for Node<K> {
+    type Ptr = Box<Self>;
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn size_mut(&mut self) -> &mut usize {
+        &mut self.size
+    }
+
+    fn left(&self) -> Option<&Self> {
+        self.left.0.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.0.as_deref()
+    }
+
+    fn left_mut(&mut self) -> Option<&mut Self> {
+        self.left.0.as_deref_mut()
+    }
+
+    fn right_mut(&mut self) -> Option<&mut Self> {
+        self.right.0.as_deref_mut()
+    }
+
+    fn take_left(&mut self) -> Option<Box<Self>> {
+        self.left.0.take()
+    }
+
+    fn take_right(&mut self) -> Option<Box<Self>> {
+        self.right.0.take()
+    }
+
+    fn set_left(&mut self, node: Option<Box<Self>>) {
+        self.left.0 = node;
+    }
+
+    fn set_right(&mut self, node: Option<Box<Self>>) {
+        self.right.0 = node;
+    }
+
+    fn update(&mut self) {
+        self.size = self.left.size() + self.right.size() + self.count;
+    }
+}
+impl<K: Ord> AVLNode for Node<K> {
+    type Key = K;
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn state(&self) -> ThreeWay {
+        self.state
+    }
+
+    fn state_mut(&mut self) -> &mut ThreeWay {
+        &mut self.state
+    }
+}
+
+impl<'a, K> IntoIterator for &'a NodePtr<K> {
+    type Item = (&'a K, usize);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(&node.left);
+            vec.push((&node.key, node.count));
+            vec.extend(&node.right);
+        }
+        vec.into_iter()
+    }
+}
+impl<K> NodePtr<K> {
+    fn size(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.size)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn keys(&self) -> Vec<&K> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.keys());
+            vec.push(&node.key);
+            vec.extend(node.right.keys());
+        }
+        vec
+    }
+}
+
+impl<K: Ord> NodePtr<K> {
+    fn insert(&mut self, key: K, count: usize) -> bool {
+        insert_rec(&mut self.0, key, |key, node| match node {
+            Some(node) => {
+                node.count += count;
+                None
+            }
+            None => Some(Node::new(key, count)),
+        })
+        .0
+    }
+
+    fn remove(&mut self, key: &K) -> usize {
+        remove_rec(&mut self.0, key).0.map_or(0, |node| node.count)
+    }
+
+    // 重複度を 1 減らしたあと、根までの size を計算し直す
+    // 重複度が 1 のノードは木から取り除かなければならないため、ここでは扱わない
+    fn decrement(&mut self, key: &K) {
+        let node = match self.0.as_mut() {
+            Some(node) => node,
+            None => return,
+        };
+        match key.cmp(&node.key) {
+            std::cmp::Ordering::Less => node.left.decrement(key),
+            std::cmp::Ordering::Greater => node.right.decrement(key),
+            std::cmp::Ordering::Equal => node.count -= 1,
+        }
+        node.update();
+    }
+
+    fn count(&self, key: &K) -> usize {
+        let mut node = match self.0.as_ref() {
+            Some(node) => node,
+            None => return 0,
+        };
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => match node.left.0.as_ref() {
+                    Some(node) => node,
+                    None => break 0,
+                },
+                std::cmp::Ordering::Greater => match node.right.0.as_ref() {
+                    Some(node) => node,
+                    None => break 0,
+                },
+                std::cmp::Ordering::Equal => break node.count,
+            };
+        }
+    }
+
+    fn min(&self) -> Option<&K> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.left.0.as_ref() {
+            node = nxt;
+        }
+        Some(&node.key)
+    }
+
+    fn max(&self) -> Option<&K> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.right.0.as_ref() {
+            node = nxt;
+        }
+        Some(&node.key)
+    }
+
+    fn find_by_order(&self, order: usize) -> Option<&K> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
+        let mut node = self.0.as_ref().unwrap();
+        loop {
+            if rest < node.left.size() {
+                node = node.left.0.as_ref().unwrap();
+                continue;
+            }
+            rest -= node.left.size();
+            if rest < node.count {
+                break Some(&node.key);
+            }
+            rest -= node.count;
+            node = node.right.0.as_ref().unwrap();
+        }
+    }
+
+    fn order_of_key(&self, key: &K) -> usize {
+        let mut order = 0;
+        let mut node = match self.0.as_ref() {
+            None => return 0,
+            Some(node) => node,
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    order += node.left.size() + node.count;
+                    node = match node.right.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break order + node.left.size();
+                }
+            }
+        }
+    }
+}
+
+/**
+ * 重複を含めた要素の個数が usize の範囲を超えるため、追加を行わなかったことを表す
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CountOverflow;
+impl std::fmt::Display for CountOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "total count exceeds usize::MAX")
+    }
+}
+impl std::error::Error for CountOverflow {}
+
+/**
+ * 同じキーを複数個持てる AVL 木
+ * 各ノードはキーとその重複度を持ち、順序統計量は重複を含めて数える
+ */
+pub struct AVLTreeMultiSet<K: Ord> {
+    root: NodePtr<K>,
+    // 異なるキーの個数
+    distinct_size: usize,
+}
+impl<K: Ord> AVLTreeMultiSet<K> {
+    pub fn new() -> Self {
+        AVLTreeMultiSet {
+            root: NodePtr(None),
+            distinct_size: 0,
+        }
+    }
+
+    /**
+     * key を 1 個追加する
+     * key がそれまで存在しなかった場合は true を返す
+     */
+    pub fn insert(&mut self, key: K) -> bool {
+        self.insert_many(key, 1)
+    }
+
+    /**
+     * key を count 個追加する
+     * key がそれまで存在しなかった場合は true を返す
+     * 重複を含めた要素の個数が usize に収まらなくなる場合は panic する
+     */
+    pub fn insert_many(&mut self, key: K, count: usize) -> bool {
+        self.try_insert_many(key, count)
+            .expect("total count overflow")
+    }
+
+    /**
+     * insert_many と同じだが、重複を含めた要素の個数が usize に収まらなくなる場合は何も変更せずに Err を返す
+     */
+    pub fn try_insert_many(&mut self, key: K, count: usize) -> Result<bool, CountOverflow> {
+        if count == 0 {
+            return Ok(false);
+        }
+        // 重複度も部分木の個数も全体の個数以下なので、全体だけを調べれば足りる
+        self.size().checked_add(count).ok_or(CountOverflow)?;
+        let inserted = self.root.insert(key, count);
+        if inserted {
+            self.distinct_size += 1;
+        }
+        Ok(inserted)
+    }
+
+    /**
+     * key を 1 個取り除く
+     * key が存在しなかった場合は false を返す
+     */
+    pub fn remove_one(&mut self, key: &K) -> bool {
+        match self.root.count(key) {
+            0 => false,
+            1 => {
+                self.remove_all(key);
+                true
+            }
+            _ => {
+                self.root.decrement(key);
+                true
+            }
+        }
+    }
+
+    /**
+     * key をすべて取り除き、取り除いた個数を返す
+     */
+    pub fn remove_all(&mut self, key: &K) -> usize {
+        let count = self.root.remove(key);
+        if count > 0 {
+            self.distinct_size -= 1;
+        }
+        count
+    }
+
+    /**
+     * key の重複度を返す
+     */
+    pub fn count(&self, key: &K) -> usize {
+        self.root.count(key)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.root.count(key) > 0
+    }
+
+    pub fn min(&self) -> Option<&K> {
+        self.root.min()
+    }
+
+    pub fn max(&self) -> Option<&K> {
+        self.root.max()
+    }
+
+    /**
+     * 重複を含めた要素の個数を返す
+     */
+    pub fn size(&self) -> usize {
+        self.root.size()
+    }
+
+    /**
+     * 異なるキーの個数を返す
+     */
+    pub fn distinct_size(&self) -> usize {
+        self.distinct_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    /**
+     * 重複を含めて昇順に並べたときの order 番目 (0-indexed) の要素を返す
+     */
+    pub fn find_by_order(&self, order: usize) -> Option<&K> {
+        self.root.find_by_order(order)
+    }
+
+    /**
+     * key 未満である要素の個数を重複を含めて返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        self.root.order_of_key(key)
+    }
+
+    /**
+     * 異なるキーを昇順に返す
+     */
+    pub fn keys(&self) -> Vec<&K> {
+        self.root.keys()
+    }
+
+    /**
+     * 異なるキーとその重複度を昇順に返す
+     */
+    pub fn counts(&self) -> Vec<(&K, usize)> {
+        self.root.into_iter().collect()
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     */
+    pub fn stats(&self) -> TreeStats {
+        avl_tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + std::fmt::Debug> AVLTreeMultiSet<K> {
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに重複の個数、部分木の大きさと平衡状態を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
+}
+impl<'a, K: Ord> IntoIterator for &'a AVLTreeMultiSet<K> {
+    type Item = &'a K;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.root
+            .into_iter()
+            .flat_map(|(k, count)| std::iter::repeat_n(k, count))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+impl<K: Ord> Default for AVLTreeMultiSet<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord> FromIterator<K> for AVLTreeMultiSet<K> {
+    fn from_iter<T: IntoIterator<Item = K>>(iter: T) -> Self {
+        let mut tree = Self::new();
+        for key in iter {
+            tree.insert(key);
+        }
+        tree
+    }
+}
@@ -1,8 +1,17 @@
 // verified by https://judge.yosupo.jp/submission/70311
 
+use std::borrow::Borrow;
+use std::io::{self, Read, Write};
 use std::iter::FromIterator;
 
-use crate::node::NodeTrait;
+use crate::diff::{Diff, DiffItem, EntryNode};
+use crate::dot::to_dot;
+use crate::invariant::{validate_avl, InvariantViolation};
+use crate::node::{NodePointer, NodeTrait};
+use crate::pretty::fmt_tree;
+use crate::snapshot::{read_header, write_header, SnapshotError, SnapshotKind, SnapshotValue};
+use crate::stats::{avl_tree_stats, TreeStats};
+use crate::transaction::Transaction;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ThreeWay {
@@ -33,8 +42,232 @@ impl<K, V> Node<K, V> {
             state: ThreeWay::Equal,
         }
     }
+
+    // dir は ThreeWay::Left か ThreeWay::Right
+    fn child(&self, dir: ThreeWay) -> Option<&Self> {
+        match dir {
+            ThreeWay::Left => self.left.0.as_deref(),
+            _ => self.right.0.as_deref(),
+        }
+    }
+
+    fn child_mut(&mut self, dir: ThreeWay) -> &mut NodePtrInner<K, V> {
+        match dir {
+            ThreeWay::Left => &mut self.left.0,
+            _ => &mut self.right.0,
+        }
+    }
+}
+
+// 根から挿入・削除する位置まで進んだときの、各ノードでの向きと元の平衡状態
+#[derive(Clone, Copy)]
+struct Step {
+    dir: ThreeWay,
+    state: ThreeWay,
+    // dir と反対側の子の平衡状態
+    // 削除で回転が起こりうる (state が dir と反対向きの) ときだけ読む
+    sibling: ThreeWay,
+}
+
+// AVL 木の高さは 1.45 log2(n + 2) 未満なので、メモリに載る個数のノードならこれで足りる
+const MAX_HEIGHT: usize = 96;
+
+// 経路を記録するスタック
+// 操作のたびにヒープを確保しないよう固定長の配列に置く
+struct Path {
+    steps: [Step; MAX_HEIGHT],
+    len: usize,
+}
+impl Path {
+    fn new() -> Self {
+        Path {
+            steps: [Step {
+                dir: ThreeWay::Equal,
+                state: ThreeWay::Equal,
+                sibling: ThreeWay::Equal,
+            }; MAX_HEIGHT],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, step: Step) {
+        self.steps[self.len] = step;
+        self.len += 1;
+    }
+}
+impl std::ops::Deref for Path {
+    type Target = [Step];
+
+    fn deref(&self) -> &[Step] {
+        &self.steps[..self.len]
+    }
+}
+
+// slot が指すノードを取り外して返す
+// 子を 2 つ持つ場合は左の子の最大値と中身を入れ替え、そちらを取り外す
+// 取り外したノードの親までの経路を path に追加する
+fn detach_iter<K, V>(slot: &mut NodePtrInner<K, V>, path: &mut Path) -> Box<Node<K, V>> {
+    let node = slot.as_mut().unwrap();
+    if node.left.0.is_none() || node.right.0.is_none() {
+        let mut removed = slot.take().unwrap();
+        *slot = removed.left.0.take().or_else(|| removed.right.0.take());
+        return removed;
+    }
+
+    path.push(Step {
+        dir: ThreeWay::Left,
+        state: node.state,
+        sibling: sibling_state(node, ThreeWay::Left),
+    });
+    let Node {
+        key, value, left, ..
+    } = &mut **node;
+    let mut cur = &mut left.0;
+    while cur.as_ref().unwrap().right.0.is_some() {
+        let node = cur.as_mut().unwrap();
+        path.push(Step {
+            dir: ThreeWay::Right,
+            state: node.state,
+            sibling: sibling_state(node, ThreeWay::Right),
+        });
+        cur = &mut node.right.0;
+    }
+    let mut removed = cur.take().unwrap();
+    *cur = removed.left.0.take();
+    std::mem::swap(key, &mut removed.key);
+    std::mem::swap(value, &mut removed.value);
+    removed
+}
+
+fn sibling_state<K, V>(node: &Node<K, V>, dir: ThreeWay) -> ThreeWay {
+    if node.state == opposite(dir) {
+        node.child(node.state).unwrap().state
+    } else {
+        ThreeWay::Equal
+    }
+}
+
+fn opposite(dir: ThreeWay) -> ThreeWay {
+    match dir {
+        ThreeWay::Left => ThreeWay::Right,
+        ThreeWay::Right => ThreeWay::Left,
+        ThreeWay::Equal => ThreeWay::Equal,
+    }
+}
+// to_dot や fmt_tree で各ノードに表示する内容
+// 最初の行がキーで、残りの行が補足の情報になる
+fn node_label<K: std::fmt::Debug, V>(node: &Node<K, V>) -> Vec<String> {
+    vec![
+        format!("{:?}", node.key),
+        format!("size: {}", node.size),
+        format!("state: {:?}", node.state),
+    ]
+}
+
+// スナップショットでの平衡状態の表現
+fn state_to_byte(state: ThreeWay) -> u8 {
+    match state {
+        ThreeWay::Equal => 0,
+        ThreeWay::Left => 1,
+        ThreeWay::Right => 2,
+    }
+}
+
+fn state_from_byte(byte: u8) -> Option<ThreeWay> {
+    match byte {
+        0 => Some(ThreeWay::Equal),
+        1 => Some(ThreeWay::Left),
+        2 => Some(ThreeWay::Right),
+        _ => None,
+    }
+}
+
+// スナップショットを読み込むときに積んでいる、根から右端までのノードとその深さ
+type SpineEntry<K, V> = (Box<Node<K, V>>, u8);
+type Spine<K, V> = Vec<SpineEntry<K, V>>;
+
+// depth より深いノードを取り出して、下のノードが上のノードの右の子になるようにつなぐ
+// depth が None ならすべて取り出す
+// 取り出した中で最も浅いノードとその深さを返す
+fn pop_deeper<K, V>(
+    spine: &mut Spine<K, V>,
+    depth: Option<u8>,
+    index: u64,
+) -> Result<Option<SpineEntry<K, V>>, SnapshotError> {
+    let mut last: Option<SpineEntry<K, V>> = None;
+    while spine
+        .last()
+        .is_some_and(|&(_, d)| depth.is_none_or(|depth| d > depth))
+    {
+        let (mut node, node_depth) = spine.pop().unwrap();
+        if let Some((right, right_depth)) = last {
+            if u16::from(right_depth) != u16::from(node_depth) + 1 {
+                return Err(SnapshotError::InvalidMetadata { index });
+            }
+            node.right = NodePtr(Some(right));
+        }
+        node.update();
+        last = Some((node, node_depth));
+    }
+    Ok(last)
+}
+
+impl<K: SnapshotValue, V: SnapshotValue> NodePtr<K, V> {
+    // 間順に、深さと平衡状態に続けてキーと値を書く
+    fn write_snapshot_entries<W: Write>(&self, depth: u8, writer: &mut W) -> io::Result<()> {
+        if let Some(node) = &self.0 {
+            node.left.write_snapshot_entries(depth + 1, writer)?;
+            depth.write_to(writer)?;
+            state_to_byte(node.state).write_to(writer)?;
+            node.key.write_to(writer)?;
+            node.value.write_to(writer)?;
+            node.right.write_snapshot_entries(depth + 1, writer)?;
+        }
+        Ok(())
+    }
+
+    // write_snapshot_entries で書いた count 個の要素から同じ形の木を組み立てる
+    // 間順に並んだ要素とその深さから、木の形は一意に決まる
+    fn read_snapshot_entries<R: Read>(reader: &mut R, count: u64) -> Result<Self, SnapshotError> {
+        let mut spine: Spine<K, V> = Vec::new();
+        for index in 0..count {
+            let depth = u8::read_from(reader)?;
+            let state = state_from_byte(u8::read_from(reader)?)
+                .ok_or(SnapshotError::InvalidMetadata { index })?;
+            let mut node = Box::new(Node::new(K::read_from(reader)?, V::read_from(reader)?));
+            node.state = state;
+
+            if let Some((left, left_depth)) = pop_deeper(&mut spine, Some(depth), index)? {
+                if u16::from(left_depth) != u16::from(depth) + 1 {
+                    return Err(SnapshotError::InvalidMetadata { index });
+                }
+                node.left = NodePtr(Some(left));
+            }
+            // 同じ深さのノードが右端に並ぶことはない
+            if spine.last().is_some_and(|&(_, d)| d == depth) {
+                return Err(SnapshotError::InvalidMetadata { index });
+            }
+            spine.push((node, depth));
+        }
+        match pop_deeper(&mut spine, None, count)? {
+            None => Ok(NodePtr(None)),
+            Some((root, 0)) => Ok(NodePtr(Some(root))),
+            Some(_) => Err(SnapshotError::InvalidMetadata { index: count }),
+        }
+    }
+}
+
+impl<K: Ord, V> EntryNode for Node<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn entry(&self) -> (&K, &V) {
+        (&self.key, &self.value)
+    }
 }
 impl<K, V> NodeTrait for Node<K, V> {
+    type Ptr = Box<Self>;
+
     fn size(&self) -> usize {
         self.size
     }
@@ -51,6 +284,14 @@ impl<K, V> NodeTrait for Node<K, V> {
         self.right.0.as_deref()
     }
 
+    fn left_mut(&mut self) -> Option<&mut Self> {
+        self.left.0.as_deref_mut()
+    }
+
+    fn right_mut(&mut self) -> Option<&mut Self> {
+        self.right.0.as_deref_mut()
+    }
+
     fn take_left(&mut self) -> Option<Box<Self>> {
         self.left.0.take()
     }
@@ -67,6 +308,21 @@ impl<K, V> NodeTrait for Node<K, V> {
         self.right.0 = node;
     }
 }
+impl<K: Ord, V> AVLNode for Node<K, V> {
+    type Key = K;
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn state(&self) -> ThreeWay {
+        self.state
+    }
+
+    fn state_mut(&mut self) -> &mut ThreeWay {
+        &mut self.state
+    }
+}
 
 impl<'a, K, V> IntoIterator for &'a NodePtr<K, V> {
     type Item = (&'a K, &'a V);
@@ -75,9 +331,9 @@ impl<'a, K, V> IntoIterator for &'a NodePtr<K, V> {
     fn into_iter(self) -> Self::IntoIter {
         let mut vec = Vec::new();
         if let Some(node) = &self.0 {
-            vec.extend(node.left.into_iter());
+            vec.extend(&node.left);
             vec.push((&node.key, &node.value));
-            vec.extend(node.right.into_iter());
+            vec.extend(&node.right);
         }
         vec.into_iter()
     }
@@ -88,8 +344,63 @@ impl<K, V> From<NodePtrInner<K, V>> for NodePtr<K, V> {
     }
 }
 impl<K, V> NodePtr<K, V> {
-    fn new(key: K, value: V) -> Self {
-        NodePtr(Some(Box::new(Node::new(key, value))))
+    // 昇順に並んだ n 個の要素から高さが最小の木を O(n) で作り、その高さとともに返す
+    // 左の部分木に半分を切り上げて置くので、左が高いか左右の高さが等しいかのどちらかになる
+    fn from_sorted_rec(iter: &mut impl Iterator<Item = (K, V)>, n: usize) -> (Self, usize) {
+        if n == 0 {
+            return (NodePtr(None), 0);
+        }
+        let (left, left_height) = Self::from_sorted_rec(iter, n / 2);
+        let (key, value) = iter.next().unwrap();
+        let (right, right_height) = Self::from_sorted_rec(iter, n - n / 2 - 1);
+        let state = if left_height > right_height {
+            ThreeWay::Left
+        } else {
+            ThreeWay::Equal
+        };
+        let node = Node {
+            key,
+            value,
+            left,
+            right,
+            size: n,
+            state,
+        };
+        (NodePtr(Some(Box::new(node))), left_height + 1)
+    }
+
+    // 昇順に f を呼び、その結果を keep に積む
+    fn retain_decide<F: FnMut(&K, &mut V) -> bool>(&mut self, f: &mut F, keep: &mut Vec<bool>) {
+        if let Some(node) = self.0.as_deref_mut() {
+            node.left.retain_decide(f, keep);
+            keep.push(f(&node.key, &mut node.value));
+            node.right.retain_decide(f, keep);
+        }
+    }
+
+    // 木を崩しながら昇順に要素を取り出し、keep に従って kept と removed に振り分ける
+    fn partition_into(
+        self,
+        keep: &mut impl Iterator<Item = bool>,
+        kept: &mut Vec<(K, V)>,
+        removed: &mut Vec<(K, V)>,
+    ) {
+        if let Some(node) = self.0 {
+            let Node {
+                key,
+                value,
+                left,
+                right,
+                ..
+            } = *node;
+            left.partition_into(keep, kept, removed);
+            if keep.next().unwrap() {
+                kept.push((key, value));
+            } else {
+                removed.push((key, value));
+            }
+            right.partition_into(keep, kept, removed);
+        }
     }
 
     fn size(&self) -> usize {
@@ -121,485 +432,997 @@ impl<K, V> NodePtr<K, V> {
     }
 }
 
-impl<K: Ord, V> NodePtr<K, V> {
-    fn insert_rec(&mut self, key: K, value: V) -> (bool, bool) {
-        let mut node = match self.0.take() {
-            Some(node) => node,
-            None => {
-                *self = NodePtr::new(key, value);
-                return (true, true);
-            }
+/**
+ * AVL 木の平衡操作に必要なノードの性質
+ * insert_rec / remove_rec などはこれを実装したノードであれば共通で使える
+ */
+pub(crate) trait AVLNode: NodeTrait {
+    type Key: Ord;
+    fn key(&self) -> &Self::Key;
+    fn state(&self) -> ThreeWay;
+    fn state_mut(&mut self) -> &mut ThreeWay;
+}
+
+// 左の子の右の子を根に持ってくる二重回転
+fn double_rotate_right<N: AVLNode>(node: &mut N) {
+    let state = node.left().unwrap().right().unwrap().state();
+    node.left_mut().unwrap().rotate_left();
+    node.rotate_right();
+    *node.state_mut() = ThreeWay::Equal;
+    if let Some(left) = node.left_mut() {
+        *left.state_mut() = match state {
+            ThreeWay::Left | ThreeWay::Equal => ThreeWay::Equal,
+            ThreeWay::Right => ThreeWay::Left,
+        };
+    }
+    if let Some(right) = node.right_mut() {
+        *right.state_mut() = match state {
+            ThreeWay::Left => ThreeWay::Right,
+            ThreeWay::Right | ThreeWay::Equal => ThreeWay::Equal,
         };
+    }
+}
 
-        match key.cmp(&node.key) {
-            std::cmp::Ordering::Less => {
-                let (left_inserted, left_increased) = node.left.insert_rec(key, value);
-                if !left_inserted {
-                    *self = Some(node).into();
-                    return (false, false);
-                }
-                node.size += 1;
-                if !left_increased {
-                    *self = Some(node).into();
-                    return (true, false);
-                }
+// 右の子の左の子を根に持ってくる二重回転
+fn double_rotate_left<N: AVLNode>(node: &mut N) {
+    let state = node.right().unwrap().left().unwrap().state();
+    node.right_mut().unwrap().rotate_right();
+    node.rotate_left();
+    *node.state_mut() = ThreeWay::Equal;
+    if let Some(left) = node.left_mut() {
+        *left.state_mut() = match state {
+            ThreeWay::Left | ThreeWay::Equal => ThreeWay::Equal,
+            ThreeWay::Right => ThreeWay::Left,
+        };
+    }
+    if let Some(right) = node.right_mut() {
+        *right.state_mut() = match state {
+            ThreeWay::Left => ThreeWay::Right,
+            ThreeWay::Right | ThreeWay::Equal => ThreeWay::Equal,
+        };
+    }
+}
 
-                match node.state {
-                    ThreeWay::Equal => {
-                        node.state = ThreeWay::Left;
-                        *self = Some(node).into();
-                        (true, true)
-                    }
-                    ThreeWay::Left => {
-                        match node.left.0.as_ref().unwrap().state {
-                            ThreeWay::Left => {
-                                node.rotate_right();
-                                node.state = ThreeWay::Equal;
-                                if let Some(mut right) = node.right.0.as_mut() {
-                                    right.state = ThreeWay::Equal;
-                                }
-                                *self = Some(node).into();
-                            }
-                            ThreeWay::Right => {
-                                let state = node
-                                    .left
-                                    .0
-                                    .as_ref()
-                                    .unwrap()
-                                    .right
-                                    .0
-                                    .as_ref()
-                                    .unwrap()
-                                    .state;
-                                node.left.0.as_mut().unwrap().rotate_left();
-                                node.rotate_right();
-                                node.state = ThreeWay::Equal;
-                                if let Some(mut right) = node.right.0.as_mut() {
-                                    right.state = match state {
-                                        ThreeWay::Left => ThreeWay::Right,
-                                        ThreeWay::Right | ThreeWay::Equal => ThreeWay::Equal,
-                                    };
-                                }
-                                if let Some(mut left) = node.left.0.as_mut() {
-                                    left.state = match state {
-                                        ThreeWay::Left | ThreeWay::Equal => ThreeWay::Equal,
-                                        ThreeWay::Right => ThreeWay::Left,
-                                    };
-                                }
-                                *self = Some(node).into();
-                            }
-                            ThreeWay::Equal => {
-                                unreachable!()
-                            }
-                        }
-                        (true, false)
-                    }
-                    ThreeWay::Right => {
-                        node.state = ThreeWay::Equal;
-                        *self = Some(node).into();
-                        (true, false)
+// 挿入によって左の子の高さが 1 増えたときに呼ぶ
+// 返り値は木の高さが増えたかどうか
+pub(crate) fn grown_left<N: AVLNode>(node: &mut N) -> bool {
+    match node.state() {
+        ThreeWay::Equal => {
+            *node.state_mut() = ThreeWay::Left;
+            true
+        }
+        ThreeWay::Right => {
+            *node.state_mut() = ThreeWay::Equal;
+            false
+        }
+        ThreeWay::Left => {
+            match node.left().unwrap().state() {
+                ThreeWay::Left => {
+                    node.rotate_right();
+                    *node.state_mut() = ThreeWay::Equal;
+                    if let Some(right) = node.right_mut() {
+                        *right.state_mut() = ThreeWay::Equal;
                     }
                 }
+                ThreeWay::Right => double_rotate_right(node),
+                ThreeWay::Equal => unreachable!(),
             }
-            std::cmp::Ordering::Equal => {
-                node.value = value;
-                *self = Some(node).into();
-                (false, false)
-            }
-            std::cmp::Ordering::Greater => {
-                let (right_inserted, right_increased) = node.right.insert_rec(key, value);
-                if !right_inserted {
-                    *self = Some(node).into();
-                    return (false, false);
-                }
-                node.size += 1;
-                if !right_increased {
-                    *self = Some(node).into();
-                    return (true, false);
-                }
+            false
+        }
+    }
+}
 
-                match node.state {
-                    ThreeWay::Equal => {
-                        node.state = ThreeWay::Right;
-                        *self = Some(node).into();
-                        (true, true)
-                    }
-                    ThreeWay::Left => {
-                        node.state = ThreeWay::Equal;
-                        *self = Some(node).into();
-                        (true, false)
-                    }
-                    ThreeWay::Right => {
-                        match node.right.0.as_ref().unwrap().state {
-                            ThreeWay::Right => {
-                                node.rotate_left();
-                                node.state = ThreeWay::Equal;
-                                if let Some(mut left) = node.left.0.as_mut() {
-                                    left.state = ThreeWay::Equal;
-                                }
-                                *self = Some(node).into();
-                            }
-                            ThreeWay::Left => {
-                                let state = node
-                                    .right
-                                    .0
-                                    .as_ref()
-                                    .unwrap()
-                                    .left
-                                    .0
-                                    .as_ref()
-                                    .unwrap()
-                                    .state;
-                                node.right.0.as_mut().unwrap().rotate_right();
-                                node.rotate_left();
-                                node.state = ThreeWay::Equal;
-                                if let Some(mut left) = node.left.0.as_mut() {
-                                    left.state = match state {
-                                        ThreeWay::Left | ThreeWay::Equal => ThreeWay::Equal,
-                                        ThreeWay::Right => ThreeWay::Left,
-                                    };
-                                }
-                                if let Some(mut right) = node.right.0.as_mut() {
-                                    right.state = match state {
-                                        ThreeWay::Left => ThreeWay::Right,
-                                        ThreeWay::Right | ThreeWay::Equal => ThreeWay::Equal,
-                                    };
-                                }
-                                *self = Some(node).into();
-                            }
-                            ThreeWay::Equal => {
-                                unreachable!()
-                            }
-                        }
-                        (true, false)
+// 挿入によって右の子の高さが 1 増えたときに呼ぶ
+// 返り値は木の高さが増えたかどうか
+pub(crate) fn grown_right<N: AVLNode>(node: &mut N) -> bool {
+    match node.state() {
+        ThreeWay::Equal => {
+            *node.state_mut() = ThreeWay::Right;
+            true
+        }
+        ThreeWay::Left => {
+            *node.state_mut() = ThreeWay::Equal;
+            false
+        }
+        ThreeWay::Right => {
+            match node.right().unwrap().state() {
+                ThreeWay::Right => {
+                    node.rotate_left();
+                    *node.state_mut() = ThreeWay::Equal;
+                    if let Some(left) = node.left_mut() {
+                        *left.state_mut() = ThreeWay::Equal;
                     }
                 }
+                ThreeWay::Left => double_rotate_left(node),
+                ThreeWay::Equal => unreachable!(),
             }
+            false
         }
     }
+}
 
-    fn remove_rec(&mut self, key: &K) -> (Option<(K, V)>, bool) {
-        let mut node = match self.0.take() {
-            Some(node) => node,
-            None => return (None, false),
-        };
+// 削除によって左の子の高さが 1 減ったときに呼ぶ
+// 返り値は木の高さが減ったかどうか
+pub(crate) fn shrunk_left<N: AVLNode>(node: &mut N) -> bool {
+    match node.state() {
+        ThreeWay::Equal => {
+            *node.state_mut() = ThreeWay::Right;
+            false
+        }
+        ThreeWay::Left => {
+            *node.state_mut() = ThreeWay::Equal;
+            true
+        }
+        ThreeWay::Right => rebalanced_for_left_remove(node),
+    }
+}
 
-        match key.cmp(&node.key) {
-            std::cmp::Ordering::Equal => match (node.left.0.take(), node.right.0.take()) {
-                (None, None) => (Some((node.key, node.value)), true),
-                (Some(left), None) => {
-                    *self = Some(left).into();
-                    (Some((node.key, node.value)), true)
-                }
-                (None, Some(right)) => {
-                    *self = Some(right).into();
-                    (Some((node.key, node.value)), true)
-                }
-                (Some(left), Some(right)) => {
-                    // 削除したいノードが子を2つ持っている場合
-                    // 左の子の最大値を消して 削除したいノードをそれに置き換える
-                    let mut wrapped_left: NodePtr<_, _> = Some(left).into();
-                    let (value, decreased) = wrapped_left.remove_max_rec();
-                    let (mut key, mut value) = value.unwrap();
-                    std::mem::swap(&mut node.key, &mut key);
-                    std::mem::swap(&mut node.value, &mut value);
-                    node.size -= 1;
-                    node.left = wrapped_left;
-                    node.right = Some(right).into();
-                    match node.state {
-                        _ if !decreased => {
-                            *self = Some(node).into();
-                            (Some((key, value)), false)
-                        }
-                        ThreeWay::Equal => {
-                            node.state = ThreeWay::Right;
-                            *self = Some(node).into();
-                            (Some((key, value)), false)
-                        }
-                        ThreeWay::Left => {
-                            node.state = ThreeWay::Equal;
-                            *self = Some(node).into();
-                            (Some((key, value)), true)
-                        }
-                        ThreeWay::Right => {
-                            *self = Some(node).into();
-                            (Some((key, value)), self.rebalanced_for_left_remove())
-                        }
-                    }
-                }
-            },
-            std::cmp::Ordering::Greater => {
-                let (value, decreased) = node.right.remove_rec(key);
-                if value.is_none() {
-                    *self = Some(node).into();
-                    return (None, false);
-                }
-                node.size -= 1;
-                if !decreased {
-                    *self = Some(node).into();
-                    return (value, false);
-                }
-                match node.state {
-                    ThreeWay::Equal => {
-                        node.state = ThreeWay::Left;
-                        *self = Some(node).into();
-                        (value, false)
-                    }
-                    ThreeWay::Right => {
-                        node.state = ThreeWay::Equal;
-                        *self = Some(node).into();
-                        (value, true)
-                    }
-                    ThreeWay::Left => {
-                        *self = Some(node).into();
-                        (value, self.rebalanced_for_right_remove())
-                    }
-                }
-            }
-            std::cmp::Ordering::Less => {
-                let (value, decreased) = node.left.remove_rec(key);
-                if value.is_none() {
-                    *self = Some(node).into();
-                    return (None, false);
-                }
-                node.size -= 1;
-                if !decreased {
-                    *self = Some(node).into();
-                    return (value, false);
-                }
-                match node.state {
-                    ThreeWay::Equal => {
-                        node.state = ThreeWay::Right;
-                        *self = Some(node).into();
-                        (value, false)
-                    }
-                    ThreeWay::Left => {
-                        node.state = ThreeWay::Equal;
-                        *self = Some(node).into();
-                        (value, true)
-                    }
-                    ThreeWay::Right => {
-                        *self = Some(node).into();
-                        (value, self.rebalanced_for_left_remove())
-                    }
-                }
-            }
+// 削除によって右の子の高さが 1 減ったときに呼ぶ
+// 返り値は木の高さが減ったかどうか
+pub(crate) fn shrunk_right<N: AVLNode>(node: &mut N) -> bool {
+    match node.state() {
+        ThreeWay::Equal => {
+            *node.state_mut() = ThreeWay::Left;
+            false
         }
+        ThreeWay::Right => {
+            *node.state_mut() = ThreeWay::Equal;
+            true
+        }
+        ThreeWay::Left => rebalanced_for_right_remove(node),
     }
+}
 
-    fn remove_max_rec(&mut self) -> (Option<(K, V)>, bool) {
-        let mut node = match self.0.take() {
-            Some(node) => node,
-            None => return (None, false),
-        };
+// 左の子の削除操作によって、左の子の高さが右の子の高さより 2 低くなったときに呼ぶ
+// 返り値は調整によって木の高さが低くなったかどうか
+fn rebalanced_for_left_remove<N: AVLNode>(node: &mut N) -> bool {
+    match node.right().unwrap().state() {
+        ThreeWay::Equal => {
+            node.rotate_left();
+            *node.state_mut() = ThreeWay::Left;
+            false
+        }
+        ThreeWay::Right => {
+            node.rotate_left();
+            *node.state_mut() = ThreeWay::Equal;
+            if let Some(left) = node.left_mut() {
+                *left.state_mut() = ThreeWay::Equal;
+            }
+            true
+        }
+        ThreeWay::Left => {
+            double_rotate_left(node);
+            true
+        }
+    }
+}
 
-        match node.right.0.take() {
-            None => {
-                let left = node.left.0.take();
-                *self = left.into();
-                (Some((node.key, node.value)), true)
+// 右の子の削除操作によって、右の子の高さが左の子の高さより 2 低くなったときに呼ぶ
+// 返り値は調整によって木の高さが低くなったかどうか
+fn rebalanced_for_right_remove<N: AVLNode>(node: &mut N) -> bool {
+    match node.left().unwrap().state() {
+        ThreeWay::Equal => {
+            node.rotate_right();
+            *node.state_mut() = ThreeWay::Right;
+            false
+        }
+        ThreeWay::Left => {
+            node.rotate_right();
+            *node.state_mut() = ThreeWay::Equal;
+            if let Some(right) = node.right_mut() {
+                *right.state_mut() = ThreeWay::Equal;
             }
-            Some(right) => {
-                let mut wrapped_right: NodePtr<_, _> = Some(right).into();
-                let (ret, decreased) = wrapped_right.remove_max_rec();
-                node.right = wrapped_right;
-                node.size -= 1;
-                if !decreased {
-                    *self = Some(node).into();
-                    return (ret, false);
-                }
+            true
+        }
+        ThreeWay::Right => {
+            double_rotate_right(node);
+            true
+        }
+    }
+}
 
-                match node.state {
-                    ThreeWay::Equal => {
-                        node.state = ThreeWay::Left;
-                        *self = Some(node).into();
-                        (ret, false)
-                    }
-                    ThreeWay::Right => {
-                        node.state = ThreeWay::Equal;
-                        *self = Some(node).into();
-                        (ret, true)
-                    }
-                    ThreeWay::Left => {
-                        *self = Some(node).into();
-                        (ret, self.rebalanced_for_right_remove())
-                    }
+/**
+ * key の位置を探し、見つかった場合はそのノードを、見つからなかった場合は None を渡して f を呼ぶ
+ * 見つからなかったときに f が返したノードが挿入される
+ * 返り値は (挿入されたかどうか, 木の高さが増えたかどうか)
+ */
+pub(crate) fn insert_rec<P, N, F>(ptr: &mut Option<P>, key: N::Key, f: F) -> (bool, bool)
+where
+    P: NodePointer<N>,
+    N: AVLNode<Ptr = P>,
+    F: FnOnce(N::Key, Option<&mut N>) -> Option<N>,
+{
+    let node = match ptr {
+        Some(node) => P::make_mut(node),
+        None => {
+            return match f(key, None) {
+                Some(node) => {
+                    *ptr = Some(P::new(node));
+                    (true, true)
                 }
+                None => (false, false),
+            };
+        }
+    };
+    node.push();
+
+    match key.cmp(node.key()) {
+        std::cmp::Ordering::Less => {
+            let mut left = node.take_left();
+            let (inserted, increased) = insert_rec(&mut left, key, f);
+            node.set_left(left);
+            node.update();
+            if !increased {
+                return (inserted, false);
             }
+            (true, grown_left(node))
+        }
+        std::cmp::Ordering::Equal => {
+            f(key, Some(node));
+            node.update();
+            (false, false)
+        }
+        std::cmp::Ordering::Greater => {
+            let mut right = node.take_right();
+            let (inserted, increased) = insert_rec(&mut right, key, f);
+            node.set_right(right);
+            node.update();
+            if !increased {
+                return (inserted, false);
+            }
+            (true, grown_right(node))
+        }
+    }
+}
+
+/**
+ * ptr が指すノードを木から取り外して返す
+ * 返り値は (取り外したノード, 木の高さが減ったかどうか)
+ */
+fn detach<P: NodePointer<N>, N: AVLNode<Ptr = P>>(ptr: &mut Option<P>) -> (Option<P>, bool) {
+    let mut removed = match ptr.take() {
+        Some(node) => node,
+        None => return (None, false),
+    };
+    let removed_mut = P::make_mut(&mut removed);
+    match (removed_mut.take_left(), removed_mut.take_right()) {
+        (None, None) => (Some(removed), true),
+        (Some(child), None) | (None, Some(child)) => {
+            *ptr = Some(child);
+            (Some(removed), true)
+        }
+        (Some(left), Some(right)) => {
+            // 削除したいノードが子を2つ持っている場合
+            // 左の子の最大値を取り外して 削除したいノードの位置に置く
+            let mut left = Some(left);
+            let (max, decreased) = remove_max_rec(&mut left);
+            let mut max = max.unwrap();
+            let max_mut = P::make_mut(&mut max);
+            max_mut.set_left(left);
+            max_mut.set_right(Some(right));
+            *max_mut.state_mut() = removed.state();
+            max_mut.update();
+            let decreased = decreased && shrunk_left(max_mut);
+            *ptr = Some(max);
+            (Some(removed), decreased)
         }
     }
+}
 
-    // 左の子の削除操作によって、左の子の高さが右の子の高さより 2 低くなったときに呼ぶ
-    // 返り値は調整によって木の高さが低くなったかどうか
-    fn rebalanced_for_left_remove(&mut self) -> bool {
-        let mut node = self.0.take().unwrap();
-        let right_state = node.right.0.as_ref().unwrap().state;
-        match right_state {
-            ThreeWay::Equal => {
-                node.rotate_left();
-                node.state = ThreeWay::Left;
-                *self = Some(node).into();
-                false
+/**
+ * key を持つノードを木から取り外して返す
+ * 返り値は (取り外したノード, 木の高さが減ったかどうか)
+ */
+pub(crate) fn remove_rec<P: NodePointer<N>, N: AVLNode<Ptr = P>>(
+    ptr: &mut Option<P>,
+    key: &N::Key,
+) -> (Option<P>, bool) {
+    let node = match ptr {
+        Some(node) => P::make_mut(node),
+        None => return (None, false),
+    };
+    node.push();
+
+    match key.cmp(node.key()) {
+        std::cmp::Ordering::Equal => detach(ptr),
+        std::cmp::Ordering::Greater => {
+            let mut right = node.take_right();
+            let (removed, decreased) = remove_rec(&mut right, key);
+            node.set_right(right);
+            if removed.is_none() {
+                return (None, false);
             }
-            ThreeWay::Right => {
-                node.rotate_left();
-                node.state = ThreeWay::Equal;
-                if let Some(mut left) = node.left.0.as_mut() {
-                    left.state = ThreeWay::Equal;
-                }
-                *self = Some(node).into();
-                true
+            node.update();
+            if !decreased {
+                return (removed, false);
             }
-            ThreeWay::Left => {
-                let state = node
-                    .right
-                    .0
-                    .as_ref()
-                    .unwrap()
-                    .left
-                    .0
-                    .as_ref()
-                    .unwrap()
-                    .state;
-                node.right.0.as_mut().unwrap().rotate_right();
-                node.rotate_left();
-                node.state = ThreeWay::Equal;
-                if let Some(mut left) = node.left.0.as_mut() {
-                    left.state = match state {
-                        ThreeWay::Left | ThreeWay::Equal => ThreeWay::Equal,
-                        ThreeWay::Right => ThreeWay::Left,
-                    };
-                }
-                if let Some(mut right) = node.right.0.as_mut() {
-                    right.state = match state {
-                        ThreeWay::Left => ThreeWay::Right,
-                        ThreeWay::Right | ThreeWay::Equal => ThreeWay::Equal,
-                    };
-                }
-                *self = Some(node).into();
-                true
+            (removed, shrunk_right(node))
+        }
+        std::cmp::Ordering::Less => {
+            let mut left = node.take_left();
+            let (removed, decreased) = remove_rec(&mut left, key);
+            node.set_left(left);
+            if removed.is_none() {
+                return (None, false);
             }
+            node.update();
+            if !decreased {
+                return (removed, false);
+            }
+            (removed, shrunk_left(node))
         }
     }
+}
 
-    // 右の子の削除操作によって、右の子の高さが左の子の高さより 2 低くなったときに呼ぶ
-    // 返り値は調整によって木の高さが低くなったかどうか
-    fn rebalanced_for_right_remove(&mut self) -> bool {
-        let mut node = self.0.take().unwrap();
-        let left_state = node.left.0.as_ref().unwrap().state;
-        match left_state {
-            ThreeWay::Equal => {
-                node.rotate_right();
-                node.state = ThreeWay::Right;
-                *self = Some(node).into();
-                false
+/**
+ * 昇順で order 番目 (0-indexed) のノードを木から取り外して返す
+ * 返り値は (取り外したノード, 木の高さが減ったかどうか)
+ */
+pub(crate) fn remove_by_order_rec<P: NodePointer<N>, N: AVLNode<Ptr = P>>(
+    ptr: &mut Option<P>,
+    order: usize,
+) -> (Option<P>, bool) {
+    let node = match ptr {
+        Some(node) => P::make_mut(node),
+        None => return (None, false),
+    };
+    node.push();
+
+    let left_size = node.left().map_or(0, |n| n.size());
+    match order.cmp(&left_size) {
+        std::cmp::Ordering::Equal => detach(ptr),
+        std::cmp::Ordering::Greater => {
+            let mut right = node.take_right();
+            let (removed, decreased) = remove_by_order_rec(&mut right, order - left_size - 1);
+            node.set_right(right);
+            if removed.is_none() {
+                return (None, false);
             }
-            ThreeWay::Left => {
-                node.rotate_right();
-                node.state = ThreeWay::Equal;
-                if let Some(mut right) = node.right.0.as_mut() {
-                    right.state = ThreeWay::Equal;
-                }
-                *self = Some(node).into();
-                true
+            node.update();
+            if !decreased {
+                return (removed, false);
             }
-            ThreeWay::Right => {
-                let state = node
-                    .left
-                    .0
-                    .as_ref()
-                    .unwrap()
-                    .right
-                    .0
-                    .as_ref()
-                    .unwrap()
-                    .state;
-                node.left.0.as_mut().unwrap().rotate_left();
-                node.rotate_right();
-                node.state = ThreeWay::Equal;
-                if let Some(mut left) = node.left.0.as_mut() {
-                    left.state = match state {
-                        ThreeWay::Left | ThreeWay::Equal => ThreeWay::Equal,
-                        ThreeWay::Right => ThreeWay::Left,
-                    };
-                }
-                if let Some(mut right) = node.right.0.as_mut() {
-                    right.state = match state {
-                        ThreeWay::Left => ThreeWay::Right,
-                        ThreeWay::Right | ThreeWay::Equal => ThreeWay::Equal,
-                    };
-                }
-                *self = Some(node).into();
-                true
+            (removed, shrunk_right(node))
+        }
+        std::cmp::Ordering::Less => {
+            let mut left = node.take_left();
+            let (removed, decreased) = remove_by_order_rec(&mut left, order);
+            node.set_left(left);
+            if removed.is_none() {
+                return (None, false);
+            }
+            node.update();
+            if !decreased {
+                return (removed, false);
             }
+            (removed, shrunk_left(node))
         }
     }
 }
 
-impl<K, V> NodePtr<K, V>
-where
-    K: Ord,
-{
-    fn insert(&mut self, key: K, value: V) -> bool {
-        self.insert_rec(key, value).0
+/**
+ * 最大のキーを持つノードを木から取り外して返す
+ * 返り値は (取り外したノード, 木の高さが減ったかどうか)
+ */
+pub(crate) fn remove_max_rec<P: NodePointer<N>, N: AVLNode<Ptr = P>>(
+    ptr: &mut Option<P>,
+) -> (Option<P>, bool) {
+    let node = match ptr {
+        Some(node) => P::make_mut(node),
+        None => return (None, false),
+    };
+    node.push();
+
+    if node.right().is_none() {
+        let mut removed = ptr.take().unwrap();
+        *ptr = P::make_mut(&mut removed).take_left();
+        return (Some(removed), true);
     }
 
-    fn remove(&mut self, key: &K) -> Option<V> {
-        self.remove_rec(key).0.map(|node| node.1)
+    let mut right = node.take_right();
+    let (removed, decreased) = remove_max_rec(&mut right);
+    node.set_right(right);
+    node.update();
+    if !decreased {
+        return (removed, false);
     }
+    (removed, shrunk_right(node))
+}
 
-    fn search(&self, key: &K) -> Option<&V> {
-        match self.0.as_ref() {
-            None => None,
-            Some(node) => match key.cmp(&node.key) {
-                std::cmp::Ordering::Less => node.left.search(key),
-                std::cmp::Ordering::Greater => node.right.search(key),
-                std::cmp::Ordering::Equal => Some(&node.value),
-            },
-        }
+// 木とその高さの組
+type Measured<K, V> = (NodePtrInner<K, V>, usize);
+// split の結果 (key 未満の木, key のノード, key より大きい木)
+type Split<K, V> = (Measured<K, V>, Option<Box<Node<K, V>>>, Measured<K, V>);
+
+// 平衡状態をたどって高い方の子へ降りることで、木の高さを O(log n) で求める
+fn height<K, V>(mut node: Option<&Node<K, V>>) -> usize {
+    let mut height = 0;
+    while let Some(cur) = node {
+        height += 1;
+        node = match cur.state {
+            ThreeWay::Left => cur.left.0.as_deref(),
+            _ => cur.right.0.as_deref(),
+        };
     }
+    height
+}
 
-    fn search_mut(&mut self, key: &K) -> Option<&mut V> {
-        match self.0.as_mut() {
-            None => None,
-            Some(node) => match key.cmp(&node.key) {
-                std::cmp::Ordering::Less => node.left.search_mut(key),
-                std::cmp::Ordering::Greater => node.right.search_mut(key),
-                std::cmp::Ordering::Equal => Some(&mut node.value),
-            },
-        }
-    }
+fn measured<K, V>(tree: NodePtr<K, V>) -> Measured<K, V> {
+    let height = height(tree.0.as_deref());
+    (tree.0, height)
+}
 
-    fn min(&self) -> Option<(&K, &V)> {
-        match self.0.as_ref() {
-            Some(mut node) => {
-                while let Some(nxt) = node.left.0.as_ref() {
-                    node = nxt;
-                }
-                Some((&node.key, &node.value))
-            }
-            None => None,
-        }
+// 高さ height のノードの左右の子の高さ
+fn child_heights<K, V>(node: &Node<K, V>, height: usize) -> (usize, usize) {
+    match node.state {
+        ThreeWay::Left => (height - 1, height - 2),
+        ThreeWay::Equal => (height - 1, height - 1),
+        ThreeWay::Right => (height - 2, height - 1),
     }
+}
 
-    fn max(&self) -> Option<(&K, &V)> {
-        match self.0.as_ref() {
-            Some(mut node) => {
-                while let Some(nxt) = node.right.0.as_ref() {
-                    node = nxt;
-                }
-                Some((&node.key, &node.value))
-            }
-            None => None,
-        }
+// 高さの差が 1 以下の 2 つの木を mid の子にする
+fn attach<K, V>(
+    mut mid: Box<Node<K, V>>,
+    left: Measured<K, V>,
+    right: Measured<K, V>,
+) -> Measured<K, V> {
+    mid.state = match left.1.cmp(&right.1) {
+        std::cmp::Ordering::Less => ThreeWay::Right,
+        std::cmp::Ordering::Equal => ThreeWay::Equal,
+        std::cmp::Ordering::Greater => ThreeWay::Left,
+    };
+    mid.left = NodePtr(left.0);
+    mid.right = NodePtr(right.0);
+    mid.update();
+    let height = left.1.max(right.1) + 1;
+    (Some(mid), height)
+}
+
+/**
+ * left のすべてのキー < mid のキー < right のすべてのキー のときに、3 つを連結する
+ * 高い方の木の縁を、低い方の木と高さの差が 1 以下になる深さまで降りてそこに連結し、
+ * 戻りながら平衡を直すので、O(|left の高さ - right の高さ| + 1) で済む
+ */
+fn join<K: Ord, V>(
+    left: Measured<K, V>,
+    mid: Box<Node<K, V>>,
+    right: Measured<K, V>,
+) -> Measured<K, V> {
+    if left.1 > right.1 + 1 {
+        join_right(left, mid, right)
+    } else if right.1 > left.1 + 1 {
+        join_left(left, mid, right)
+    } else {
+        attach(mid, left, right)
     }
+}
 
-    fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
-        if self.size() <= order {
-            return None;
+// left の方が 2 以上高いとき、left の右の縁に連結する
+fn join_right<K: Ord, V>(
+    left: Measured<K, V>,
+    mid: Box<Node<K, V>>,
+    right: Measured<K, V>,
+) -> Measured<K, V> {
+    let mut node = left.0.unwrap();
+    let (left_height, right_height) = child_heights(&node, left.1);
+    let (joined, joined_height) = join((node.right.0.take(), right_height), mid, right);
+    node.right = NodePtr(joined);
+    if joined_height <= left_height + 1 {
+        return attach_existing(node, left_height, joined_height);
+    }
+    // 右の子が左の子より 2 高くなったので回転する
+    let height = match node.right.0.as_ref().unwrap().state {
+        ThreeWay::Left => {
+            double_rotate_left(node.as_mut());
+            left_height + 2
         }
+        ThreeWay::Right => {
+            node.rotate_left();
+            node.state = ThreeWay::Equal;
+            node.left.0.as_mut().unwrap().state = ThreeWay::Equal;
+            left_height + 2
+        }
+        // 挿入と異なり、連結した部分木は左右の高さが等しいまま高くなることがある
+        ThreeWay::Equal => {
+            node.rotate_left();
+            node.state = ThreeWay::Left;
+            node.left.0.as_mut().unwrap().state = ThreeWay::Right;
+            left_height + 3
+        }
+    };
+    (Some(node), height)
+}
 
-        let mut rest = order;
+// right の方が 2 以上高いとき、right の左の縁に連結する
+fn join_left<K: Ord, V>(
+    left: Measured<K, V>,
+    mid: Box<Node<K, V>>,
+    right: Measured<K, V>,
+) -> Measured<K, V> {
+    let mut node = right.0.unwrap();
+    let (left_height, right_height) = child_heights(&node, right.1);
+    let (joined, joined_height) = join(left, mid, (node.left.0.take(), left_height));
+    node.left = NodePtr(joined);
+    if joined_height <= right_height + 1 {
+        return attach_existing(node, joined_height, right_height);
+    }
+    // 左の子が右の子より 2 高くなったので回転する
+    let height = match node.left.0.as_ref().unwrap().state {
+        ThreeWay::Right => {
+            double_rotate_right(node.as_mut());
+            right_height + 2
+        }
+        ThreeWay::Left => {
+            node.rotate_right();
+            node.state = ThreeWay::Equal;
+            node.right.0.as_mut().unwrap().state = ThreeWay::Equal;
+            right_height + 2
+        }
+        ThreeWay::Equal => {
+            node.rotate_right();
+            node.state = ThreeWay::Right;
+            node.right.0.as_mut().unwrap().state = ThreeWay::Left;
+            right_height + 3
+        }
+    };
+    (Some(node), height)
+}
+
+// 子を付け替えたノードの平衡状態と size を、子の高さから求め直す
+fn attach_existing<K, V>(
+    mut node: Box<Node<K, V>>,
+    left_height: usize,
+    right_height: usize,
+) -> Measured<K, V> {
+    let left = (node.left.0.take(), left_height);
+    let right = (node.right.0.take(), right_height);
+    attach(node, left, right)
+}
+
+/**
+ * key 未満の木、key のノード (存在する場合)、key より大きい木に分ける
+ * 根から key までの経路に沿って join するので O(log n) かかる
+ */
+fn split<K: Ord, V>(tree: Measured<K, V>, key: &K) -> Split<K, V> {
+    let mut node = match tree.0 {
+        Some(node) => node,
+        None => return ((None, 0), None, (None, 0)),
+    };
+    let (left_height, right_height) = child_heights(&node, tree.1);
+    let left = (node.left.0.take(), left_height);
+    let right = (node.right.0.take(), right_height);
+    match key.cmp(&node.key) {
+        std::cmp::Ordering::Less => {
+            let (less, found, greater) = split(left, key);
+            (less, found, join(greater, node, right))
+        }
+        std::cmp::Ordering::Greater => {
+            let (less, found, greater) = split(right, key);
+            (join(left, node, less), found, greater)
+        }
+        std::cmp::Ordering::Equal => (left, Some(node), right),
+    }
+}
+
+/**
+ * 2 つの木の和をとる
+ * 高い方の木を低い方の木の根のキーで分割し、左右それぞれの和を再帰的に求めて根で連結する
+ * 同じキーがあった場合は f(キー, self 側の値, other 側の値) を値とし、self 側のキーを残す
+ * swapped は a が other 側の木であることを表す
+ */
+fn union<K: Ord, V, F: FnMut(&K, V, V) -> V>(
+    a: Measured<K, V>,
+    b: Measured<K, V>,
+    f: &mut F,
+    swapped: bool,
+) -> Measured<K, V> {
+    if a.0.is_none() {
+        return b;
+    }
+    if b.0.is_none() {
+        return a;
+    }
+    if a.1 < b.1 {
+        return union(b, a, f, !swapped);
+    }
+    let mut root = b.0.unwrap();
+    let (left_height, right_height) = child_heights(&root, b.1);
+    let b_left = (root.left.0.take(), left_height);
+    let b_right = (root.right.0.take(), right_height);
+    let (a_left, found, a_right) = split(a, &root.key);
+    let left = union(a_left, b_left, f, swapped);
+    let right = union(a_right, b_right, f, swapped);
+    let mid = match found {
+        Some(found) => {
+            let (ours, theirs) = if swapped {
+                (root, found)
+            } else {
+                (found, root)
+            };
+            let Node { key, value, .. } = *ours;
+            let theirs = theirs.value;
+            let value = f(&key, value, theirs);
+            Box::new(Node::new(key, value))
+        }
+        None => root,
+    };
+    join(left, mid, right)
+}
+
+// これより小さい部分問題は並列化せずに解く
+#[cfg(feature = "rayon")]
+const PARALLEL_GRAIN: usize = 1 << 12;
+
+#[cfg(feature = "rayon")]
+fn join_if<A: Send, B: Send>(
+    parallel: bool,
+    a: impl FnOnce() -> A + Send,
+    b: impl FnOnce() -> B + Send,
+) -> (A, B) {
+    if parallel {
+        rayon::join(a, b)
+    } else {
+        (a(), b())
+    }
+}
+
+#[cfg(feature = "rayon")]
+fn tree_size<K, V>(tree: &Measured<K, V>) -> usize {
+    tree.0.as_ref().map_or(0, |node| node.size)
+}
+
+// 最小の要素のノードを取り外し、残りの木とともに返す
+#[cfg(feature = "rayon")]
+fn pop_min<K: Ord, V>(tree: Measured<K, V>) -> (Box<Node<K, V>>, Measured<K, V>) {
+    let mut node = tree.0.unwrap();
+    let (left_height, right_height) = child_heights(&node, tree.1);
+    let right = (node.right.0.take(), right_height);
+    match node.left.0.take() {
+        None => (node, right),
+        Some(left) => {
+            let (min, left) = pop_min((Some(left), left_height));
+            (min, join(left, node, right))
+        }
+    }
+}
+
+// left のすべてのキー < right のすべてのキー のときに、2 つを連結する
+#[cfg(feature = "rayon")]
+fn join2<K: Ord, V>(left: Measured<K, V>, right: Measured<K, V>) -> Measured<K, V> {
+    if right.0.is_none() {
+        return left;
+    }
+    let (min, right) = pop_min(right);
+    join(left, min, right)
+}
+
+// 昇順に並んだ要素から from_sorted_rec と同じ形の木を作る
+// 大きい部分木は左右を並列に作る
+#[cfg(feature = "rayon")]
+fn from_sorted_par_rec<K: Send, V: Send>(slots: &mut [Option<(K, V)>]) -> Measured<K, V> {
+    let n = slots.len();
+    if n == 0 {
+        return (None, 0);
+    }
+    let (left_slots, rest) = slots.split_at_mut(n / 2);
+    let (mid, right_slots) = rest.split_first_mut().unwrap();
+    let ((left, left_height), (right, right_height)) = join_if(
+        n >= PARALLEL_GRAIN,
+        || from_sorted_par_rec(left_slots),
+        || from_sorted_par_rec(right_slots),
+    );
+    let (key, value) = mid.take().unwrap();
+    let state = if left_height > right_height {
+        ThreeWay::Left
+    } else {
+        ThreeWay::Equal
+    };
+    let node = Node {
+        key,
+        value,
+        left: NodePtr(left),
+        right: NodePtr(right),
+        size: n,
+        state,
+    };
+    (Some(Box::new(node)), left_height + 1)
+}
+
+// union と同じく分割と連結で和をとり、左右の部分問題を並列に解く
+// 同じキーは self 側 (swapped でなければ a) のノードを残す
+#[cfg(feature = "rayon")]
+fn union_par_rec<K: Ord + Send, V: Send>(
+    a: Measured<K, V>,
+    b: Measured<K, V>,
+    swapped: bool,
+) -> Measured<K, V> {
+    if a.0.is_none() {
+        return b;
+    }
+    if b.0.is_none() {
+        return a;
+    }
+    if a.1 < b.1 {
+        return union_par_rec(b, a, !swapped);
+    }
+    let parallel = tree_size(&a) + tree_size(&b) >= PARALLEL_GRAIN;
+    let mut root = b.0.unwrap();
+    let (left_height, right_height) = child_heights(&root, b.1);
+    let b_left = (root.left.0.take(), left_height);
+    let b_right = (root.right.0.take(), right_height);
+    let (a_left, found, a_right) = split(a, &root.key);
+    let (left, right) = join_if(
+        parallel,
+        || union_par_rec(a_left, b_left, swapped),
+        || union_par_rec(a_right, b_right, swapped),
+    );
+    let mid = match found {
+        Some(found) if !swapped => found,
+        _ => root,
+    };
+    join(left, mid, right)
+}
+
+// 両方にあるキーだけを残す。左右の部分問題は並列に解く
+#[cfg(feature = "rayon")]
+fn intersection_par_rec<K: Ord + Send, V: Send>(
+    a: Measured<K, V>,
+    b: Measured<K, V>,
+    swapped: bool,
+) -> Measured<K, V> {
+    if a.0.is_none() || b.0.is_none() {
+        return (None, 0);
+    }
+    if a.1 < b.1 {
+        return intersection_par_rec(b, a, !swapped);
+    }
+    let parallel = tree_size(&a) + tree_size(&b) >= PARALLEL_GRAIN;
+    let mut root = b.0.unwrap();
+    let (left_height, right_height) = child_heights(&root, b.1);
+    let b_left = (root.left.0.take(), left_height);
+    let b_right = (root.right.0.take(), right_height);
+    let (a_left, found, a_right) = split(a, &root.key);
+    let (left, right) = join_if(
+        parallel,
+        || intersection_par_rec(a_left, b_left, swapped),
+        || intersection_par_rec(a_right, b_right, swapped),
+    );
+    match found {
+        Some(found) => join(left, if swapped { root } else { found }, right),
+        None => join2(left, right),
+    }
+}
+
+impl<K, V> NodePtr<K, V>
+where
+    K: Ord,
+{
+    /**
+     * 再帰せずに挿入する
+     * 根から降りながら経路を記録して葉に新しいノードを置き、もう一度根からたどって size を増やす
+     * 高さの変化が止まるノード (平衡状態が Equal でない最も深いノード) より下の平衡状態を書き換え、
+     * 最後にそのノードで必要なら回転する
+     * key がすでに存在した場合は値を置き換え、元の値を返す
+     */
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_ranked(key, value).1
+    }
+
+    /**
+     * insert と同じだが、key の順位 (key 未満の要素の個数) もあわせて返す
+     * 回転でノードの中身が入れ替わっても順位は変わらないので、呼び出し側は順位から要素を引き直せる
+     */
+    fn insert_ranked(&mut self, key: K, value: V) -> (usize, Option<V>) {
+        let mut path = Path::new();
+        let mut pivot = 0;
+        let mut order = 0;
+        let mut slot = &mut self.0;
+        while let Some(node) = slot {
+            let dir = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => ThreeWay::Left,
+                std::cmp::Ordering::Greater => ThreeWay::Right,
+                std::cmp::Ordering::Equal => {
+                    let old = std::mem::replace(&mut node.value, value);
+                    return (order + node.left.size(), Some(old));
+                }
+            };
+            if node.state != ThreeWay::Equal {
+                pivot = path.len;
+            }
+            if dir == ThreeWay::Right {
+                order += node.left.size() + 1;
+            }
+            path.push(Step {
+                dir,
+                state: node.state,
+                sibling: ThreeWay::Equal,
+            });
+            slot = node.child_mut(dir);
+        }
+        *slot = Some(Box::new(Node::new(key, value)));
+        if path.is_empty() {
+            return (0, None);
+        }
+
+        let mut slot = &mut self.0;
+        for step in &path[..pivot] {
+            let node = slot.as_mut().unwrap();
+            node.size += 1;
+            slot = node.child_mut(step.dir);
+        }
+        let pivot_node = slot.as_mut().unwrap();
+        pivot_node.size += 1;
+        let mut below = pivot_node.child_mut(path[pivot].dir);
+        // pivot より下のノードはすべて Equal なので、進んだ向きに傾く
+        for step in &path[pivot + 1..] {
+            let node = below.as_mut().unwrap();
+            node.size += 1;
+            node.state = step.dir;
+            below = node.child_mut(step.dir);
+        }
+
+        match path[pivot].dir {
+            ThreeWay::Left => grown_left(pivot_node.as_mut()),
+            _ => grown_right(pivot_node.as_mut()),
+        };
+        (order, None)
+    }
+
+    /**
+     * 再帰せずに削除する
+     * 根から降りながら経路を記録してノードを取り外し、下から順に高さの減少がどこまで伝わるかを求める
+     * そのあともう一度根からたどって size を減らし、その深さから下の平衡状態の調整と回転を行う
+     */
+    fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let mut path = Path::new();
+        let mut slot = &mut self.0;
+        let removed = loop {
+            let dir = match key.cmp(slot.as_deref()?.key.borrow()) {
+                std::cmp::Ordering::Less => ThreeWay::Left,
+                std::cmp::Ordering::Greater => ThreeWay::Right,
+                std::cmp::Ordering::Equal => break detach_iter(slot, &mut path),
+            };
+            let node = slot.as_mut().unwrap();
+            path.push(Step {
+                dir,
+                state: node.state,
+                sibling: sibling_state(node, dir),
+            });
+            slot = node.child_mut(dir);
+        };
+        if path.is_empty() {
+            return Some(removed.value);
+        }
+
+        // 高さの減少が止まる深さを下から求める
+        let mut stop = 0;
+        for (depth, step) in path.iter().enumerate().rev() {
+            let decreased = if step.state == ThreeWay::Equal {
+                false
+            } else if step.state == step.dir {
+                true
+            } else {
+                step.sibling != ThreeWay::Equal
+            };
+            if !decreased {
+                stop = depth;
+                break;
+            }
+        }
+
+        let mut slot = &mut self.0;
+        slot.as_mut().unwrap().size -= 1;
+        for (depth, step) in path.iter().enumerate() {
+            let node = slot.as_mut().unwrap();
+            let last = depth + 1 == path.len;
+            // 回転で update したときに削除後の大きさが使われるよう、子の size を先に減らす
+            if !last {
+                node.child_mut(step.dir).as_mut().unwrap().size -= 1;
+            }
+            if depth < stop {
+                slot = node.child_mut(step.dir);
+                continue;
+            }
+            // 回転した場合、元のノードは進む向きの子に下がっている
+            let rotated = step.state == opposite(step.dir);
+            match step.dir {
+                ThreeWay::Left => shrunk_left(node.as_mut()),
+                _ => shrunk_right(node.as_mut()),
+            };
+            if last {
+                break;
+            }
+            slot = if rotated {
+                node.child_mut(step.dir)
+                    .as_mut()
+                    .unwrap()
+                    .child_mut(step.dir)
+            } else {
+                node.child_mut(step.dir)
+            };
+        }
+
+        Some(removed.value)
+    }
+
+    // 比較用に残している再帰版 (insert_rec / remove_rec を使う)
+    #[cfg(feature = "bench")]
+    fn insert_recursive(&mut self, key: K, value: V) -> Option<V> {
+        let mut old = None;
+        insert_rec(&mut self.0, key, |key, node| match node {
+            Some(node) => {
+                old = Some(std::mem::replace(&mut node.value, value));
+                None
+            }
+            None => Some(Node::new(key, value)),
+        });
+        old
+    }
+
+    #[cfg(feature = "bench")]
+    fn remove_recursive(&mut self, key: &K) -> Option<V> {
+        remove_rec(&mut self.0, key).0.map(|node| node.value)
+    }
+
+    fn search<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        match self.0.as_ref() {
+            None => None,
+            Some(node) => match key.cmp(node.key.borrow()) {
+                std::cmp::Ordering::Less => node.left.search(key),
+                std::cmp::Ordering::Greater => node.right.search(key),
+                std::cmp::Ordering::Equal => Some(&node.value),
+            },
+        }
+    }
+
+    fn search_mut(&mut self, key: &K) -> Option<&mut V> {
+        match self.0.as_mut() {
+            None => None,
+            Some(node) => match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.search_mut(key),
+                std::cmp::Ordering::Greater => node.right.search_mut(key),
+                std::cmp::Ordering::Equal => Some(&mut node.value),
+            },
+        }
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        match self.0.as_ref() {
+            Some(mut node) => {
+                while let Some(nxt) = node.left.0.as_ref() {
+                    node = nxt;
+                }
+                Some((&node.key, &node.value))
+            }
+            None => None,
+        }
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        match self.0.as_ref() {
+            Some(mut node) => {
+                while let Some(nxt) = node.right.0.as_ref() {
+                    node = nxt;
+                }
+                Some((&node.key, &node.value))
+            }
+            None => None,
+        }
+    }
+
+    fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
         let mut node = self.0.as_ref().unwrap();
         loop {
             match node.left.size().cmp(&rest) {
@@ -617,6 +1440,12 @@ where
         }
     }
 
+    fn remove_by_order(&mut self, order: usize) -> Option<(K, V)> {
+        remove_by_order_rec(&mut self.0, order)
+            .0
+            .map(|node| (node.key, node.value))
+    }
+
     fn order_of_key(&self, key: &K) -> usize {
         let mut order = 0;
         let mut node = match self.0.as_ref() {
@@ -654,22 +1483,159 @@ impl<K: Ord, V> AVLTreeMap<K, V> {
         AVLTreeMap { root: None.into() }
     }
 
+    /**
+     * キーの昇順に並んだ重複のない要素から O(n) で木を作る
+     */
+    pub(crate) fn from_sorted(vec: Vec<(K, V)>) -> Self {
+        debug_assert!(vec.windows(2).all(|w| w[0].0 < w[1].0));
+        let n = vec.len();
+        AVLTreeMap {
+            root: NodePtr::from_sorted_rec(&mut vec.into_iter(), n).0,
+        }
+    }
+
     pub fn insert(&mut self, key: K, value: V) -> bool {
+        self.root.insert(key, value).is_none()
+    }
+
+    /**
+     * insert と同じだが、key がすでに存在した場合は置き換える前の値を返す
+     */
+    pub(crate) fn replace(&mut self, key: K, value: V) -> Option<V> {
         self.root.insert(key, value)
     }
 
+    /**
+     * replace と同じだが、木に置かれたキーと値への参照もあわせて返す
+     * キーの比較は挿入の 1 回だけで、参照は挿入で求めた順位から根をたどり直して得る
+     */
+    pub(crate) fn replace_entry(&mut self, key: K, value: V) -> (&K, &V, Option<V>) {
+        let (order, old) = self.root.insert_ranked(key, value);
+        let (key, value) = self.root.find_by_order(order).unwrap();
+        (key, value, old)
+    }
+
     pub fn remove(&mut self, key: &K) -> Option<V> {
         self.root.remove(key)
     }
 
+    /**
+     * 再帰版の insert / remove
+     * ベンチマークで非再帰版と比べるためのもので、動作は insert / remove と同じ
+     * bench フィーチャーを有効にしたときだけ使える
+     */
+    #[cfg(feature = "bench")]
+    #[doc(hidden)]
+    pub fn insert_recursive(&mut self, key: K, value: V) -> bool {
+        self.root.insert_recursive(key, value).is_none()
+    }
+
+    #[cfg(feature = "bench")]
+    #[doc(hidden)]
+    pub fn remove_recursive(&mut self, key: &K) -> Option<V> {
+        self.root.remove_recursive(key)
+    }
+
     pub fn get(&self, key: &K) -> Option<&V> {
         self.root.search(key)
     }
 
+    /**
+     * get / remove と同じだが、Vec<u8> のキーを &[u8] で引くように、借用した形のキーで探す
+     */
+    pub(crate) fn get_borrowed<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.root.search(key)
+    }
+
+    pub(crate) fn remove_borrowed<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.root.remove(key)
+    }
+
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
         self.root.search_mut(key)
     }
 
+    /**
+     * self を古い木、other を新しい木として、差分をキーの昇順に返す
+     * 両方の木を間順に突き合わせるので O(n + m) かかる
+     */
+    pub fn diff<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = DiffItem<'a, K, V>> + 'a
+    where
+        V: PartialEq,
+    {
+        Diff::new(self.root.0.as_deref(), other.root.0.as_deref())
+    }
+
+    /**
+     * other のすべての要素を self に加える
+     * 両方にあるキーの値は f(キー, self の値, other の値) の結果にする。f を呼ぶ順序は定めない
+     * 木の分割と連結で和をとるので、m 個の要素の木と n 個の要素の木 (m <= n) の併合は
+     * どちらが self であっても O(m log(n / m + 1)) で済む
+     */
+    pub fn merge_with<F: FnMut(&K, V, V) -> V>(&mut self, other: Self, mut f: F) {
+        let ours = measured(NodePtr(self.root.0.take()));
+        let merged = union(ours, measured(other.root), &mut f, false);
+        self.root = NodePtr(merged.0);
+    }
+
+    /**
+     * order 番目の要素を指すカーソルを返す
+     */
+    pub(crate) fn cursor_at(&self, order: usize) -> Cursor<'_, K, V> {
+        Cursor::at_order(self.root.0.as_deref(), order)
+    }
+
+    /**
+     * 昇順に並んだ重複のない要素から、大きい部分木を並列に組み立てて木を作る
+     * slots の要素はすべて取り出される
+     */
+    #[cfg(feature = "rayon")]
+    pub(crate) fn from_sorted_slots(slots: &mut [Option<(K, V)>]) -> Self
+    where
+        K: Send,
+        V: Send,
+    {
+        AVLTreeMap {
+            root: NodePtr(from_sorted_par_rec(slots).0),
+        }
+    }
+
+    /**
+     * f が false を返した要素をすべて取り除く
+     * 要素はキーの昇順に f に渡す
+     */
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, f: F) {
+        self.retain_removed(f);
+    }
+
+    /**
+     * retain と同じだが、取り除いた要素をキーの昇順に返す
+     * f はすべて木を崩す前に呼ぶので、f が panic しても木は元の要素を保つ
+     * 残す要素を昇順に集めて作り直すので、全体で O(n) になる
+     */
+    pub(crate) fn retain_removed<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) -> Vec<(K, V)> {
+        let mut keep = Vec::with_capacity(self.size());
+        self.root.retain_decide(&mut f, &mut keep);
+        if keep.iter().all(|&keep| keep) {
+            return Vec::new();
+        }
+        let mut kept = Vec::with_capacity(self.size());
+        let mut removed = Vec::new();
+        std::mem::replace(&mut self.root, NodePtr(None)).partition_into(
+            &mut keep.into_iter(),
+            &mut kept,
+            &mut removed,
+        );
+        *self = Self::from_sorted(kept);
+        removed
+    }
+
     pub fn min(&self) -> Option<(&K, &V)> {
         self.root.min()
     }
@@ -704,6 +1670,108 @@ impl<K: Ord, V> AVLTreeMap<K, V> {
     pub fn values(&self) -> Vec<&V> {
         self.root.values()
     }
+
+    /**
+     * key 以上である最小の要素を指すカーソルを返す
+     * そのような要素が存在しない場合は末尾の番兵を指す
+     */
+    pub fn lower_bound(&self, key: &K) -> Cursor<'_, K, V> {
+        Cursor::lower_bound(&self.root, key)
+    }
+
+    /**
+     * key 以上である最小の要素を指す、木を書き換えられるカーソルを返す
+     * そのような要素が存在しない場合は末尾の番兵を指す
+     */
+    pub fn lower_bound_mut(&mut self, key: &K) -> CursorMut<'_, K, V> {
+        CursorMut::lower_bound(&mut self.root, key)
+    }
+
+    /**
+     * 木の不変条件がすべて成り立っているかを確かめる
+     * 成り立っていない場合は、違反のあったノードの根からの経路とその内容を返す
+     */
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        validate_avl(self.root.0.as_deref())
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     */
+    pub fn stats(&self) -> TreeStats {
+        avl_tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + Clone, V> AVLTreeMap<K, V> {
+    /**
+     * トランザクションを開始する
+     * トランザクションを commit せずに破棄すると、開始時の内容に戻る
+     */
+    pub fn begin(&mut self) -> Transaction<'_, K, V> {
+        Transaction::new(self)
+    }
+
+    /**
+     * f の中の操作をまとめて行う
+     * f が Ok を返した場合は確定し、Err を返した場合やパニックした場合は開始時の内容に戻す
+     */
+    pub fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Transaction<'_, K, V>) -> Result<T, E>,
+    {
+        let mut tx = self.begin();
+        let result = f(&mut tx);
+        if result.is_ok() {
+            tx.commit();
+        }
+        result
+    }
+}
+impl<K: Ord + SnapshotValue, V: SnapshotValue> AVLTreeMap<K, V> {
+    /**
+     * 木の形と平衡状態を保ったまま、要素をキーの昇順に書き出す
+     */
+    pub fn write_snapshot<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_header(writer, SnapshotKind::AVLTreeMap, self.size())?;
+        self.root.write_snapshot_entries(0, writer)
+    }
+
+    /**
+     * write_snapshot で書き出した木を、平衡操作をせずにそのままの形で読み込む
+     * 読み込んだ木が不変条件を満たさない場合はエラーを返す
+     */
+    pub fn read_snapshot<R: Read>(mut reader: R) -> Result<Self, SnapshotError> {
+        let count = read_header(&mut reader, SnapshotKind::AVLTreeMap)?;
+        let tree = AVLTreeMap {
+            root: NodePtr::read_snapshot_entries(&mut reader, count)?,
+        };
+        tree.validate()?;
+        Ok(tree)
+    }
+}
+impl<K: Ord + std::fmt::Debug, V> AVLTreeMap<K, V> {
+    /**
+     * 木の形を Graphviz の DOT 形式で返す
+     * 各ノードにはキー、部分木の大きさ、平衡状態を表示する
+     */
+    pub fn to_dot(&self) -> String {
+        to_dot(self.root.0.as_deref(), node_label)
+    }
+
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに部分木の大きさと平衡状態を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
 }
 impl<'a, K: Ord, V> IntoIterator for &'a AVLTreeMap<K, V> {
     type Item = (&'a K, &'a V);
@@ -736,8 +1804,65 @@ impl<K: Ord> AVLTreeSet<K> {
         AVLTreeSet { root: None.into() }
     }
 
+    /**
+     * 昇順に並んだ重複のない要素から O(n) で木を作る
+     */
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_sorted(vec: Vec<K>) -> Self {
+        debug_assert!(vec.windows(2).all(|w| w[0] < w[1]));
+        let n = vec.len();
+        AVLTreeSet {
+            root: NodePtr::from_sorted_rec(&mut vec.into_iter().map(|key| (key, ())), n).0,
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    pub(crate) fn cursor_at(&self, order: usize) -> Cursor<'_, K, ()> {
+        Cursor::at_order(self.root.0.as_deref(), order)
+    }
+
+    #[cfg(feature = "rayon")]
+    pub(crate) fn from_sorted_slots(slots: &mut [Option<(K, ())>]) -> Self
+    where
+        K: Send,
+    {
+        AVLTreeSet {
+            root: NodePtr(from_sorted_par_rec(slots).0),
+        }
+    }
+
+    /**
+     * 2 つの木の和を、分割と連結による分割統治で並列に求める
+     */
+    #[cfg(feature = "rayon")]
+    pub(crate) fn union_par(self, other: Self) -> Self
+    where
+        K: Send,
+    {
+        let ours = measured(self.root);
+        let theirs = measured(other.root);
+        AVLTreeSet {
+            root: NodePtr(union_par_rec(ours, theirs, false).0),
+        }
+    }
+
+    /**
+     * 2 つの木の共通部分を、分割と連結による分割統治で並列に求める
+     */
+    #[cfg(feature = "rayon")]
+    pub(crate) fn intersection_par(self, other: Self) -> Self
+    where
+        K: Send,
+    {
+        let ours = measured(self.root);
+        let theirs = measured(other.root);
+        AVLTreeSet {
+            root: NodePtr(intersection_par_rec(ours, theirs, false).0),
+        }
+    }
+
     pub fn insert(&mut self, key: K) -> bool {
-        self.root.insert(key, ())
+        self.root.insert(key, ()).is_none()
     }
 
     pub fn remove(&mut self, key: &K) -> bool {
@@ -778,6 +1903,83 @@ impl<K: Ord> AVLTreeSet<K> {
     pub fn keys(&self) -> Vec<&K> {
         self.root.keys()
     }
+
+    /**
+     * key 以上である最小の要素を指すカーソルを返す
+     * そのような要素が存在しない場合は末尾の番兵を指す
+     */
+    pub fn lower_bound(&self, key: &K) -> SetCursor<'_, K> {
+        SetCursor(Cursor::lower_bound(&self.root, key))
+    }
+
+    /**
+     * key 以上である最小の要素を指す、木を書き換えられるカーソルを返す
+     * そのような要素が存在しない場合は末尾の番兵を指す
+     */
+    pub fn lower_bound_mut(&mut self, key: &K) -> SetCursorMut<'_, K> {
+        SetCursorMut(CursorMut::lower_bound(&mut self.root, key))
+    }
+
+    /**
+     * 木の不変条件がすべて成り立っているかを確かめる
+     * 成り立っていない場合は、違反のあったノードの根からの経路とその内容を返す
+     */
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        validate_avl(self.root.0.as_deref())
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     */
+    pub fn stats(&self) -> TreeStats {
+        avl_tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + SnapshotValue> AVLTreeSet<K> {
+    /**
+     * 木の形と平衡状態を保ったまま、要素を昇順に書き出す
+     */
+    pub fn write_snapshot<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_header(writer, SnapshotKind::AVLTreeSet, self.size())?;
+        self.root.write_snapshot_entries(0, writer)
+    }
+
+    /**
+     * write_snapshot で書き出した木を、平衡操作をせずにそのままの形で読み込む
+     * 読み込んだ木が不変条件を満たさない場合はエラーを返す
+     */
+    pub fn read_snapshot<R: Read>(mut reader: R) -> Result<Self, SnapshotError> {
+        let count = read_header(&mut reader, SnapshotKind::AVLTreeSet)?;
+        let tree = AVLTreeSet {
+            root: NodePtr::read_snapshot_entries(&mut reader, count)?,
+        };
+        tree.validate()?;
+        Ok(tree)
+    }
+}
+impl<K: Ord + std::fmt::Debug> AVLTreeSet<K> {
+    /**
+     * 木の形を Graphviz の DOT 形式で返す
+     * 各ノードにはキー、部分木の大きさ、平衡状態を表示する
+     */
+    pub fn to_dot(&self) -> String {
+        to_dot(self.root.0.as_deref(), node_label)
+    }
+
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに部分木の大きさと平衡状態を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
 }
 impl<'a, K: Ord> IntoIterator for &'a AVLTreeSet<K> {
     type Item = &'a K;
@@ -805,3 +2007,561 @@ impl<K: Ord> FromIterator<K> for AVLTreeSet<K> {
         tree
     }
 }
+
+/**
+ * AVLTreeMap の要素を指すカーソル
+ * 最大の要素の次には要素を持たない番兵があり、番兵の次は最小の要素に戻る
+ * 根から現在のノードまでの経路を持つため、隣への移動は償却 O(1) でできる
+ */
+pub struct Cursor<'a, K, V> {
+    root: Option<&'a Node<K, V>>,
+    // 根から現在のノードまでの経路 (番兵を指すときは空)
+    stack: Vec<&'a Node<K, V>>,
+    index: usize,
+}
+impl<'a, K: Ord, V> Cursor<'a, K, V> {
+    fn lower_bound(root: &'a NodePtr<K, V>, key: &K) -> Self {
+        let mut stack = Vec::new();
+        let mut depth = 0;
+        let mut index = 0;
+        let mut node = root.0.as_deref();
+        while let Some(cur) = node {
+            stack.push(cur);
+            node = match key.cmp(&cur.key) {
+                std::cmp::Ordering::Greater => {
+                    index += cur.left.size() + 1;
+                    cur.right.0.as_deref()
+                }
+                std::cmp::Ordering::Equal => {
+                    index += cur.left.size();
+                    depth = stack.len();
+                    break;
+                }
+                std::cmp::Ordering::Less => {
+                    depth = stack.len();
+                    cur.left.0.as_deref()
+                }
+            };
+        }
+        stack.truncate(depth);
+        Cursor {
+            root: root.0.as_deref(),
+            stack,
+            index,
+        }
+    }
+}
+impl<'a, K, V> Cursor<'a, K, V> {
+    /**
+     * root を根とする木の order 番目の要素を指すカーソルを作る
+     * order が要素の個数に等しい場合は番兵を指す
+     */
+    fn at_order(root: Option<&'a Node<K, V>>, order: usize) -> Self {
+        let mut stack = Vec::new();
+        let mut rest = order;
+        let mut node = root.filter(|root| order < root.size);
+        while let Some(cur) = node {
+            stack.push(cur);
+            node = match cur.left.size().cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= cur.left.size() + 1;
+                    cur.right.0.as_deref()
+                }
+                std::cmp::Ordering::Greater => cur.left.0.as_deref(),
+                std::cmp::Ordering::Equal => None,
+            };
+        }
+        Cursor {
+            root,
+            stack,
+            index: order,
+        }
+    }
+
+    /**
+     * 同じ木の order 番目の要素を指すカーソルを作る
+     */
+    #[cfg(feature = "rayon")]
+    pub(crate) fn at(&self, order: usize) -> Self {
+        Cursor::at_order(self.root, order)
+    }
+
+    fn size(&self) -> usize {
+        self.root.map_or(0, |node| node.size)
+    }
+
+    fn current(&self) -> Option<&'a Node<K, V>> {
+        self.stack.last().copied()
+    }
+
+    // 現在のノードから左の子をたどれるだけたどる
+    fn descend_left(&mut self, mut node: &'a Node<K, V>) {
+        self.stack.push(node);
+        while let Some(left) = node.left.0.as_deref() {
+            self.stack.push(left);
+            node = left;
+        }
+    }
+
+    fn descend_right(&mut self, mut node: &'a Node<K, V>) {
+        self.stack.push(node);
+        while let Some(right) = node.right.0.as_deref() {
+            self.stack.push(right);
+            node = right;
+        }
+    }
+
+    /**
+     * 現在の要素の昇順での位置を返す
+     * 番兵を指しているときは要素の個数を返す
+     */
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn key(&self) -> Option<&'a K> {
+        self.current().map(|node| &node.key)
+    }
+
+    pub fn value(&self) -> Option<&'a V> {
+        self.current().map(|node| &node.value)
+    }
+
+    pub fn move_next(&mut self) {
+        let node = match self.current() {
+            Some(node) => node,
+            None => {
+                if let Some(root) = self.root {
+                    self.descend_left(root);
+                }
+                self.index = 0;
+                return;
+            }
+        };
+        self.index += 1;
+        if let Some(right) = node.right.0.as_deref() {
+            self.descend_left(right);
+            return;
+        }
+        // 左の子として登ってくるまで戻る
+        while let Some(child) = self.stack.pop() {
+            match self.current() {
+                Some(parent) if is_left_child(parent, child) => return,
+                Some(_) => {}
+                None => return,
+            }
+        }
+    }
+
+    pub fn move_prev(&mut self) {
+        let node = match self.current() {
+            Some(node) => node,
+            None => {
+                if let Some(root) = self.root {
+                    self.descend_right(root);
+                    self.index = self.size() - 1;
+                }
+                return;
+            }
+        };
+        if let Some(left) = node.left.0.as_deref() {
+            self.index -= 1;
+            self.descend_right(left);
+            return;
+        }
+        // 右の子として登ってくるまで戻る
+        while let Some(child) = self.stack.pop() {
+            match self.current() {
+                Some(parent) if is_right_child(parent, child) => {
+                    self.index -= 1;
+                    return;
+                }
+                Some(_) => {}
+                None => {
+                    self.index = self.size();
+                    return;
+                }
+            }
+        }
+    }
+
+    /**
+     * カーソルを動かさずに次の要素を返す
+     */
+    pub fn peek_next(&self) -> Option<(&'a K, &'a V)> {
+        let node = match self.current() {
+            Some(node) => node,
+            None => return leftmost(self.root?),
+        };
+        if let Some(right) = node.right.0.as_deref() {
+            return leftmost(right);
+        }
+        self.stack
+            .windows(2)
+            .rev()
+            .find(|pair| is_left_child(pair[0], pair[1]))
+            .map(|pair| (&pair[0].key, &pair[0].value))
+    }
+
+    /**
+     * カーソルを動かさずに前の要素を返す
+     */
+    pub fn peek_prev(&self) -> Option<(&'a K, &'a V)> {
+        let node = match self.current() {
+            Some(node) => node,
+            None => return rightmost(self.root?),
+        };
+        if let Some(left) = node.left.0.as_deref() {
+            return rightmost(left);
+        }
+        self.stack
+            .windows(2)
+            .rev()
+            .find(|pair| is_right_child(pair[0], pair[1]))
+            .map(|pair| (&pair[0].key, &pair[0].value))
+    }
+}
+
+fn is_left_child<K, V>(parent: &Node<K, V>, child: &Node<K, V>) -> bool {
+    parent
+        .left
+        .0
+        .as_deref()
+        .is_some_and(|left| std::ptr::eq(left, child))
+}
+
+fn is_right_child<K, V>(parent: &Node<K, V>, child: &Node<K, V>) -> bool {
+    parent
+        .right
+        .0
+        .as_deref()
+        .is_some_and(|right| std::ptr::eq(right, child))
+}
+
+fn leftmost<K, V>(mut node: &Node<K, V>) -> Option<(&K, &V)> {
+    while let Some(left) = node.left.0.as_deref() {
+        node = left;
+    }
+    Some((&node.key, &node.value))
+}
+
+fn rightmost<K, V>(mut node: &Node<K, V>) -> Option<(&K, &V)> {
+    while let Some(right) = node.right.0.as_deref() {
+        node = right;
+    }
+    Some((&node.key, &node.value))
+}
+
+/**
+ * AVLTreeMap の要素を指し、木を書き換えられるカーソル
+ * 根から現在のノードまでに進んだ向きを持ち、要素を読み書きするときは根からその向きにたどり直す
+ * 親へ戻る移動は向きを取り除くだけでよく、読み書きや移動はいずれも O(log n) でできる
+ * 要素の挿入・削除で木の形が変わったときは、位置 (0-indexed) から向きを作り直す
+ */
+pub struct CursorMut<'a, K, V> {
+    root: &'a mut NodePtr<K, V>,
+    // 根から現在のノードまでに進んだ向き (番兵を指すときは空)
+    path: Vec<ThreeWay>,
+    // 番兵を指すときは要素の個数に等しい
+    index: usize,
+}
+impl<'a, K: Ord, V> CursorMut<'a, K, V> {
+    fn lower_bound(root: &'a mut NodePtr<K, V>, key: &K) -> Self {
+        let index = root.order_of_key(key);
+        let mut cursor = CursorMut {
+            root,
+            path: Vec::new(),
+            index,
+        };
+        cursor.seek();
+        cursor
+    }
+
+    /**
+     * 現在の要素を取り除いて返し、カーソルは次の要素を指す
+     * 番兵を指しているときは何もせず None を返す
+     */
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        if self.is_ghost() {
+            return None;
+        }
+        let removed = self.root.remove_by_order(self.index);
+        self.seek();
+        removed
+    }
+
+    /**
+     * 現在の要素の直前に要素を挿入する
+     * 番兵を指しているときは最大の要素の後ろに挿入する
+     * key が前の要素のキーより大きく、現在の要素のキーより小さくない場合は panic する
+     */
+    pub fn insert_before(&mut self, key: K, value: V) {
+        assert!(
+            self.peek_prev().is_none_or(|(prev, _)| *prev < key)
+                && self.key().is_none_or(|cur| key < *cur),
+            "key must lie strictly between the previous and the current key"
+        );
+        self.root.insert(key, value);
+        self.index += 1;
+        self.seek();
+    }
+
+    /**
+     * 現在の要素の直後に要素を挿入する
+     * 番兵を指しているときは最小の要素の前に挿入する
+     * key が現在の要素のキーより大きく、次の要素のキーより小さくない場合は panic する
+     */
+    pub fn insert_after(&mut self, key: K, value: V) {
+        assert!(
+            self.key().is_none_or(|cur| *cur < key)
+                && self.peek_next().is_none_or(|(next, _)| key < *next),
+            "key must lie strictly between the current and the next key"
+        );
+        let ghost = self.is_ghost();
+        self.root.insert(key, value);
+        if ghost {
+            self.index += 1;
+        }
+        self.seek();
+    }
+}
+impl<K, V> CursorMut<'_, K, V> {
+    // index 番目の要素までの向きを根から作り直す
+    fn seek(&mut self) {
+        self.path.clear();
+        let mut rest = self.index;
+        let mut node = self.root.0.as_deref().filter(|root| rest < root.size);
+        while let Some(cur) = node {
+            node = match cur.left.size().cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= cur.left.size() + 1;
+                    self.path.push(ThreeWay::Right);
+                    cur.right.0.as_deref()
+                }
+                std::cmp::Ordering::Greater => {
+                    self.path.push(ThreeWay::Left);
+                    cur.left.0.as_deref()
+                }
+                std::cmp::Ordering::Equal => None,
+            };
+        }
+    }
+
+    fn is_ghost(&self) -> bool {
+        self.index >= self.root.size()
+    }
+
+    fn current(&self) -> Option<&Node<K, V>> {
+        if self.is_ghost() {
+            return None;
+        }
+        follow(self.root.0.as_deref(), &self.path)
+    }
+
+    // node から dir の向きの子をたどれるだけたどり、進んだ向きを path に積む
+    fn descend(path: &mut Vec<ThreeWay>, mut node: &Node<K, V>, dir: ThreeWay) {
+        while let Some(child) = node.child(dir) {
+            path.push(dir);
+            node = child;
+        }
+    }
+
+    // dir の向きの子として登ってくるまで戻り、見つかったかどうかを返す
+    // 根まで戻っても見つからなければ向きは空になる
+    fn ascend(&mut self, dir: ThreeWay) -> bool {
+        while let Some(last) = self.path.pop() {
+            if last == dir {
+                return true;
+            }
+        }
+        false
+    }
+
+    /**
+     * 現在の要素の昇順での位置を返す
+     * 番兵を指しているときは要素の個数を返す
+     */
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn key(&self) -> Option<&K> {
+        self.current().map(|node| &node.key)
+    }
+
+    pub fn value(&self) -> Option<&V> {
+        self.current().map(|node| &node.value)
+    }
+
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        if self.is_ghost() {
+            return None;
+        }
+        let mut node = self.root.0.as_deref_mut()?;
+        for &dir in &self.path {
+            node = node.child_mut(dir).as_deref_mut()?;
+        }
+        Some(&mut node.value)
+    }
+
+    pub fn move_next(&mut self) {
+        if self.is_ghost() {
+            self.index = 0;
+            self.path.clear();
+            if let Some(root) = self.root.0.as_deref() {
+                Self::descend(&mut self.path, root, ThreeWay::Left);
+            }
+            return;
+        }
+        self.index += 1;
+        let node = follow(self.root.0.as_deref(), &self.path).unwrap();
+        match node.right.0.as_deref() {
+            Some(right) => {
+                self.path.push(ThreeWay::Right);
+                Self::descend(&mut self.path, right, ThreeWay::Left);
+            }
+            None => {
+                // 最大の要素から進んだ場合は向きが空になり、index は要素の個数に等しくなる
+                self.ascend(ThreeWay::Left);
+            }
+        }
+    }
+
+    pub fn move_prev(&mut self) {
+        if self.is_ghost() {
+            self.path.clear();
+            if let Some(root) = self.root.0.as_deref() {
+                Self::descend(&mut self.path, root, ThreeWay::Right);
+            }
+            self.index = self.root.size().saturating_sub(1);
+            return;
+        }
+        let node = follow(self.root.0.as_deref(), &self.path).unwrap();
+        match node.left.0.as_deref() {
+            Some(left) => {
+                self.index -= 1;
+                self.path.push(ThreeWay::Left);
+                Self::descend(&mut self.path, left, ThreeWay::Right);
+            }
+            None => {
+                self.index = if self.ascend(ThreeWay::Right) {
+                    self.index - 1
+                } else {
+                    self.root.size()
+                };
+            }
+        }
+    }
+
+    /**
+     * カーソルを動かさずに次の要素を返す
+     */
+    pub fn peek_next(&self) -> Option<(&K, &V)> {
+        let node = match self.current() {
+            Some(node) => node,
+            None => return leftmost(self.root.0.as_deref()?),
+        };
+        if let Some(right) = node.right.0.as_deref() {
+            return leftmost(right);
+        }
+        // 最後に左へ進んだノードが次の要素
+        let depth = self.path.iter().rposition(|&dir| dir == ThreeWay::Left)?;
+        follow(self.root.0.as_deref(), &self.path[..depth]).map(|node| (&node.key, &node.value))
+    }
+
+    /**
+     * カーソルを動かさずに前の要素を返す
+     */
+    pub fn peek_prev(&self) -> Option<(&K, &V)> {
+        let node = match self.current() {
+            Some(node) => node,
+            None => return rightmost(self.root.0.as_deref()?),
+        };
+        if let Some(left) = node.left.0.as_deref() {
+            return rightmost(left);
+        }
+        // 最後に右へ進んだノードが前の要素
+        let depth = self.path.iter().rposition(|&dir| dir == ThreeWay::Right)?;
+        follow(self.root.0.as_deref(), &self.path[..depth]).map(|node| (&node.key, &node.value))
+    }
+}
+
+// node から path の向きに順にたどったノード
+fn follow<'a, K, V>(mut node: Option<&'a Node<K, V>>, path: &[ThreeWay]) -> Option<&'a Node<K, V>> {
+    for &dir in path {
+        node = node?.child(dir);
+    }
+    node
+}
+
+/**
+ * AVLTreeSet の要素を指すカーソル
+ */
+pub struct SetCursor<'a, K>(Cursor<'a, K, ()>);
+impl<'a, K> SetCursor<'a, K> {
+    pub fn index(&self) -> usize {
+        self.0.index()
+    }
+
+    pub fn key(&self) -> Option<&'a K> {
+        self.0.key()
+    }
+
+    pub fn move_next(&mut self) {
+        self.0.move_next()
+    }
+
+    pub fn move_prev(&mut self) {
+        self.0.move_prev()
+    }
+
+    pub fn peek_next(&self) -> Option<&'a K> {
+        self.0.peek_next().map(|(k, _)| k)
+    }
+
+    pub fn peek_prev(&self) -> Option<&'a K> {
+        self.0.peek_prev().map(|(k, _)| k)
+    }
+}
+
+/**
+ * AVLTreeSet の要素を指し、木を書き換えられるカーソル
+ */
+pub struct SetCursorMut<'a, K>(CursorMut<'a, K, ()>);
+impl<'a, K: Ord> SetCursorMut<'a, K> {
+    pub fn index(&self) -> usize {
+        self.0.index()
+    }
+
+    pub fn key(&self) -> Option<&K> {
+        self.0.key()
+    }
+
+    pub fn move_next(&mut self) {
+        self.0.move_next()
+    }
+
+    pub fn move_prev(&mut self) {
+        self.0.move_prev()
+    }
+
+    pub fn peek_next(&self) -> Option<&K> {
+        self.0.peek_next().map(|(k, _)| k)
+    }
+
+    pub fn peek_prev(&self) -> Option<&K> {
+        self.0.peek_prev().map(|(k, _)| k)
+    }
+
+    pub fn remove_current(&mut self) -> Option<K> {
+        self.0.remove_current().map(|(k, _)| k)
+    }
+
+    pub fn insert_before(&mut self, key: K) {
+        self.0.insert_before(key, ())
+    }
+
+    pub fn insert_after(&mut self, key: K) {
+        self.0.insert_after(key, ())
+    }
+}
@@ -0,0 +1,19 @@
+use std::ops::Bound;
+
+// key が区間の下端 bound を満たすか
+pub(crate) fn satisfies_lower<K: Ord>(key: &K, bound: Bound<&K>) -> bool {
+    match bound {
+        Bound::Included(lower) => key >= lower,
+        Bound::Excluded(lower) => key > lower,
+        Bound::Unbounded => true,
+    }
+}
+
+// key が区間の上端 bound を満たすか
+pub(crate) fn satisfies_upper<K: Ord>(key: &K, bound: Bound<&K>) -> bool {
+    match bound {
+        Bound::Included(upper) => key <= upper,
+        Bound::Excluded(upper) => key < upper,
+        Bound::Unbounded => true,
+    }
+}
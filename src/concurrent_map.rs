@@ -0,0 +1,177 @@
+use std::ops::{Bound, RangeBounds};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::avl_tree::{AVLTreeMap, Cursor};
+use crate::bound::satisfies_upper;
+
+/**
+ * 複数のスレッドから共有して読み書きできる順序付きマップ
+ * キーの範囲ごとに AVLTreeMap を分け、それぞれを RwLock で守る
+ * 1 つのキーに対する操作はそのキーを含む区間のロックだけを取るので、別の区間への書き込みとは並行に進む
+ * 複数の区間にまたがる読み出しは区間の昇順に読み取りロックを取り、最後まで保持する
+ * 書き込みは 1 つの区間のロックしか取らないため、デッドロックせず、読み出した結果はある時点の状態と一致する
+ */
+pub struct ConcurrentAVLMap<K: Ord, V> {
+    // shards[i] は boundaries[i - 1] 以上 boundaries[i] 未満のキーを持つ
+    boundaries: Vec<K>,
+    shards: Vec<RwLock<AVLTreeMap<K, V>>>,
+}
+impl<K: Ord, V> ConcurrentAVLMap<K, V> {
+    /**
+     * 区間に分けない (全体を 1 つのロックで守る) マップを作る
+     */
+    pub fn new() -> Self {
+        Self::with_boundaries(Vec::new())
+    }
+
+    /**
+     * boundaries の各キーを区間の始まりとして、boundaries.len() + 1 個の区間に分けたマップを作る
+     * boundaries が狭義単調増加でない場合は panic する
+     */
+    pub fn with_boundaries(boundaries: Vec<K>) -> Self {
+        assert!(
+            boundaries.windows(2).all(|w| w[0] < w[1]),
+            "boundaries must be strictly increasing"
+        );
+        let shards = (0..=boundaries.len())
+            .map(|_| RwLock::new(AVLTreeMap::new()))
+            .collect();
+        ConcurrentAVLMap { boundaries, shards }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    // key を含む区間の番号
+    fn shard_of(&self, key: &K) -> usize {
+        self.boundaries.partition_point(|boundary| boundary <= key)
+    }
+
+    fn read(&self, shard: usize) -> RwLockReadGuard<'_, AVLTreeMap<K, V>> {
+        self.shards[shard].read().expect("shard lock poisoned")
+    }
+
+    fn write(&self, shard: usize) -> RwLockWriteGuard<'_, AVLTreeMap<K, V>> {
+        self.shards[shard].write().expect("shard lock poisoned")
+    }
+
+    // first 番目から last 番目までの区間の読み取りロックを昇順に取る
+    fn read_all(&self, first: usize, last: usize) -> Vec<RwLockReadGuard<'_, AVLTreeMap<K, V>>> {
+        (first..=last).map(|shard| self.read(shard)).collect()
+    }
+
+    /**
+     * key がそれまで存在しなかった場合は true を返す
+     */
+    pub fn insert(&self, key: K, value: V) -> bool {
+        self.write(self.shard_of(&key)).insert(key, value)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.write(self.shard_of(key)).remove(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.read(self.shard_of(key)).get(key).is_some()
+    }
+
+    /**
+     * 要素の個数を返す
+     * すべての区間の読み取りロックを同時に取るので、ある時点での個数と一致する
+     */
+    pub fn size(&self) -> usize {
+        self.read_all(0, self.shards.len() - 1)
+            .iter()
+            .map(|shard| shard.size())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    /**
+     * key より小さいキーの個数を返す
+     * key を含む区間とそれより前の区間の読み取りロックを同時に取って数える
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        let shards = self.read_all(0, self.shard_of(key));
+        let (last, init) = shards.split_last().unwrap();
+        init.iter().map(|shard| shard.size()).sum::<usize>() + last.order_of_key(key)
+    }
+
+    /**
+     * 区間ごとの木をつなげて 1 つの AVLTreeMap にする
+     */
+    pub fn into_inner(self) -> AVLTreeMap<K, V> {
+        let mut map = AVLTreeMap::new();
+        for shard in self.shards {
+            let shard = shard.into_inner().expect("shard lock poisoned");
+            // 区間どうしはキーが重ならないので f は呼ばれない
+            map.merge_with(shard, |_, value, _| value);
+        }
+        map
+    }
+}
+impl<K: Ord, V: Clone> ConcurrentAVLMap<K, V> {
+    /**
+     * key に対応する値の複製を返す
+     */
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.read(self.shard_of(key)).get(key).cloned()
+    }
+}
+impl<K: Ord + Clone, V: Clone> ConcurrentAVLMap<K, V> {
+    /**
+     * キーが range に含まれる要素の複製をキーの昇順に返す
+     * range と重なる区間の読み取りロックを同時に取るので、ある時点での内容と一致する
+     */
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let (start, end) = (range.start_bound(), range.end_bound());
+        let first = match start {
+            Bound::Included(key) | Bound::Excluded(key) => self.shard_of(key),
+            Bound::Unbounded => 0,
+        };
+        let last = match end {
+            Bound::Included(key) | Bound::Excluded(key) => self.shard_of(key),
+            Bound::Unbounded => self.shards.len() - 1,
+        };
+        if first > last {
+            return Vec::new();
+        }
+        let shards = self.read_all(first, last);
+        let mut entries = Vec::new();
+        for shard in &shards {
+            let mut cursor = lower_cursor(shard, start);
+            while let (Some(key), Some(value)) = (cursor.key(), cursor.value()) {
+                if !satisfies_upper(key, end) {
+                    break;
+                }
+                entries.push((key.clone(), value.clone()));
+                cursor.move_next();
+            }
+        }
+        entries
+    }
+}
+impl<K: Ord, V> Default for ConcurrentAVLMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// bound を満たす最小の要素を指すカーソル
+fn lower_cursor<'a, K: Ord, V>(map: &'a AVLTreeMap<K, V>, bound: Bound<&K>) -> Cursor<'a, K, V> {
+    match bound {
+        Bound::Included(lower) => map.lower_bound(lower),
+        Bound::Excluded(lower) => {
+            let mut cursor = map.lower_bound(lower);
+            if cursor.key() == Some(lower) {
+                cursor.move_next();
+            }
+            cursor
+        }
+        Bound::Unbounded => map.cursor_at(0),
+    }
+}
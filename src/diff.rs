@@ -0,0 +1,126 @@
+use crate::node::NodeTrait;
+
+/**
+ * 2 つの木の差分の 1 項目
+ * Removed は古い木にだけ、Added は新しい木にだけあるキーを表す
+ */
+#[derive(PartialEq, Eq, Debug)]
+pub enum DiffItem<'a, K, V> {
+    Added(&'a K, &'a V),
+    Removed(&'a K, &'a V),
+    Changed { key: &'a K, old: &'a V, new: &'a V },
+}
+
+/**
+ * キーと値を持つノード
+ */
+pub(crate) trait EntryNode: NodeTrait {
+    type Key: Ord;
+    type Value;
+    fn entry(&self) -> (&Self::Key, &Self::Value);
+}
+
+enum Pending<'a, N> {
+    // まだ展開していない部分木
+    Subtree(&'a N),
+    // 次に比べるノード
+    Node(&'a N),
+}
+impl<N> Clone for Pending<'_, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<N> Copy for Pending<'_, N> {}
+
+/**
+ * 2 つの木を間順に並べて突き合わせ、差分をキーの昇順に返すイテレータ
+ * 各木について残りの要素を、未展開の部分木とノードのスタックとして持つ
+ * 両方のスタックの先頭が同じ部分木を指している場合は、中を見ずにまとめて読み飛ばす
+ * そのため全体で O(n + m) で、版の間で部分木を共有している場合は共有していない部分だけをたどる
+ */
+pub(crate) struct Diff<'a, N> {
+    old: Vec<Pending<'a, N>>,
+    new: Vec<Pending<'a, N>>,
+}
+impl<'a, N: EntryNode> Diff<'a, N> {
+    pub(crate) fn new(old: Option<&'a N>, new: Option<&'a N>) -> Self {
+        Diff {
+            old: old.into_iter().map(Pending::Subtree).collect(),
+            new: new.into_iter().map(Pending::Subtree).collect(),
+        }
+    }
+}
+
+// 先頭の部分木を右の部分木、根、左の部分木に分けて積み直す
+fn expand<N: NodeTrait>(stack: &mut Vec<Pending<'_, N>>) {
+    if let Some(Pending::Subtree(node)) = stack.pop() {
+        stack.extend(node.right().map(Pending::Subtree));
+        stack.push(Pending::Node(node));
+        stack.extend(node.left().map(Pending::Subtree));
+    }
+}
+
+impl<'a, N> Iterator for Diff<'a, N>
+where
+    N: EntryNode,
+    N::Value: PartialEq,
+{
+    type Item = DiffItem<'a, N::Key, N::Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match (self.old.last().copied(), self.new.last().copied()) {
+                (None, None) => return None,
+                (Some(Pending::Subtree(old)), Some(Pending::Subtree(new))) => {
+                    if std::ptr::eq(old, new) {
+                        self.old.pop();
+                        self.new.pop();
+                    } else if old.size() >= new.size() {
+                        // 大きい方の中に小さい方と共有している部分木があり得るので、大きい方から展開する
+                        expand(&mut self.old);
+                    } else {
+                        expand(&mut self.new);
+                    }
+                }
+                (Some(Pending::Subtree(_)), _) => expand(&mut self.old),
+                (_, Some(Pending::Subtree(_))) => expand(&mut self.new),
+                (Some(Pending::Node(old)), None) => {
+                    self.old.pop();
+                    let (key, value) = old.entry();
+                    return Some(DiffItem::Removed(key, value));
+                }
+                (None, Some(Pending::Node(new))) => {
+                    self.new.pop();
+                    let (key, value) = new.entry();
+                    return Some(DiffItem::Added(key, value));
+                }
+                (Some(Pending::Node(old)), Some(Pending::Node(new))) => {
+                    let (old_key, old_value) = old.entry();
+                    let (new_key, new_value) = new.entry();
+                    match old_key.cmp(new_key) {
+                        std::cmp::Ordering::Less => {
+                            self.old.pop();
+                            return Some(DiffItem::Removed(old_key, old_value));
+                        }
+                        std::cmp::Ordering::Greater => {
+                            self.new.pop();
+                            return Some(DiffItem::Added(new_key, new_value));
+                        }
+                        std::cmp::Ordering::Equal => {
+                            self.old.pop();
+                            self.new.pop();
+                            if !std::ptr::eq(old, new) && old_value != new_value {
+                                return Some(DiffItem::Changed {
+                                    key: new_key,
+                                    old: old_value,
+                                    new: new_value,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt::Write;
+
+use crate::node::NodeTrait;
+
+// DOT の二重引用符で囲んだ文字列の中で使えるようにする
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// 部分木を out に書き出し、その根の DOT 上の名前を返す
+fn write_node<N: NodeTrait>(
+    node: Option<&N>,
+    count: &mut usize,
+    out: &mut String,
+    label: &impl Fn(&N) -> Vec<String>,
+) -> String {
+    let id = *count;
+    *count += 1;
+    let node = match node {
+        Some(node) => node,
+        None => {
+            let name = format!("null{id}");
+            writeln!(out, "    {name} [shape=point];").unwrap();
+            return name;
+        }
+    };
+
+    let name = format!("n{id}");
+    let lines = label(node)
+        .iter()
+        .map(|line| escape(line))
+        .collect::<Vec<_>>();
+    writeln!(out, "    {name} [label=\"{}\"];", lines.join("\\n")).unwrap();
+    let left = write_node(node.left(), count, out, label);
+    let right = write_node(node.right(), count, out, label);
+    writeln!(out, "    {name} -> {left} [label=\"L\"];").unwrap();
+    writeln!(out, "    {name} -> {right} [label=\"R\"];").unwrap();
+    name
+}
+
+/**
+ * 木の形を Graphviz の DOT 形式で返す
+ * 各ノードには label が返す行を並べて表示し、存在しない子は点で表す
+ */
+pub(crate) fn to_dot<N: NodeTrait>(root: Option<&N>, label: impl Fn(&N) -> Vec<String>) -> String {
+    let mut out = String::from("digraph {\n    node [shape=box];\n");
+    if root.is_some() {
+        write_node(root, &mut 0, &mut out, &label);
+    }
+    out.push_str("}\n");
+    out
+}
@@ -0,0 +1,292 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::avl_tree::AVLTreeMap;
+use crate::snapshot::SnapshotError;
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+const WAL_FILE: &str = "wal";
+
+const OP_INSERT: u8 = 1;
+const OP_REMOVE: u8 = 2;
+
+// CRC-32 (IEEE 802.3)
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+enum Record {
+    Insert(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+}
+
+// ログの 1 レコードは、中身の長さ (u32)、中身の CRC-32 (u32)、中身の順に並ぶ
+// 中身は操作の種類 (u8)、キーの長さ (u32)、キー、(挿入なら) 値からなる
+// 長さが u32 に収まらない場合は InvalidInput のエラーを返す
+fn encode_record(op: u8, key: &[u8], value: &[u8]) -> io::Result<Vec<u8>> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "record too large for the log");
+    let payload_len = 5usize
+        .checked_add(key.len())
+        .and_then(|len| len.checked_add(value.len()))
+        .and_then(|len| u32::try_from(len).ok())
+        .ok_or_else(too_large)?;
+
+    let mut payload = Vec::with_capacity(payload_len as usize);
+    payload.push(op);
+    // キーは中身より短いので、中身の長さが収まればキーの長さも収まる
+    payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    payload.extend_from_slice(key);
+    payload.extend_from_slice(value);
+
+    let mut record = Vec::with_capacity(8 + payload.len());
+    record.extend_from_slice(&payload_len.to_le_bytes());
+    record.extend_from_slice(&crc32(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+fn decode_payload(payload: &[u8]) -> Option<Record> {
+    let (&op, rest) = payload.split_first()?;
+    let key_len = u32::from_le_bytes(rest.get(..4)?.try_into().unwrap()) as usize;
+    let rest = &rest[4..];
+    let key = rest.get(..key_len)?.to_vec();
+    let value = &rest[key_len..];
+    match op {
+        OP_INSERT => Some(Record::Insert(key, value.to_vec())),
+        OP_REMOVE if value.is_empty() => Some(Record::Remove(key)),
+        _ => None,
+    }
+}
+
+// 読めるだけ読む
+// 途中でファイルが終わった場合は読めたバイト数を返す
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+// 正しく読めたレコードをすべて map に適用し、そのレコードの個数と末尾の位置を返す
+// 最後のレコードが書き込みの途中で途切れていたり壊れていたりした場合は、その手前までを有効とする
+fn replay<R: Read>(
+    reader: &mut R,
+    map: &mut AVLTreeMap<Vec<u8>, Vec<u8>>,
+) -> io::Result<(usize, u64)> {
+    let mut count = 0;
+    let mut valid_len = 0;
+    loop {
+        let mut header = [0; 8];
+        if read_up_to(reader, &mut header)? < header.len() {
+            break;
+        }
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let mut payload = Vec::new();
+        if reader.take(len as u64).read_to_end(&mut payload)? < len || crc32(&payload) != crc {
+            break;
+        }
+        match decode_payload(&payload) {
+            Some(Record::Insert(key, value)) => {
+                map.insert(key, value);
+            }
+            Some(Record::Remove(key)) => {
+                map.remove(&key);
+            }
+            None => break,
+        }
+        count += 1;
+        valid_len += (header.len() + len) as u64;
+    }
+    Ok((count, valid_len))
+}
+
+/**
+ * 先行書き込みログ (write-ahead log) で永続化する AVLTreeMap<Vec<u8>, Vec<u8>>
+ * 1 つのディレクトリにスナップショットとログを置き、挿入と削除は木に反映する前にログへ追記する
+ * open ではスナップショットを読み込んでからログを再生し、途切れた末尾のレコードは捨てる
+ * checkpoint でスナップショットを書き直すと、ログは空になる
+ */
+pub struct DurableMap {
+    dir: PathBuf,
+    map: AVLTreeMap<Vec<u8>, Vec<u8>>,
+    wal: File,
+    // ログの有効な末尾の位置
+    wal_len: u64,
+    // 最後のチェックポイントより後にログへ書いたレコードの個数
+    log_records: usize,
+    checkpoint_every: Option<usize>,
+}
+impl DurableMap {
+    /**
+     * dir にある木を開く
+     * dir が存在しない場合は作り、空の木として開く
+     */
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, SnapshotError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut map = match File::open(dir.join(SNAPSHOT_FILE)) {
+            Ok(file) => AVLTreeMap::read_snapshot(BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => AVLTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut wal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(WAL_FILE))?;
+        let (log_records, valid_len) = replay(&mut BufReader::new(&mut wal), &mut map)?;
+        // 途切れたレコードの後ろに追記しないよう、有効な末尾で切り詰める
+        if wal.metadata()?.len() != valid_len {
+            wal.set_len(valid_len)?;
+            wal.sync_all()?;
+        }
+
+        Ok(DurableMap {
+            dir,
+            map,
+            wal,
+            wal_len: valid_len,
+            log_records,
+            checkpoint_every: None,
+        })
+    }
+
+    /**
+     * ログのレコードが records 個たまるたびに自動で checkpoint する
+     * None なら自動では行わない
+     */
+    pub fn set_checkpoint_every(&mut self, records: Option<usize>) {
+        self.checkpoint_every = records;
+    }
+
+    // 書き込みに失敗した場合は、途中まで書いたレコードの後ろに次のレコードを書かないよう切り詰める
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        if let Err(err) = self.wal.write_all(record) {
+            let _ = self.wal.set_len(self.wal_len);
+            return Err(err);
+        }
+        self.wal_len += record.len() as u64;
+        self.log_records += 1;
+        Ok(())
+    }
+
+    fn checkpoint_if_needed(&mut self) -> io::Result<()> {
+        match self.checkpoint_every {
+            Some(every) if self.log_records >= every => self.checkpoint(),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * ログに追記してから key に value を対応させる
+     * key がそれまで存在しなかった場合は true を返す
+     * キーと値の長さの和がおよそ 4 GiB を超える場合は InvalidInput のエラーを返し、何も変更しない
+     * 自動の checkpoint に失敗した場合はエラーを返すが、操作はログと木に反映されている
+     */
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> io::Result<bool> {
+        self.append(&encode_record(OP_INSERT, &key, &value)?)?;
+        let inserted = self.map.insert(key, value);
+        self.checkpoint_if_needed()?;
+        Ok(inserted)
+    }
+
+    /**
+     * key が存在する場合は、ログに追記してから取り除く
+     * 自動の checkpoint に失敗した場合はエラーを返すが、操作はログと木に反映されている
+     */
+    pub fn remove(&mut self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        // 探索を 1 回で済ませるため先に木から取り除き、ログへの追記に失敗した場合は元に戻す
+        let removed = match self.map.remove_borrowed(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        if let Err(err) = encode_record(OP_REMOVE, key, &[]).and_then(|record| self.append(&record))
+        {
+            self.map.insert(key.to_vec(), removed);
+            return Err(err);
+        }
+        self.checkpoint_if_needed()?;
+        Ok(Some(removed))
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
+        self.map.get_borrowed(key)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn size(&self) -> usize {
+        self.map.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /**
+     * 読み出し用に中の木を返す
+     */
+    pub fn map(&self) -> &AVLTreeMap<Vec<u8>, Vec<u8>> {
+        &self.map
+    }
+
+    /**
+     * 最後のチェックポイントより後にログへ書いたレコードの個数を返す
+     */
+    pub fn log_records(&self) -> usize {
+        self.log_records
+    }
+
+    /**
+     * ログに書いた内容をディスクまで書き出す
+     */
+    pub fn sync(&self) -> io::Result<()> {
+        self.wal.sync_data()
+    }
+
+    /**
+     * 現在の木をスナップショットに書き、ログを空にする
+     * スナップショットは一時ファイルに書いてから置き換えるので、途中で止まっても古いスナップショットとログが残る
+     * 置き換えた後でログを空にする前に止まった場合も、ログの再生は同じ結果になる
+     */
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        self.map.write_snapshot(&mut writer)?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+        #[cfg(unix)]
+        File::open(&self.dir)?.sync_all()?;
+
+        self.wal.set_len(0)?;
+        self.wal.sync_all()?;
+        self.wal_len = 0;
+        self.log_records = 0;
+        Ok(())
+    }
+}
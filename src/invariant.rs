@@ -0,0 +1,202 @@
+use crate::avl_tree::{AVLNode, ThreeWay};
+use crate::node::NodeTrait;
+use crate::treap::TreapNode;
+
+/**
+ * 木の不変条件のうち、破られていたもの
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum ViolationKind {
+    // キーが二分探索木の順序になっていない
+    Order,
+    // size が子の size の和 + 1 と一致しない
+    Size {
+        expected: usize,
+        actual: usize,
+    },
+    // 左右の部分木の高さの差が 1 を超えているか、平衡状態が実際の高さと一致しない
+    Balance {
+        state: ThreeWay,
+        left_height: usize,
+        right_height: usize,
+    },
+    // 子の priority が親の priority より大きい
+    HeapOrder,
+}
+
+/**
+ * validate で見つかった不変条件の違反
+ * path は根から違反のあったノードまでの向き (ThreeWay::Left か ThreeWay::Right) の列で、空なら根を表す
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct InvariantViolation {
+    path: Vec<ThreeWay>,
+    kind: ViolationKind,
+}
+impl InvariantViolation {
+    pub(crate) fn new(path: &[ThreeWay], kind: ViolationKind) -> Self {
+        InvariantViolation {
+            path: path.to_vec(),
+            kind,
+        }
+    }
+
+    pub fn path(&self) -> &[ThreeWay] {
+        &self.path
+    }
+
+    pub fn kind(&self) -> &ViolationKind {
+        &self.kind
+    }
+}
+impl std::fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ViolationKind::Order => write!(f, "key out of order")?,
+            ViolationKind::Size { expected, actual } => {
+                write!(f, "size is {actual} but subtree has {expected} nodes")?
+            }
+            ViolationKind::Balance {
+                state,
+                left_height,
+                right_height,
+            } => write!(
+                f,
+                "state is {state:?} but left height is {left_height} and right height is {right_height}"
+            )?,
+            ViolationKind::HeapOrder => write!(f, "priority is larger than parent's")?,
+        }
+        write!(f, " at root")?;
+        for dir in &self.path {
+            match dir {
+                ThreeWay::Left => write!(f, ".left")?,
+                _ => write!(f, ".right")?,
+            }
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for InvariantViolation {}
+
+// 以下の検査は、ノードの持ち方が違う ArenaAVLTreeMap の validate からも使う
+pub(crate) fn check_order<K: Ord>(
+    key: &K,
+    lower: Option<&K>,
+    upper: Option<&K>,
+    path: &[ThreeWay],
+) -> Result<(), InvariantViolation> {
+    if lower.is_some_and(|lower| key <= lower) || upper.is_some_and(|upper| key >= upper) {
+        return Err(InvariantViolation::new(path, ViolationKind::Order));
+    }
+    Ok(())
+}
+
+// actual は size、expected は子の size の和 + 1
+pub(crate) fn check_size_sum(
+    actual: usize,
+    expected: usize,
+    path: &[ThreeWay],
+) -> Result<(), InvariantViolation> {
+    if actual != expected {
+        return Err(InvariantViolation::new(
+            path,
+            ViolationKind::Size { expected, actual },
+        ));
+    }
+    Ok(())
+}
+
+// 各ノードが自分自身の 1 個だけを数えている木を前提とする
+fn check_size<N: NodeTrait>(node: &N, path: &[ThreeWay]) -> Result<(), InvariantViolation> {
+    let expected = node.left().map_or(0, |n| n.size()) + node.right().map_or(0, |n| n.size()) + 1;
+    check_size_sum(node.size(), expected, path)
+}
+
+// 平衡状態が左右の部分木の実際の高さと一致するかを確かめ、部分木の高さを返す
+pub(crate) fn check_balance(
+    state: ThreeWay,
+    left_height: usize,
+    right_height: usize,
+    path: &[ThreeWay],
+) -> Result<usize, InvariantViolation> {
+    let actual = match left_height.cmp(&right_height) {
+        std::cmp::Ordering::Greater => ThreeWay::Left,
+        std::cmp::Ordering::Less => ThreeWay::Right,
+        std::cmp::Ordering::Equal => ThreeWay::Equal,
+    };
+    if left_height.abs_diff(right_height) > 1 || state != actual {
+        return Err(InvariantViolation::new(
+            path,
+            ViolationKind::Balance {
+                state,
+                left_height,
+                right_height,
+            },
+        ));
+    }
+    Ok(left_height.max(right_height) + 1)
+}
+
+// 部分木の高さを返す
+fn validate_avl_rec<'a, N: AVLNode>(
+    node: Option<&'a N>,
+    lower: Option<&'a N::Key>,
+    upper: Option<&'a N::Key>,
+    path: &mut Vec<ThreeWay>,
+) -> Result<usize, InvariantViolation> {
+    let node = match node {
+        Some(node) => node,
+        None => return Ok(0),
+    };
+    check_order(node.key(), lower, upper, path)?;
+
+    path.push(ThreeWay::Left);
+    let left_height = validate_avl_rec(node.left(), lower, Some(node.key()), path)?;
+    path.pop();
+    path.push(ThreeWay::Right);
+    let right_height = validate_avl_rec(node.right(), Some(node.key()), upper, path)?;
+    path.pop();
+
+    check_size(node, path)?;
+    check_balance(node.state(), left_height, right_height, path)
+}
+
+/**
+ * 二分探索木の順序、size、平衡状態と実際の高さの一致を確かめる
+ */
+pub(crate) fn validate_avl<N: AVLNode>(root: Option<&N>) -> Result<(), InvariantViolation> {
+    validate_avl_rec(root, None, None, &mut Vec::new()).map(|_| ())
+}
+
+fn validate_treap_rec<'a, N: TreapNode>(
+    node: Option<&'a N>,
+    lower: Option<&'a N::Key>,
+    upper: Option<&'a N::Key>,
+    parent_priority: f64,
+    path: &mut Vec<ThreeWay>,
+) -> Result<(), InvariantViolation> {
+    let node = match node {
+        Some(node) => node,
+        None => return Ok(()),
+    };
+    check_order(node.key(), lower, upper, path)?;
+    if node.priority() > parent_priority {
+        return Err(InvariantViolation::new(path, ViolationKind::HeapOrder));
+    }
+
+    path.push(ThreeWay::Left);
+    validate_treap_rec(node.left(), lower, Some(node.key()), node.priority(), path)?;
+    path.pop();
+    path.push(ThreeWay::Right);
+    validate_treap_rec(node.right(), Some(node.key()), upper, node.priority(), path)?;
+    path.pop();
+
+    check_size(node, path)
+}
+
+/**
+ * 二分探索木の順序、size、priority のヒープ順序を確かめる
+ */
+pub(crate) fn validate_treap<N: TreapNode>(root: Option<&N>) -> Result<(), InvariantViolation> {
+    validate_treap_rec(root, None, None, f64::INFINITY, &mut Vec::new())
+}
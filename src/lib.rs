@@ -1,4 +1,26 @@
+pub mod arena_avl_tree;
+pub mod avl_multimap;
+pub mod avl_multiset;
 pub mod avl_tree;
+mod bound;
+pub mod concurrent_map;
+pub mod diff;
+mod dot;
+pub mod durable_map;
+pub mod invariant;
+pub mod monoid_avl_tree;
 mod node;
+pub mod observed_map;
+pub mod persistent_avl_tree;
+pub mod persistent_treap;
+mod pretty;
+#[cfg(feature = "rayon")]
+pub mod rayon_support;
+#[cfg(feature = "serde")]
+pub mod serde_support;
+pub mod snapshot;
+pub mod stats;
+pub mod transaction;
 pub mod treap;
 pub mod tree_trait;
+pub mod weighted_avl_tree;
@@ -0,0 +1,1047 @@
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+use crate::avl_tree::{insert_rec, remove_rec, AVLNode, ThreeWay};
+use crate::bound::{satisfies_lower, satisfies_upper};
+use crate::node::NodeTrait;
+use crate::pretty::fmt_tree;
+use crate::stats::{avl_tree_stats, TreeStats};
+
+/**
+ * 部分木の集約値を定めるモノイド
+ * combine は結合的で、identity はその単位元でなければならない
+ */
+pub trait Monoid<K, V> {
+    type Aggregate: Clone;
+    fn identity() -> Self::Aggregate;
+    fn combine(left: &Self::Aggregate, right: &Self::Aggregate) -> Self::Aggregate;
+    fn lift(key: &K, value: &V) -> Self::Aggregate;
+}
+
+/**
+ * 値に作用する写像とその集約値への作用を定める
+ * compose(f, g) は g を作用させたあとに f を作用させる写像を表す
+ * map_aggregate は size 個の要素の集約値に f を作用させた結果が、
+ * 各要素に apply で f を作用させてから集約した結果と一致しなければならない
+ */
+pub trait MapMonoid<K, V>: Monoid<K, V> {
+    type Map: Clone;
+    fn compose(f: &Self::Map, g: &Self::Map) -> Self::Map;
+    fn apply(f: &Self::Map, key: &K, value: &mut V);
+    fn map_aggregate(f: &Self::Map, aggregate: &Self::Aggregate, size: usize) -> Self::Aggregate;
+}
+
+// 作用を持たないモノイドを MapMonoid として扱うためのラッパー
+struct WithoutMap<M>(PhantomData<M>);
+impl<K, V, M: Monoid<K, V>> Monoid<K, V> for WithoutMap<M> {
+    type Aggregate = M::Aggregate;
+
+    fn identity() -> Self::Aggregate {
+        M::identity()
+    }
+
+    fn combine(left: &Self::Aggregate, right: &Self::Aggregate) -> Self::Aggregate {
+        M::combine(left, right)
+    }
+
+    fn lift(key: &K, value: &V) -> Self::Aggregate {
+        M::lift(key, value)
+    }
+}
+impl<K, V, M: Monoid<K, V>> MapMonoid<K, V> for WithoutMap<M> {
+    type Map = ();
+
+    fn compose(_f: &(), _g: &()) {}
+
+    fn apply(_f: &(), _key: &K, _value: &mut V) {}
+
+    fn map_aggregate(_f: &(), aggregate: &Self::Aggregate, _size: usize) -> Self::Aggregate {
+        aggregate.clone()
+    }
+}
+
+// 祖先から伝わる作用 outer と、それより前に溜められた作用 inner を合成する
+fn compose_tag<K, V, M: MapMonoid<K, V>>(
+    outer: &Option<M::Map>,
+    inner: &Option<M::Map>,
+) -> Option<M::Map> {
+    match (outer, inner) {
+        (Some(f), Some(g)) => Some(M::compose(f, g)),
+        (Some(f), None) => Some(f.clone()),
+        (None, g) => g.clone(),
+    }
+}
+
+type NodePtrInner<K, V, M> = Option<Box<Node<K, V, M>>>;
+struct NodePtr<K, V, M: MapMonoid<K, V>>(NodePtrInner<K, V, M>);
+struct Node<K, V, M: MapMonoid<K, V>> {
+    key: K,
+    value: V,
+    left: NodePtr<K, V, M>,
+    right: NodePtr<K, V, M>,
+    size: usize,
+    state: ThreeWay,
+    aggregate: M::Aggregate,
+    // 子に伝播していない作用 (このノードの値と集約値には反映済み)
+    lazy: Option<M::Map>,
+}
+
+impl<K, V, M: MapMonoid<K, V>> Node<K, V, M> {
+    fn new(key: K, value: V) -> Self {
+        let aggregate = M::lift(&key, &value);
+        Node {
+            key,
+            value,
+            left: NodePtr(None),
+            right: NodePtr(None),
+            size: 1,
+            state: ThreeWay::Equal,
+            aggregate,
+            lazy: None,
+        }
+    }
+
+    // 部分木全体に f を作用させる
+    fn apply(&mut self, f: &M::Map) {
+        M::apply(f, &self.key, &mut self.value);
+        self.aggregate = M::map_aggregate(f, &self.aggregate, self.size);
+        self.lazy = Some(match self.lazy.take() {
+            Some(g) => M::compose(f, &g),
+            None => f.clone(),
+        });
+    }
+
+    // 祖先に溜まっている作用 tag を反映した、このノード単体の集約値
+    fn lift_with(&self, tag: &Option<M::Map>) -> M::Aggregate {
+        let aggregate = M::lift(&self.key, &self.value);
+        match tag {
+            Some(f) => M::map_aggregate(f, &aggregate, 1),
+            None => aggregate,
+        }
+    }
+
+    // 祖先に溜まっている作用が tag のとき、子に伝わるべき作用
+    fn child_tag(&self, tag: &Option<M::Map>) -> Option<M::Map> {
+        compose_tag::<K, V, M>(tag, &self.lazy)
+    }
+}
+
+// fmt_tree で各ノードに表示する内容
+// 最初の行がキーで、残りの行が補足の情報になる
+fn node_label<K: std::fmt::Debug, V, M: MapMonoid<K, V>>(node: &Node<K, V, M>) -> Vec<String> {
+    vec![
+        format!("{:?}", node.key),
+        format!("size: {}", node.size),
+        format!("state: {:?}", node.state),
+    ]
+}
+
+impl<K, V, M: MapMonoid<K, V>> NodeTrait for Node<K, V, M> {
+    type Ptr = Box<Self>;
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn size_mut(&mut self) -> &mut usize {
+        &mut self.size
+    }
+
+    fn left(&self) -> Option<&Self> {
+        self.left.0.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.0.as_deref()
+    }
+
+    fn left_mut(&mut self) -> Option<&mut Self> {
+        self.left.0.as_deref_mut()
+    }
+
+    fn right_mut(&mut self) -> Option<&mut Self> {
+        self.right.0.as_deref_mut()
+    }
+
+    fn take_left(&mut self) -> Option<Box<Self>> {
+        self.left.0.take()
+    }
+
+    fn take_right(&mut self) -> Option<Box<Self>> {
+        self.right.0.take()
+    }
+
+    fn set_left(&mut self, node: Option<Box<Self>>) {
+        self.left.0 = node;
+    }
+
+    fn set_right(&mut self, node: Option<Box<Self>>) {
+        self.right.0 = node;
+    }
+
+    fn update(&mut self) {
+        self.size = self.left.size() + self.right.size() + 1;
+        self.aggregate = M::combine(
+            &M::combine(&self.left.aggregate(), &M::lift(&self.key, &self.value)),
+            &self.right.aggregate(),
+        );
+    }
+
+    fn push(&mut self) {
+        if let Some(f) = self.lazy.take() {
+            if let Some(left) = self.left.0.as_mut() {
+                left.apply(&f);
+            }
+            if let Some(right) = self.right.0.as_mut() {
+                right.apply(&f);
+            }
+        }
+    }
+}
+impl<K: Ord, V, M: MapMonoid<K, V>> AVLNode for Node<K, V, M> {
+    type Key = K;
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn state(&self) -> ThreeWay {
+        self.state
+    }
+
+    fn state_mut(&mut self) -> &mut ThreeWay {
+        &mut self.state
+    }
+}
+
+impl<'a, K, V, M: MapMonoid<K, V>> IntoIterator for &'a NodePtr<K, V, M> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(&node.left);
+            vec.push((&node.key, &node.value));
+            vec.extend(&node.right);
+        }
+        vec.into_iter()
+    }
+}
+impl<K, V, M: MapMonoid<K, V>> NodePtr<K, V, M> {
+    fn size(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.size)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn aggregate(&self) -> M::Aggregate {
+        self.0
+            .as_ref()
+            .map_or_else(M::identity, |node| node.aggregate.clone())
+    }
+
+    // 祖先に溜まっている作用 tag を反映した部分木の集約値
+    fn aggregate_with(&self, tag: &Option<M::Map>) -> M::Aggregate {
+        match (&self.0, tag) {
+            (None, _) => M::identity(),
+            (Some(node), None) => node.aggregate.clone(),
+            (Some(node), Some(f)) => M::map_aggregate(f, &node.aggregate, node.size),
+        }
+    }
+
+    // 溜まっている作用をすべて葉まで伝播する
+    fn push_all(&mut self) {
+        if let Some(node) = self.0.as_mut() {
+            node.push();
+            node.left.push_all();
+            node.right.push_all();
+        }
+    }
+
+    fn keys(&self) -> Vec<&K> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.keys());
+            vec.push(&node.key);
+            vec.extend(node.right.keys());
+        }
+        vec
+    }
+
+    fn values(&self) -> Vec<&V> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.values());
+            vec.push(&node.value);
+            vec.extend(node.right.values());
+        }
+        vec
+    }
+}
+
+impl<K: Ord, V, M: MapMonoid<K, V>> NodePtr<K, V, M> {
+    fn insert(&mut self, key: K, value: V) -> bool {
+        insert_rec(&mut self.0, key, |key, node| match node {
+            Some(node) => {
+                node.value = value;
+                None
+            }
+            None => Some(Node::new(key, value)),
+        })
+        .0
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        remove_rec(&mut self.0, key).0.map(|node| node.value)
+    }
+
+    // 値を書き換えたあと、根までの集約値を計算し直す
+    fn modify<R>(&mut self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        let node = self.0.as_mut()?;
+        node.push();
+        let ret = match key.cmp(&node.key) {
+            std::cmp::Ordering::Less => node.left.modify(key, f),
+            std::cmp::Ordering::Greater => node.right.modify(key, f),
+            std::cmp::Ordering::Equal => Some(f(&mut node.value)),
+        };
+        node.update();
+        ret
+    }
+
+    fn search(&self, key: &K) -> Option<&V> {
+        let mut node = self.0.as_ref()?;
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_ref()?,
+                std::cmp::Ordering::Greater => node.right.0.as_ref()?,
+                std::cmp::Ordering::Equal => break Some(&node.value),
+            };
+        }
+    }
+
+    // 通った経路の作用を伝播しながら探索する
+    fn search_pushed(&mut self, key: &K) -> Option<&V> {
+        let mut node = self.0.as_mut()?;
+        loop {
+            node.push();
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_mut()?,
+                std::cmp::Ordering::Greater => node.right.0.as_mut()?,
+                std::cmp::Ordering::Equal => break Some(&node.value),
+            };
+        }
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.left.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.right.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
+        let mut node = self.0.as_ref().unwrap();
+        loop {
+            match node.left.size().cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= node.left.size() + 1;
+                    node = node.right.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Greater => {
+                    node = node.left.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Equal => {
+                    break Some((&node.key, &node.value));
+                }
+            }
+        }
+    }
+
+    // 通った経路の作用を伝播しながら order 番目の要素を探す
+    fn find_by_order_pushed(&mut self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
+        let mut node = self.0.as_mut().unwrap();
+        loop {
+            node.push();
+            match node.left.size().cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= node.left.size() + 1;
+                    node = node.right.0.as_mut().unwrap();
+                }
+                std::cmp::Ordering::Greater => {
+                    node = node.left.0.as_mut().unwrap();
+                }
+                std::cmp::Ordering::Equal => {
+                    break Some((&node.key, &node.value));
+                }
+            }
+        }
+    }
+
+    fn order_of_key(&self, key: &K) -> usize {
+        let mut order = 0;
+        let mut node = match self.0.as_ref() {
+            None => return 0,
+            Some(node) => node,
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    order += node.left.size() + 1;
+                    node = match node.right.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break order + node.left.size();
+                }
+            }
+        }
+    }
+
+    // 部分木のうち lower を満たすキーを持つ要素の集約値
+    // tag は祖先に溜まっている作用
+    fn fold_lower(&self, lower: Bound<&K>, mut tag: Option<M::Map>) -> M::Aggregate {
+        let mut acc = M::identity();
+        let mut ptr = self;
+        while let Some(node) = ptr.0.as_ref() {
+            let child_tag = node.child_tag(&tag);
+            if satisfies_lower(&node.key, lower) {
+                let right = M::combine(
+                    &node.lift_with(&tag),
+                    &node.right.aggregate_with(&child_tag),
+                );
+                acc = M::combine(&right, &acc);
+                ptr = &node.left;
+            } else {
+                ptr = &node.right;
+            }
+            tag = child_tag;
+        }
+        acc
+    }
+
+    // 部分木のうち upper を満たすキーを持つ要素の集約値
+    // tag は祖先に溜まっている作用
+    fn fold_upper(&self, upper: Bound<&K>, mut tag: Option<M::Map>) -> M::Aggregate {
+        let mut acc = M::identity();
+        let mut ptr = self;
+        while let Some(node) = ptr.0.as_ref() {
+            let child_tag = node.child_tag(&tag);
+            if satisfies_upper(&node.key, upper) {
+                let left = M::combine(&node.left.aggregate_with(&child_tag), &node.lift_with(&tag));
+                acc = M::combine(&acc, &left);
+                ptr = &node.right;
+            } else {
+                ptr = &node.left;
+            }
+            tag = child_tag;
+        }
+        acc
+    }
+
+    fn fold_range(&self, lower: Bound<&K>, upper: Bound<&K>) -> M::Aggregate {
+        let mut tag = None;
+        let mut ptr = self;
+        while let Some(node) = ptr.0.as_ref() {
+            let child_tag = node.child_tag(&tag);
+            if !satisfies_lower(&node.key, lower) {
+                ptr = &node.right;
+            } else if !satisfies_upper(&node.key, upper) {
+                ptr = &node.left;
+            } else {
+                // 範囲の両端がこのノードの左右に分かれる
+                let left = M::combine(
+                    &node.left.fold_lower(lower, child_tag.clone()),
+                    &node.lift_with(&tag),
+                );
+                return M::combine(&left, &node.right.fold_upper(upper, child_tag));
+            }
+            tag = child_tag;
+        }
+        M::identity()
+    }
+
+    fn fold_prefix(&self, rank: usize) -> M::Aggregate {
+        let mut acc = M::identity();
+        let mut rest = rank;
+        let mut tag = None;
+        let mut ptr = self;
+        while let Some(node) = ptr.0.as_ref() {
+            if rest == 0 {
+                break;
+            }
+            let child_tag = node.child_tag(&tag);
+            if rest <= node.left.size() {
+                ptr = &node.left;
+            } else {
+                let left = M::combine(&node.left.aggregate_with(&child_tag), &node.lift_with(&tag));
+                acc = M::combine(&acc, &left);
+                rest -= node.left.size() + 1;
+                ptr = &node.right;
+            }
+            tag = child_tag;
+        }
+        acc
+    }
+
+    // キーが start 以上の要素を昇順に畳み込んでいき、pred を満たさなくなる最初のノードを返す
+    fn max_right<P>(&self, start: &K, pred: P) -> Option<&Node<K, V, M>>
+    where
+        P: Fn(&M::Aggregate) -> bool,
+    {
+        // start 以上のキーを持つ部分を (ノード, その右の部分木) の組に分解する
+        // 訪れた順とは逆順に並べると昇順になる
+        let mut pieces = Vec::new();
+        let mut tag = None;
+        let mut ptr = self;
+        while let Some(node) = ptr.0.as_ref() {
+            let child_tag = node.child_tag(&tag);
+            if &node.key >= start {
+                pieces.push((node.as_ref(), tag, child_tag.clone()));
+                ptr = &node.left;
+            } else {
+                ptr = &node.right;
+            }
+            tag = child_tag;
+        }
+
+        let mut acc = M::identity();
+        for (node, tag, child_tag) in pieces.into_iter().rev() {
+            let next = M::combine(&acc, &node.lift_with(&tag));
+            if !pred(&next) {
+                return Some(node);
+            }
+            acc = next;
+            let next = M::combine(&acc, &node.right.aggregate_with(&child_tag));
+            if !pred(&next) {
+                return node.right.max_right_all(acc, child_tag, &pred);
+            }
+            acc = next;
+        }
+        None
+    }
+
+    // 部分木全体を畳み込むと pred を満たさないとわかっているときに、満たさなくなる最初のノードを返す
+    fn max_right_all<P>(
+        &self,
+        mut acc: M::Aggregate,
+        mut tag: Option<M::Map>,
+        pred: &P,
+    ) -> Option<&Node<K, V, M>>
+    where
+        P: Fn(&M::Aggregate) -> bool,
+    {
+        let mut node = self.0.as_ref()?;
+        loop {
+            let child_tag = node.child_tag(&tag);
+            let next = M::combine(&acc, &node.left.aggregate_with(&child_tag));
+            if !pred(&next) {
+                node = node.left.0.as_ref()?;
+                tag = child_tag;
+                continue;
+            }
+            acc = next;
+            let next = M::combine(&acc, &node.lift_with(&tag));
+            if !pred(&next) {
+                break Some(node);
+            }
+            acc = next;
+            node = node.right.0.as_ref()?;
+            tag = child_tag;
+        }
+    }
+
+    // キーが end 未満の要素を降順に畳み込んでいき、pred を満たさなくなる最初のノードを返す
+    fn min_left<P>(&self, end: &K, pred: P) -> Option<&Node<K, V, M>>
+    where
+        P: Fn(&M::Aggregate) -> bool,
+    {
+        // end 未満のキーを持つ部分を (左の部分木, ノード) の組に分解する
+        // 訪れた順とは逆順に並べると降順になる
+        let mut pieces = Vec::new();
+        let mut tag = None;
+        let mut ptr = self;
+        while let Some(node) = ptr.0.as_ref() {
+            let child_tag = node.child_tag(&tag);
+            if &node.key < end {
+                pieces.push((node.as_ref(), tag, child_tag.clone()));
+                ptr = &node.right;
+            } else {
+                ptr = &node.left;
+            }
+            tag = child_tag;
+        }
+
+        let mut acc = M::identity();
+        for (node, tag, child_tag) in pieces.into_iter().rev() {
+            let next = M::combine(&node.lift_with(&tag), &acc);
+            if !pred(&next) {
+                return Some(node);
+            }
+            acc = next;
+            let next = M::combine(&node.left.aggregate_with(&child_tag), &acc);
+            if !pred(&next) {
+                return node.left.min_left_all(acc, child_tag, &pred);
+            }
+            acc = next;
+        }
+        None
+    }
+
+    // 部分木全体を畳み込むと pred を満たさないとわかっているときに、満たさなくなる最初のノードを返す
+    fn min_left_all<P>(
+        &self,
+        mut acc: M::Aggregate,
+        mut tag: Option<M::Map>,
+        pred: &P,
+    ) -> Option<&Node<K, V, M>>
+    where
+        P: Fn(&M::Aggregate) -> bool,
+    {
+        let mut node = self.0.as_ref()?;
+        loop {
+            let child_tag = node.child_tag(&tag);
+            let next = M::combine(&node.right.aggregate_with(&child_tag), &acc);
+            if !pred(&next) {
+                node = node.right.0.as_ref()?;
+                tag = child_tag;
+                continue;
+            }
+            acc = next;
+            let next = M::combine(&node.lift_with(&tag), &acc);
+            if !pred(&next) {
+                break Some(node);
+            }
+            acc = next;
+            node = node.left.0.as_ref()?;
+            tag = child_tag;
+        }
+    }
+
+    // 部分木のうち lower を満たすキーを持つ要素に f を作用させる
+    fn apply_lower(&mut self, lower: Bound<&K>, f: &M::Map) {
+        let node = match self.0.as_mut() {
+            Some(node) => node,
+            None => return,
+        };
+        node.push();
+        if satisfies_lower(&node.key, lower) {
+            M::apply(f, &node.key, &mut node.value);
+            if let Some(right) = node.right.0.as_mut() {
+                right.apply(f);
+            }
+            node.left.apply_lower(lower, f);
+        } else {
+            node.right.apply_lower(lower, f);
+        }
+        node.update();
+    }
+
+    // 部分木のうち upper を満たすキーを持つ要素に f を作用させる
+    fn apply_upper(&mut self, upper: Bound<&K>, f: &M::Map) {
+        let node = match self.0.as_mut() {
+            Some(node) => node,
+            None => return,
+        };
+        node.push();
+        if satisfies_upper(&node.key, upper) {
+            M::apply(f, &node.key, &mut node.value);
+            if let Some(left) = node.left.0.as_mut() {
+                left.apply(f);
+            }
+            node.right.apply_upper(upper, f);
+        } else {
+            node.left.apply_upper(upper, f);
+        }
+        node.update();
+    }
+
+    fn apply_range(&mut self, lower: Bound<&K>, upper: Bound<&K>, f: &M::Map) {
+        let node = match self.0.as_mut() {
+            Some(node) => node,
+            None => return,
+        };
+        node.push();
+        if !satisfies_lower(&node.key, lower) {
+            node.right.apply_range(lower, upper, f);
+        } else if !satisfies_upper(&node.key, upper) {
+            node.left.apply_range(lower, upper, f);
+        } else {
+            // 範囲の両端がこのノードの左右に分かれる
+            M::apply(f, &node.key, &mut node.value);
+            node.left.apply_lower(lower, f);
+            node.right.apply_upper(upper, f);
+        }
+        node.update();
+    }
+}
+
+/**
+ * 各ノードに部分木の集約値を持たせた AVL 木
+ * 範囲の集約値を O(log n) で求められる
+ */
+pub struct MonoidAVLTreeMap<K: Ord, V, M: Monoid<K, V>> {
+    root: NodePtr<K, V, WithoutMap<M>>,
+}
+impl<K: Ord, V, M: Monoid<K, V>> MonoidAVLTreeMap<K, V, M> {
+    pub fn new() -> Self {
+        MonoidAVLTreeMap {
+            root: NodePtr(None),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        self.root.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.root.remove(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.search(key)
+    }
+
+    /**
+     * key に対応する値を f で書き換え、集約値を更新する
+     * key が存在しない場合は None を返す
+     */
+    pub fn modify<R>(&mut self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        self.root.modify(key, f)
+    }
+
+    pub fn min(&self) -> Option<(&K, &V)> {
+        self.root.min()
+    }
+
+    pub fn max(&self) -> Option<(&K, &V)> {
+        self.root.max()
+    }
+
+    pub fn size(&self) -> usize {
+        self.root.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    pub fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        self.root.find_by_order(order)
+    }
+
+    /**
+     * key 未満である要素の個数を返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        self.root.order_of_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<&K> {
+        self.root.keys()
+    }
+
+    pub fn values(&self) -> Vec<&V> {
+        self.root.values()
+    }
+
+    /**
+     * キーが range に含まれる要素の集約値をキーの昇順に畳み込んで返す
+     */
+    pub fn fold_range<R: RangeBounds<K>>(&self, range: R) -> M::Aggregate {
+        self.root.fold_range(range.start_bound(), range.end_bound())
+    }
+
+    /**
+     * すべての要素の集約値を返す
+     */
+    pub fn fold_all(&self) -> M::Aggregate {
+        self.root.aggregate()
+    }
+
+    /**
+     * キーの小さい方から rank 個の要素の集約値を返す
+     */
+    pub fn fold_prefix(&self, rank: usize) -> M::Aggregate {
+        self.root.fold_prefix(rank)
+    }
+
+    /**
+     * キーが start 以上の要素を昇順に畳み込んでいき、
+     * 集約値が初めて pred を満たさなくなったときに最後に加えた要素を返す
+     * 最後まで pred を満たし続ける場合は None を返す
+     * pred(identity) は true で、pred は単調でなければならない
+     */
+    pub fn max_right<P>(&self, start: &K, pred: P) -> Option<(&K, &V)>
+    where
+        P: Fn(&M::Aggregate) -> bool,
+    {
+        self.root
+            .max_right(start, pred)
+            .map(|node| (&node.key, &node.value))
+    }
+
+    /**
+     * キーが end 未満の要素を降順に畳み込んでいき、
+     * 集約値が初めて pred を満たさなくなったときに最後に加えた要素を返す
+     * 最後まで pred を満たし続ける場合は None を返す
+     * pred(identity) は true で、pred は単調でなければならない
+     */
+    pub fn min_left<P>(&self, end: &K, pred: P) -> Option<(&K, &V)>
+    where
+        P: Fn(&M::Aggregate) -> bool,
+    {
+        self.root
+            .min_left(end, pred)
+            .map(|node| (&node.key, &node.value))
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     */
+    pub fn stats(&self) -> TreeStats {
+        avl_tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + std::fmt::Debug, V, M: Monoid<K, V>> MonoidAVLTreeMap<K, V, M> {
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに部分木の大きさと平衡状態を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
+}
+impl<'a, K: Ord, V, M: Monoid<K, V>> IntoIterator for &'a MonoidAVLTreeMap<K, V, M> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.root.into_iter()
+    }
+}
+impl<K: Ord, V, M: Monoid<K, V>> Default for MonoidAVLTreeMap<K, V, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord, V, M: Monoid<K, V>> FromIterator<(K, V)> for MonoidAVLTreeMap<K, V, M> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
+
+/**
+ * MonoidAVLTreeMap に加えて、キーの範囲に含まれる値へ写像を一括で作用させられる AVL 木
+ * 作用は遅延させて保持し、探索や回転で子をたどるときに伝播する
+ */
+pub struct LazyMonoidAVLTreeMap<K: Ord, V, M: MapMonoid<K, V>> {
+    root: NodePtr<K, V, M>,
+}
+impl<K: Ord, V, M: MapMonoid<K, V>> LazyMonoidAVLTreeMap<K, V, M> {
+    pub fn new() -> Self {
+        LazyMonoidAVLTreeMap {
+            root: NodePtr(None),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        self.root.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.root.remove(key)
+    }
+
+    /**
+     * 経路上の遅延作用を伝播するため &mut self を取る
+     */
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.root.search_pushed(key)
+    }
+
+    /**
+     * key に対応する値を f で書き換え、集約値を更新する
+     * key が存在しない場合は None を返す
+     */
+    pub fn modify<R>(&mut self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        self.root.modify(key, f)
+    }
+
+    pub fn min(&mut self) -> Option<(&K, &V)> {
+        self.root.find_by_order_pushed(0)
+    }
+
+    pub fn max(&mut self) -> Option<(&K, &V)> {
+        let size = self.size();
+        self.root.find_by_order_pushed(size.checked_sub(1)?)
+    }
+
+    pub fn size(&self) -> usize {
+        self.root.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    pub fn find_by_order(&mut self, order: usize) -> Option<(&K, &V)> {
+        self.root.find_by_order_pushed(order)
+    }
+
+    /**
+     * key 未満である要素の個数を返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        self.root.order_of_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<&K> {
+        self.root.keys()
+    }
+
+    /**
+     * すべての遅延作用を伝播してから値を列挙する
+     */
+    pub fn values(&mut self) -> Vec<&V> {
+        self.root.push_all();
+        self.root.values()
+    }
+
+    /**
+     * キーが range に含まれる要素の値すべてに f を作用させる
+     */
+    pub fn apply_range<R: RangeBounds<K>>(&mut self, range: R, f: M::Map) {
+        self.root
+            .apply_range(range.start_bound(), range.end_bound(), &f)
+    }
+
+    /**
+     * キーが range に含まれる要素の集約値をキーの昇順に畳み込んで返す
+     */
+    pub fn fold_range<R: RangeBounds<K>>(&self, range: R) -> M::Aggregate {
+        self.root.fold_range(range.start_bound(), range.end_bound())
+    }
+
+    /**
+     * すべての要素の集約値を返す
+     */
+    pub fn fold_all(&self) -> M::Aggregate {
+        self.root.aggregate()
+    }
+
+    /**
+     * キーの小さい方から rank 個の要素の集約値を返す
+     */
+    pub fn fold_prefix(&self, rank: usize) -> M::Aggregate {
+        self.root.fold_prefix(rank)
+    }
+
+    /**
+     * MonoidAVLTreeMap::max_right と同じ
+     * 返す値に遅延作用を反映させるため &mut self を取る
+     */
+    pub fn max_right<P>(&mut self, start: &K, pred: P) -> Option<(&K, &V)>
+    where
+        P: Fn(&M::Aggregate) -> bool,
+    {
+        let order = {
+            let node = self.root.max_right(start, pred)?;
+            self.root.order_of_key(&node.key)
+        };
+        self.root.find_by_order_pushed(order)
+    }
+
+    /**
+     * MonoidAVLTreeMap::min_left と同じ
+     * 返す値に遅延作用を反映させるため &mut self を取る
+     */
+    pub fn min_left<P>(&mut self, end: &K, pred: P) -> Option<(&K, &V)>
+    where
+        P: Fn(&M::Aggregate) -> bool,
+    {
+        let order = {
+            let node = self.root.min_left(end, pred)?;
+            self.root.order_of_key(&node.key)
+        };
+        self.root.find_by_order_pushed(order)
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     */
+    pub fn stats(&self) -> TreeStats {
+        avl_tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + std::fmt::Debug, V, M: MapMonoid<K, V>> LazyMonoidAVLTreeMap<K, V, M> {
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに部分木の大きさと平衡状態を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
+}
+impl<K: Ord, V, M: MapMonoid<K, V>> Default for LazyMonoidAVLTreeMap<K, V, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord, V, M: MapMonoid<K, V>> FromIterator<(K, V)> for LazyMonoidAVLTreeMap<K, V, M> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
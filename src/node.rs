@@ -1,43 +1,94 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+/**
+ * 子を指すポインタ
+ * 通常の木では Box を、版の間でノードを共有する永続木では Arc を使う
+ */
+pub trait NodePointer<N>: Deref<Target = N> {
+    fn new(node: N) -> Self;
+    /**
+     * 書き換えるための参照を返す
+     * 他の版と共有している場合は、その場で複製してから返す
+     */
+    fn make_mut(this: &mut Self) -> &mut N;
+}
+impl<N> NodePointer<N> for Box<N> {
+    fn new(node: N) -> Self {
+        Box::new(node)
+    }
+
+    fn make_mut(this: &mut Self) -> &mut N {
+        this
+    }
+}
+// 共有しているノードだけを書き換えの直前に複製するので、更新で複製されるのは根からの経路上のノードだけになる
+// 複製は Arc::make_mut が新しい Arc に直接作るため、1 ノードにつき 1 回の確保で済む
+impl<N: Clone> NodePointer<N> for Arc<N> {
+    fn new(node: N) -> Self {
+        Arc::new(node)
+    }
+
+    fn make_mut(this: &mut Self) -> &mut N {
+        Arc::make_mut(this)
+    }
+}
+
 pub trait NodeTrait: Sized {
+    type Ptr: NodePointer<Self>;
     fn size(&self) -> usize;
     fn size_mut(&mut self) -> &mut usize;
     fn left(&self) -> Option<&Self>;
     fn right(&self) -> Option<&Self>;
-    fn take_left(&mut self) -> Option<Box<Self>>;
-    fn take_right(&mut self) -> Option<Box<Self>>;
-    fn set_left(&mut self, node: Option<Box<Self>>);
-    fn set_right(&mut self, node: Option<Box<Self>>);
+    fn left_mut(&mut self) -> Option<&mut Self>;
+    fn right_mut(&mut self) -> Option<&mut Self>;
+    fn take_left(&mut self) -> Option<Self::Ptr>;
+    fn take_right(&mut self) -> Option<Self::Ptr>;
+    fn set_left(&mut self, node: Option<Self::Ptr>);
+    fn set_right(&mut self, node: Option<Self::Ptr>);
+    /**
+     * 子が変化したあとに呼ばれ、部分木から計算される値を再計算する
+     * size 以外の値 (和や最大値など) を持つノードはこれを上書きする
+     */
+    fn update(&mut self) {
+        *self.size_mut() =
+            self.left().map_or(0, |n| n.size()) + self.right().map_or(0, |n| n.size()) + 1;
+    }
+    /**
+     * 子を読み書きする前に呼ばれ、このノードに溜めている遅延作用を子に伝播する
+     * 遅延作用を持たないノードでは何もしない
+     */
+    fn push(&mut self) {}
     fn rotate_right(&mut self) {
+        self.push();
         let mut left = match self.take_left() {
             Some(left) => left,
             None => return,
         };
+        let left_mut = Self::Ptr::make_mut(&mut left);
+        left_mut.push();
 
-        self.set_left(left.take_right());
-        // TODO: update() 的な関数を呼ぶ
-        *self.size_mut() = self.left().map_or(0, |node| node.size())
-            + self.right().map_or(0, |node| node.size())
-            + 1;
+        self.set_left(left_mut.take_right());
+        self.update();
 
-        std::mem::swap(self, &mut left);
+        std::mem::swap(self, left_mut);
         self.set_right(Some(left));
-        *self.size_mut() =
-            self.left().map_or(0, |n| n.size()) + self.right().map_or(0, |n| n.size()) + 1;
+        self.update();
     }
     fn rotate_left(&mut self) {
+        self.push();
         let mut right = match self.take_right() {
             Some(right) => right,
             None => return,
         };
+        let right_mut = Self::Ptr::make_mut(&mut right);
+        right_mut.push();
 
-        self.set_right(right.take_left());
-        // TODO: update() 的な関数を呼ぶ
-        *self.size_mut() =
-            self.left().map_or(0, |n| n.size()) + self.right().map_or(0, |n| n.size()) + 1;
+        self.set_right(right_mut.take_left());
+        self.update();
 
-        std::mem::swap(self, &mut right);
+        std::mem::swap(self, right_mut);
         self.set_left(Some(right));
-        *self.size_mut() =
-            self.left().map_or(0, |n| n.size()) + self.right().map_or(0, |n| n.size()) + 1;
+        self.update();
     }
 }
@@ -0,0 +1,125 @@
+use crate::avl_tree::AVLTreeMap;
+
+/**
+ * ObservedMap の変更を受け取る
+ * 各メソッドは木に変更を反映した後に呼ばれる。既定の実装は何もしない
+ */
+pub trait MapObserver<K, V> {
+    // 新しいキーが挿入された
+    fn on_insert(&mut self, _key: &K, _value: &V) {}
+    // 既存のキーの値が old から new に変わった
+    fn on_update(&mut self, _key: &K, _old: &V, _new: &V) {}
+    // キーが取り除かれた
+    fn on_remove(&mut self, _key: &K, _value: &V) {}
+}
+
+/**
+ * 変更のたびに observer へ通知する AVLTreeMap
+ * 木を書き換える操作はすべてこの型を通して行うので、通知の漏れはない
+ */
+pub struct ObservedMap<K: Ord, V, O: MapObserver<K, V>> {
+    map: AVLTreeMap<K, V>,
+    observer: O,
+}
+impl<K: Ord, V, O: MapObserver<K, V>> ObservedMap<K, V, O> {
+    pub fn new(observer: O) -> Self {
+        Self::with_map(AVLTreeMap::new(), observer)
+    }
+
+    /**
+     * 既存の木を包む
+     * map にすでにある要素については通知しない
+     */
+    pub fn with_map(map: AVLTreeMap<K, V>, observer: O) -> Self {
+        ObservedMap { map, observer }
+    }
+
+    /**
+     * key がすでに存在した場合は値を置き換えて on_update を、そうでなければ on_insert を呼ぶ
+     * key がそれまで存在しなかった場合は true を返す
+     */
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        match self.map.replace_entry(key, value) {
+            (key, value, Some(old)) => {
+                self.observer.on_update(key, &old, value);
+                false
+            }
+            (key, value, None) => {
+                self.observer.on_insert(key, value);
+                true
+            }
+        }
+    }
+
+    /**
+     * key に対応する値を f で書き換えて on_update を呼ぶ
+     * key が存在しない場合は何もせず false を返す
+     */
+    pub fn update<F: FnOnce(&mut V)>(&mut self, key: &K, f: F) -> bool
+    where
+        V: Clone,
+    {
+        let Some(slot) = self.map.get_mut(key) else {
+            return false;
+        };
+        let old = slot.clone();
+        f(slot);
+        self.observer.on_update(key, &old, slot);
+        true
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.map.remove(key)?;
+        self.observer.on_remove(key, &value);
+        Some(value)
+    }
+
+    /**
+     * f が false を返した要素をすべて取り除き、それぞれについて on_remove を呼ぶ
+     * 通知の漏れがないよう、f には値を書き換えられない参照を渡す
+     * on_remove は木から取り除いたあとにキーの昇順で呼ぶ
+     */
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&mut self, mut f: F) {
+        for (key, value) in self.map.retain_removed(|key, value| f(key, value)) {
+            self.observer.on_remove(&key, &value);
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.get(key).is_some()
+    }
+
+    pub fn size(&self) -> usize {
+        self.map.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /**
+     * 読み出し用に中の木を返す
+     */
+    pub fn map(&self) -> &AVLTreeMap<K, V> {
+        &self.map
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /**
+     * 中の木と observer を取り出す
+     */
+    pub fn into_parts(self) -> (AVLTreeMap<K, V>, O) {
+        (self.map, self.observer)
+    }
+}
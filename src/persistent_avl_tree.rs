@@ -0,0 +1,422 @@
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+use crate::avl_tree::{insert_rec, remove_rec, AVLNode, ThreeWay};
+use crate::diff::{Diff, DiffItem, EntryNode};
+use crate::node::NodeTrait;
+use crate::pretty::fmt_tree;
+use crate::stats::{avl_tree_stats, TreeStats};
+
+type NodePtrInner<K, V> = Option<Arc<Node<K, V>>>;
+struct NodePtr<K, V>(NodePtrInner<K, V>);
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: NodePtr<K, V>,
+    right: NodePtr<K, V>,
+    size: usize,
+    state: ThreeWay,
+}
+
+impl<K, V> Clone for NodePtr<K, V> {
+    fn clone(&self) -> Self {
+        NodePtr(self.0.clone())
+    }
+}
+impl<K: Clone, V: Clone> Clone for Node<K, V> {
+    fn clone(&self) -> Self {
+        Node {
+            key: self.key.clone(),
+            value: self.value.clone(),
+            left: self.left.clone(),
+            right: self.right.clone(),
+            size: self.size,
+            state: self.state,
+        }
+    }
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Self {
+        Node {
+            key,
+            value,
+            left: NodePtr(None),
+            right: NodePtr(None),
+            size: 1,
+            state: ThreeWay::Equal,
+        }
+    }
+}
+
+// fmt_tree で各ノードに表示する内容
+// 最初の行がキーで、残りの行が補足の情報になる
+fn node_label<K: std::fmt::Debug, V>(node: &Node<K, V>) -> Vec<String> {
+    vec![
+        format!("{:?}", node.key),
+        format!("size: {}", node.size),
+        format!("state: {:?}", node.state),
+    ]
+}
+
+// 子を Arc で持ち、他の版と共有しているノードは書き換える直前に複製する (NodePointer を参照)
+impl<K: Clone, V: Clone> NodeTrait for Node<K, V> {
+    type Ptr = Arc<Self>;
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn size_mut(&mut self) -> &mut usize {
+        &mut self.size
+    }
+
+    fn left(&self) -> Option<&Self> {
+        self.left.0.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.0.as_deref()
+    }
+
+    fn left_mut(&mut self) -> Option<&mut Self> {
+        self.left.0.as_mut().map(Arc::make_mut)
+    }
+
+    fn right_mut(&mut self) -> Option<&mut Self> {
+        self.right.0.as_mut().map(Arc::make_mut)
+    }
+
+    fn take_left(&mut self) -> Option<Arc<Self>> {
+        self.left.0.take()
+    }
+
+    fn take_right(&mut self) -> Option<Arc<Self>> {
+        self.right.0.take()
+    }
+
+    fn set_left(&mut self, node: Option<Arc<Self>>) {
+        self.left.0 = node;
+    }
+
+    fn set_right(&mut self, node: Option<Arc<Self>>) {
+        self.right.0 = node;
+    }
+}
+impl<K: Ord + Clone, V: Clone> EntryNode for Node<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn entry(&self) -> (&K, &V) {
+        (&self.key, &self.value)
+    }
+}
+impl<K: Ord + Clone, V: Clone> AVLNode for Node<K, V> {
+    type Key = K;
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn state(&self) -> ThreeWay {
+        self.state
+    }
+
+    fn state_mut(&mut self) -> &mut ThreeWay {
+        &mut self.state
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a NodePtr<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(&node.left);
+            vec.push((&node.key, &node.value));
+            vec.extend(&node.right);
+        }
+        vec.into_iter()
+    }
+}
+impl<K, V> NodePtr<K, V> {
+    fn size(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.size)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn keys(&self) -> Vec<&K> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.keys());
+            vec.push(&node.key);
+            vec.extend(node.right.keys());
+        }
+        vec
+    }
+
+    fn values(&self) -> Vec<&V> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.values());
+            vec.push(&node.value);
+            vec.extend(node.right.values());
+        }
+        vec
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.left.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.right.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
+        let mut node = self.0.as_ref().unwrap();
+        loop {
+            match node.left.size().cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= node.left.size() + 1;
+                    node = node.right.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Greater => {
+                    node = node.left.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Equal => {
+                    break Some((&node.key, &node.value));
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> NodePtr<K, V> {
+    fn search(&self, key: &K) -> Option<&V> {
+        let mut node = self.0.as_ref()?;
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_ref()?,
+                std::cmp::Ordering::Greater => node.right.0.as_ref()?,
+                std::cmp::Ordering::Equal => break Some(&node.value),
+            };
+        }
+    }
+
+    fn order_of_key(&self, key: &K) -> usize {
+        let mut order = 0;
+        let mut node = match self.0.as_ref() {
+            None => return 0,
+            Some(node) => node,
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    order += node.left.size() + 1;
+                    node = match node.right.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break order + node.left.size();
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> NodePtr<K, V> {
+    fn insert(&self, key: K, value: V) -> Self {
+        let mut root = self.0.clone();
+        insert_rec(&mut root, key, |key, node| match node {
+            Some(node) => {
+                node.value = value;
+                None
+            }
+            None => Some(Node::new(key, value)),
+        });
+        NodePtr(root)
+    }
+
+    fn remove(&self, key: &K) -> Self {
+        let mut root = self.0.clone();
+        remove_rec(&mut root, key);
+        NodePtr(root)
+    }
+}
+
+/**
+ * 永続 AVL 木
+ * insert や remove は元の木を変更せずに新しい版を返し、変更のない部分木は版の間で共有する
+ * clone は根の参照カウントを増やすだけなので O(1) でできる
+ */
+pub struct PersistentAVLMap<K: Ord, V> {
+    root: NodePtr<K, V>,
+}
+impl<K: Ord, V> PersistentAVLMap<K, V> {
+    pub fn new() -> Self {
+        PersistentAVLMap {
+            root: NodePtr(None),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.search(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.root.search(key).is_some()
+    }
+
+    pub fn min(&self) -> Option<(&K, &V)> {
+        self.root.min()
+    }
+
+    pub fn max(&self) -> Option<(&K, &V)> {
+        self.root.max()
+    }
+
+    pub fn size(&self) -> usize {
+        self.root.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    pub fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        self.root.find_by_order(order)
+    }
+
+    /**
+     * key 未満である要素の個数を返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        self.root.order_of_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<&K> {
+        self.root.keys()
+    }
+
+    pub fn values(&self) -> Vec<&V> {
+        self.root.values()
+    }
+}
+impl<K: Ord + Clone, V: Clone> PersistentAVLMap<K, V> {
+    /**
+     * key に value を対応させた新しい版を返す
+     * key が既に存在する場合は値を上書きする
+     */
+    #[must_use]
+    pub fn insert(&self, key: K, value: V) -> Self {
+        PersistentAVLMap {
+            root: self.root.insert(key, value),
+        }
+    }
+
+    /**
+     * key を取り除いた新しい版を返す
+     * key が存在しない場合は自身と同じ木を共有する版を返す
+     */
+    #[must_use]
+    pub fn remove(&self, key: &K) -> Self {
+        if !self.contains_key(key) {
+            return self.clone();
+        }
+        PersistentAVLMap {
+            root: self.root.remove(key),
+        }
+    }
+
+    /**
+     * self を古い版、other を新しい版として、差分をキーの昇順に返す
+     * 2 つの版が共有している部分木は中をたどらずに読み飛ばすので、
+     * 一方の版から少数の更新で作った版との比較は、更新で複製したノードの個数程度で済む
+     */
+    pub fn diff<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = DiffItem<'a, K, V>> + 'a
+    where
+        V: PartialEq,
+    {
+        Diff::new(self.root.0.as_deref(), other.root.0.as_deref())
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     * ほかの版と共有しているノードもこの版のノードとして数える
+     */
+    pub fn stats(&self) -> TreeStats {
+        avl_tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + Clone + std::fmt::Debug, V: Clone> PersistentAVLMap<K, V> {
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに部分木の大きさと平衡状態を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
+}
+impl<K: Ord, V> Clone for PersistentAVLMap<K, V> {
+    fn clone(&self) -> Self {
+        PersistentAVLMap {
+            root: self.root.clone(),
+        }
+    }
+}
+impl<'a, K: Ord, V> IntoIterator for &'a PersistentAVLMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.root.into_iter()
+    }
+}
+impl<K: Ord, V> Default for PersistentAVLMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord + Clone, V: Clone> FromIterator<(K, V)> for PersistentAVLMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree = tree.insert(key, value);
+        }
+        tree
+    }
+}
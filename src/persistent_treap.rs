@@ -0,0 +1,437 @@
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+use rand::random;
+
+use crate::node::NodeTrait;
+use crate::pretty::fmt_tree;
+use crate::stats::{tree_stats, TreeStats};
+use crate::treap::{insert_rec, merge, remove_rec, split, TreapNode};
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: NodePtr<K, V>,
+    right: NodePtr<K, V>,
+    size: usize,
+    priority: f64,
+}
+type NodePtrInner<K, V> = Option<Arc<Node<K, V>>>;
+struct NodePtr<K, V>(NodePtrInner<K, V>);
+
+impl<K, V> Clone for NodePtr<K, V> {
+    fn clone(&self) -> Self {
+        NodePtr(self.0.clone())
+    }
+}
+impl<K: Clone, V: Clone> Clone for Node<K, V> {
+    fn clone(&self) -> Self {
+        Node {
+            key: self.key.clone(),
+            value: self.value.clone(),
+            left: self.left.clone(),
+            right: self.right.clone(),
+            size: self.size,
+            priority: self.priority,
+        }
+    }
+}
+
+// fmt_tree で各ノードに表示する内容
+// 最初の行がキーで、残りの行が補足の情報になる
+fn node_label<K: std::fmt::Debug, V>(node: &Node<K, V>) -> Vec<String> {
+    vec![
+        format!("{:?}", node.key),
+        format!("size: {}", node.size),
+        format!("priority: {:.4}", node.priority),
+    ]
+}
+
+// 子を Arc で持ち、他の版と共有しているノードは書き換える直前に複製する (NodePointer を参照)
+impl<K: Clone, V: Clone> NodeTrait for Node<K, V> {
+    type Ptr = Arc<Self>;
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn size_mut(&mut self) -> &mut usize {
+        &mut self.size
+    }
+
+    fn left(&self) -> Option<&Self> {
+        self.left.0.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.0.as_deref()
+    }
+
+    fn left_mut(&mut self) -> Option<&mut Self> {
+        self.left.0.as_mut().map(Arc::make_mut)
+    }
+
+    fn right_mut(&mut self) -> Option<&mut Self> {
+        self.right.0.as_mut().map(Arc::make_mut)
+    }
+
+    fn take_left(&mut self) -> Option<Arc<Self>> {
+        self.left.0.take()
+    }
+
+    fn take_right(&mut self) -> Option<Arc<Self>> {
+        self.right.0.take()
+    }
+
+    fn set_left(&mut self, node: Option<Arc<Self>>) {
+        self.left.0 = node;
+    }
+
+    fn set_right(&mut self, node: Option<Arc<Self>>) {
+        self.right.0 = node;
+    }
+}
+impl<K: Ord + Clone, V: Clone> TreapNode for Node<K, V> {
+    type Key = K;
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn priority(&self) -> f64 {
+        self.priority
+    }
+}
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Self {
+        Node {
+            key,
+            value,
+            left: NodePtr(None),
+            right: NodePtr(None),
+            size: 1,
+            priority: random::<f64>(),
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a NodePtr<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(&node.left);
+            vec.push((&node.key, &node.value));
+            vec.extend(&node.right);
+        }
+        vec.into_iter()
+    }
+}
+impl<K, V> NodePtr<K, V> {
+    fn size(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.size)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn keys(&self) -> Vec<&K> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.keys());
+            vec.push(&node.key);
+            vec.extend(node.right.keys());
+        }
+        vec
+    }
+
+    fn values(&self) -> Vec<&V> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.values());
+            vec.push(&node.value);
+            vec.extend(node.right.values());
+        }
+        vec
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.left.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.right.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
+        let mut node = self.0.as_ref().unwrap();
+        loop {
+            match node.left.size().cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= node.left.size() + 1;
+                    node = node.right.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Greater => {
+                    node = node.left.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Equal => {
+                    break Some((&node.key, &node.value));
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> NodePtr<K, V> {
+    fn search(&self, key: &K) -> Option<&V> {
+        let mut node = self.0.as_ref()?;
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_ref()?,
+                std::cmp::Ordering::Greater => node.right.0.as_ref()?,
+                std::cmp::Ordering::Equal => break Some(&node.value),
+            };
+        }
+    }
+
+    fn order_of_key(&self, key: &K) -> usize {
+        let mut order = 0;
+        let mut node = match self.0.as_ref() {
+            None => return 0,
+            Some(node) => node,
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    order += node.left.size() + 1;
+                    node = match node.right.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break order + node.left.size();
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> NodePtr<K, V> {
+    fn insert(&self, key: K, value: V) -> Self {
+        let mut root = self.0.clone();
+        insert_rec(&mut root, key, |key, node| match node {
+            Some(node) => {
+                node.value = value;
+                None
+            }
+            None => Some(Node::new(key, value)),
+        });
+        NodePtr(root)
+    }
+
+    fn remove(&self, key: &K) -> Self {
+        let mut root = self.0.clone();
+        remove_rec(&mut root, key);
+        NodePtr(root)
+    }
+
+    fn split(&self, key: &K) -> (Self, Self) {
+        let (left, right) = split(self.0.clone(), key);
+        (NodePtr(left), NodePtr(right))
+    }
+
+    fn merge(&self, other: &Self) -> Self {
+        NodePtr(merge(self.0.clone(), other.0.clone()))
+    }
+}
+
+/**
+ * 永続 Treap
+ * insert, remove, split, merge は元の版を変更せずに新しい版を返し、変更のない部分木は版の間で共有する
+ * 各版は独立に問い合わせることができ、clone は O(1) でできる
+ */
+pub struct PersistentTreap<K: Ord, V> {
+    root: NodePtr<K, V>,
+}
+impl<K: Ord, V> PersistentTreap<K, V> {
+    pub fn new() -> Self {
+        PersistentTreap {
+            root: NodePtr(None),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.search(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.root.search(key).is_some()
+    }
+
+    pub fn min(&self) -> Option<(&K, &V)> {
+        self.root.min()
+    }
+
+    pub fn max(&self) -> Option<(&K, &V)> {
+        self.root.max()
+    }
+
+    pub fn size(&self) -> usize {
+        self.root.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    pub fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        self.root.find_by_order(order)
+    }
+
+    /**
+     * key 未満である要素の個数を返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        self.root.order_of_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<&K> {
+        self.root.keys()
+    }
+
+    pub fn values(&self) -> Vec<&V> {
+        self.root.values()
+    }
+}
+impl<K: Ord + Clone, V: Clone> PersistentTreap<K, V> {
+    /**
+     * key に value を対応させた新しい版を返す
+     * key が既に存在する場合は値を上書きする
+     */
+    #[must_use]
+    pub fn insert(&self, key: K, value: V) -> Self {
+        PersistentTreap {
+            root: self.root.insert(key, value),
+        }
+    }
+
+    /**
+     * key を取り除いた新しい版を返す
+     * key が存在しない場合は自身と同じ木を共有する版を返す
+     */
+    #[must_use]
+    pub fn remove(&self, key: &K) -> Self {
+        if !self.contains_key(key) {
+            return self.clone();
+        }
+        PersistentTreap {
+            root: self.root.remove(key),
+        }
+    }
+
+    /**
+     * key 未満の要素からなる版と key 以上の要素からなる版を返す
+     */
+    #[must_use]
+    pub fn split(&self, key: &K) -> (Self, Self) {
+        let (left, right) = self.root.split(key);
+        (
+            PersistentTreap { root: left },
+            PersistentTreap { root: right },
+        )
+    }
+
+    /**
+     * self の末尾に other を連結した版を返す
+     * other のキーはすべて self のキーより大きくなければならず、そうでない場合は panic する
+     */
+    #[must_use]
+    pub fn merge(&self, other: &Self) -> Self {
+        assert!(
+            match (self.max(), other.min()) {
+                (Some((max, _)), Some((min, _))) => max < min,
+                _ => true,
+            },
+            "keys of other must be greater than all keys of self"
+        );
+        PersistentTreap {
+            root: self.root.merge(&other.root),
+        }
+    }
+
+    /**
+     * 高さやノードの深さなど、木の形に関する統計を返す
+     * ほかの版と共有しているノードもこの版のノードとして数える
+     */
+    pub fn stats(&self) -> TreeStats {
+        tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + Clone + std::fmt::Debug, V: Clone> PersistentTreap<K, V> {
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに部分木の大きさと優先度を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
+}
+impl<K: Ord, V> Clone for PersistentTreap<K, V> {
+    fn clone(&self) -> Self {
+        PersistentTreap {
+            root: self.root.clone(),
+        }
+    }
+}
+impl<'a, K: Ord, V> IntoIterator for &'a PersistentTreap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.root.into_iter()
+    }
+}
+impl<K: Ord, V> Default for PersistentTreap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord + Clone, V: Clone> FromIterator<(K, V)> for PersistentTreap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree = tree.insert(key, value);
+        }
+        tree
+    }
+}
@@ -0,0 +1,78 @@
+use crate::node::NodeTrait;
+
+#[derive(Clone, Copy)]
+enum Position {
+    Root,
+    Left,
+    Right,
+}
+
+fn write_node<N: NodeTrait>(
+    node: Option<&N>,
+    prefix: &str,
+    position: Position,
+    annotated: bool,
+    out: &mut String,
+    label: &impl Fn(&N) -> Vec<String>,
+) {
+    let node = match node {
+        Some(node) => node,
+        None => return,
+    };
+
+    // 右の子は上に、左の子は下に書く
+    // 親の行をまたいで反対側の子へ伸びる枝だけ縦線でつなぐ
+    let (right_prefix, left_prefix) = match position {
+        Position::Root => (String::new(), String::new()),
+        Position::Left => (format!("{prefix}│   "), format!("{prefix}    ")),
+        Position::Right => (format!("{prefix}    "), format!("{prefix}│   ")),
+    };
+
+    write_node(
+        node.right(),
+        &right_prefix,
+        Position::Right,
+        annotated,
+        out,
+        label,
+    );
+
+    out.push_str(prefix);
+    out.push_str(match position {
+        Position::Root => "",
+        Position::Left => "└── ",
+        Position::Right => "┌── ",
+    });
+    let lines = label(node);
+    out.push_str(&lines[0]);
+    if annotated && lines.len() > 1 {
+        out.push_str(&format!(" ({})", lines[1..].join(", ")));
+    }
+    out.push('\n');
+
+    write_node(
+        node.left(),
+        &left_prefix,
+        Position::Left,
+        annotated,
+        out,
+        label,
+    );
+}
+
+/**
+ * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+ * label が返す最初の行をノードの表示に使い、annotated が true なら残りの行を括弧内に添える
+ */
+pub(crate) fn fmt_tree<N: NodeTrait>(
+    root: Option<&N>,
+    annotated: bool,
+    label: impl Fn(&N) -> Vec<String>,
+) -> String {
+    if root.is_none() {
+        return String::from("(empty)\n");
+    }
+    let mut out = String::new();
+    write_node(root, "", Position::Root, annotated, &mut out, &label);
+    out
+}
@@ -0,0 +1,187 @@
+use rayon::iter::plumbing::{bridge, Consumer, Producer, ProducerCallback, UnindexedConsumer};
+use rayon::prelude::*;
+
+use crate::avl_tree::{AVLTreeMap, AVLTreeSet, Cursor};
+
+/**
+ * AVLTreeMap の要素をキーの昇順に並列にたどるイテレータ
+ * 各ノードの size を使って位置で範囲を二等分していくので、木の形によらず均等に分割できる
+ */
+pub struct ParIter<'a, K, V> {
+    // 範囲の先頭を指すカーソル
+    front: Cursor<'a, K, V>,
+    start: usize,
+    end: usize,
+}
+
+// ParIter を位置で分割していく単位
+struct IterProducer<'a, K, V> {
+    front: Cursor<'a, K, V>,
+    start: usize,
+    end: usize,
+}
+
+// start から end の直前までの要素を順にたどる
+struct Iter<'a, K, V> {
+    front: Cursor<'a, K, V>,
+    // 次に next_back で返す要素の 1 つ後ろを指す
+    back: Cursor<'a, K, V>,
+    len: usize,
+}
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = (self.front.key()?, self.front.value()?);
+        self.front.move_next();
+        self.len -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.back.move_prev();
+        self.len -= 1;
+        Some((self.back.key()?, self.back.value()?))
+    }
+}
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<'a, K: Sync, V: Sync> Producer for IterProducer<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let back = self.front.at(self.end);
+        Iter {
+            front: self.front,
+            back,
+            len: self.end - self.start,
+        }
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let mid = self.start + index;
+        let right = IterProducer {
+            front: self.front.at(mid),
+            start: mid,
+            end: self.end,
+        };
+        let left = IterProducer {
+            front: self.front,
+            start: self.start,
+            end: mid,
+        };
+        (left, right)
+    }
+}
+
+impl<'a, K: Sync, V: Sync> ParallelIterator for ParIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge(self, consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        Some(self.end - self.start)
+    }
+}
+impl<K: Sync, V: Sync> IndexedParallelIterator for ParIter<'_, K, V> {
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    fn drive<C: Consumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge(self, consumer)
+    }
+
+    fn with_producer<CB: ProducerCallback<Self::Item>>(self, callback: CB) -> CB::Output {
+        callback.callback(IterProducer {
+            front: self.front,
+            start: self.start,
+            end: self.end,
+        })
+    }
+}
+
+impl<K: Ord + Sync, V: Sync> AVLTreeMap<K, V> {
+    /**
+     * 要素をキーの昇順に並列にたどるイテレータを返す
+     */
+    pub fn par_iter(&self) -> ParIter<'_, K, V> {
+        ParIter {
+            front: self.cursor_at(0),
+            start: 0,
+            end: self.size(),
+        }
+    }
+}
+impl<K: Ord + Send + Sync, V: Send + Sync> AVLTreeMap<K, V> {
+    /**
+     * キーの昇順に並んだ重複のない要素から、部分木を並列に組み立てて木を作る
+     * キーが狭義単調増加でない場合は panic する
+     */
+    pub fn from_sorted_par(vec: Vec<(K, V)>) -> Self {
+        assert!(
+            vec.par_windows(2).all(|w| w[0].0 < w[1].0),
+            "keys must be strictly increasing"
+        );
+        let mut slots: Vec<_> = vec.into_par_iter().map(Some).collect();
+        AVLTreeMap::from_sorted_slots(&mut slots)
+    }
+}
+
+impl<K: Ord + Sync> AVLTreeSet<K> {
+    /**
+     * 要素を昇順に並列にたどるイテレータを返す
+     */
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = &K> {
+        ParIter {
+            front: self.cursor_at(0),
+            start: 0,
+            end: self.size(),
+        }
+        .map(|(key, _)| key)
+    }
+}
+impl<K: Ord + Send + Sync> AVLTreeSet<K> {
+    /**
+     * 昇順に並んだ重複のない要素から、部分木を並列に組み立てて木を作る
+     * 要素が狭義単調増加でない場合は panic する
+     */
+    pub fn from_sorted_par(vec: Vec<K>) -> Self {
+        assert!(
+            vec.par_windows(2).all(|w| w[0] < w[1]),
+            "keys must be strictly increasing"
+        );
+        let mut slots: Vec<_> = vec.into_par_iter().map(|key| Some((key, ()))).collect();
+        AVLTreeSet::from_sorted_slots(&mut slots)
+    }
+
+    /**
+     * 2 つの集合の和を返す
+     * 低い方の木の根で高い方の木を分割し、左右の和を並列に求めて連結する
+     */
+    pub fn par_union(self, other: Self) -> Self {
+        self.union_par(other)
+    }
+
+    /**
+     * 2 つの集合の共通部分を返す
+     * 低い方の木の根で高い方の木を分割し、左右の共通部分を並列に求めて連結する
+     */
+    pub fn par_intersection(self, other: Self) -> Self {
+        self.intersection_par(other)
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::avl_tree::{AVLTreeMap, AVLTreeSet};
+use crate::treap::Treap;
+
+/**
+ * 入力に同じキーが複数回現れたときの扱い
+ * 集合では KeepFirst と KeepLast は同じ結果になる
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DuplicateKeys {
+    // 最初に現れた値を残す
+    KeepFirst,
+    // 最後に現れた値で上書きする (BTreeMap と同じ)
+    #[default]
+    KeepLast,
+    // エラーにする
+    Error,
+}
+
+// 逆直列化で木を組み立てるのに必要な操作
+trait Build: Sized {
+    type Key: Ord;
+    type Item;
+    fn key(item: &Self::Item) -> &Self::Key;
+    fn from_sorted(vec: Vec<Self::Item>) -> Self;
+    fn contains(&self, key: &Self::Key) -> bool;
+    fn insert(&mut self, item: Self::Item);
+}
+
+impl<K: Ord, V> Build for AVLTreeMap<K, V> {
+    type Key = K;
+    type Item = (K, V);
+
+    fn key(item: &(K, V)) -> &K {
+        &item.0
+    }
+
+    fn from_sorted(vec: Vec<(K, V)>) -> Self {
+        AVLTreeMap::from_sorted(vec)
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn insert(&mut self, (key, value): (K, V)) {
+        AVLTreeMap::insert(self, key, value);
+    }
+}
+
+impl<K: Ord> Build for AVLTreeSet<K> {
+    type Key = K;
+    type Item = K;
+
+    fn key(item: &K) -> &K {
+        item
+    }
+
+    fn from_sorted(vec: Vec<K>) -> Self {
+        AVLTreeSet::from_sorted(vec)
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        AVLTreeSet::contains(self, key)
+    }
+
+    fn insert(&mut self, key: K) {
+        AVLTreeSet::insert(self, key);
+    }
+}
+
+impl<K: Ord, V> Build for Treap<K, V> {
+    type Key = K;
+    type Item = (K, V);
+
+    fn key(item: &(K, V)) -> &K {
+        &item.0
+    }
+
+    fn from_sorted(vec: Vec<(K, V)>) -> Self {
+        Treap::from_sorted(vec)
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn insert(&mut self, (key, value): (K, V)) {
+        Treap::insert(self, key, value);
+    }
+}
+
+/**
+ * 要素を順に受け取って木を組み立てる
+ * キーが狭義単調増加している間は配列に溜め、最後にまとめて O(n) で木を作る
+ * 途中で順序が崩れたら、それまでの要素から木を作り、残りは 1 つずつ挿入する
+ */
+fn build<T: Build, E: de::Error>(
+    mut next: impl FnMut() -> Result<Option<T::Item>, E>,
+    duplicate_keys: DuplicateKeys,
+    size_hint: Option<usize>,
+) -> Result<T, E> {
+    let mut sorted: Vec<T::Item> = Vec::with_capacity(size_hint.unwrap_or(0).min(4096));
+    let mut rest = None;
+    while let Some(item) = next()? {
+        if sorted
+            .last()
+            .is_none_or(|last| T::key(last) < T::key(&item))
+        {
+            sorted.push(item);
+        } else {
+            rest = Some(item);
+            break;
+        }
+    }
+
+    let mut tree = T::from_sorted(sorted);
+    while let Some(item) = rest {
+        if !tree.contains(T::key(&item)) || duplicate_keys == DuplicateKeys::KeepLast {
+            tree.insert(item);
+        } else if duplicate_keys == DuplicateKeys::Error {
+            return Err(E::custom("duplicate key"));
+        }
+        rest = next()?;
+    }
+    Ok(tree)
+}
+
+impl<K: Ord + Serialize, V: Serialize> Serialize for AVLTreeMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.size()))?;
+        for (key, value) in self {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<K: Ord + Serialize> Serialize for AVLTreeSet<K> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.size()))?;
+        for key in self {
+            seq.serialize_element(key)?;
+        }
+        seq.end()
+    }
+}
+
+impl<K: Ord + Serialize, V: Serialize> Serialize for Treap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.size()))?;
+        for (key, value) in self {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+struct MapVisitor<T> {
+    duplicate_keys: DuplicateKeys,
+    marker: PhantomData<T>,
+}
+impl<'de, K, V, T> Visitor<'de> for MapVisitor<T>
+where
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+    T: Build<Item = (K, V)>,
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<T, A::Error> {
+        let size_hint = map.size_hint();
+        build(|| map.next_entry(), self.duplicate_keys, size_hint)
+    }
+}
+
+struct SeqVisitor<T> {
+    duplicate_keys: DuplicateKeys,
+    marker: PhantomData<T>,
+}
+impl<'de, K, T> Visitor<'de> for SeqVisitor<T>
+where
+    K: Deserialize<'de>,
+    T: Build<Item = K>,
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let size_hint = seq.size_hint();
+        build(|| seq.next_element(), self.duplicate_keys, size_hint)
+    }
+}
+
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for AVLTreeMap<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::deserialize_with(deserializer, DuplicateKeys::default())
+    }
+}
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> AVLTreeMap<K, V> {
+    /**
+     * 同じキーが複数回現れたときの扱いを指定して逆直列化する
+     */
+    pub fn deserialize_with<D: Deserializer<'de>>(
+        deserializer: D,
+        duplicate_keys: DuplicateKeys,
+    ) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MapVisitor {
+            duplicate_keys,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<'de, K: Ord + Deserialize<'de>> Deserialize<'de> for AVLTreeSet<K> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::deserialize_with(deserializer, DuplicateKeys::default())
+    }
+}
+impl<'de, K: Ord + Deserialize<'de>> AVLTreeSet<K> {
+    /**
+     * 同じキーが複数回現れたときの扱いを指定して逆直列化する
+     */
+    pub fn deserialize_with<D: Deserializer<'de>>(
+        deserializer: D,
+        duplicate_keys: DuplicateKeys,
+    ) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(SeqVisitor {
+            duplicate_keys,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Treap<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::deserialize_with(deserializer, DuplicateKeys::default())
+    }
+}
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> Treap<K, V> {
+    /**
+     * 同じキーが複数回現れたときの扱いを指定して逆直列化する
+     */
+    pub fn deserialize_with<D: Deserializer<'de>>(
+        deserializer: D,
+        duplicate_keys: DuplicateKeys,
+    ) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MapVisitor {
+            duplicate_keys,
+            marker: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,242 @@
+use std::io::{self, Read, Write};
+
+use crate::invariant::InvariantViolation;
+
+/**
+ * スナップショットの先頭に置く識別子
+ */
+const MAGIC: [u8; 4] = *b"BSTS";
+/**
+ * 現在の形式の版
+ * 形式を変えたときは上げ、読み込み時に一致しなければ UnsupportedVersion を返す
+ */
+pub const VERSION: u16 = 1;
+
+/**
+ * スナップショットに保存した木の種類
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SnapshotKind {
+    AVLTreeMap = 0,
+    AVLTreeSet = 1,
+    Treap = 2,
+}
+
+/**
+ * スナップショットの読み込みに失敗した理由
+ */
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    // 先頭の識別子が一致しない
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    // 別の種類の木のスナップショットを読もうとした
+    KindMismatch { expected: SnapshotKind, found: u8 },
+    // index 番目の要素の平衡状態や深さ、priority が木の形として成り立たない
+    InvalidMetadata { index: u64 },
+    // 組み立てた木が不変条件を満たさない
+    InvalidTree(InvariantViolation),
+}
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "I/O error: {err}"),
+            SnapshotError::InvalidMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {version}")
+            }
+            SnapshotError::KindMismatch { expected, found } => {
+                write!(f, "expected a {expected:?} snapshot but found kind {found}")
+            }
+            SnapshotError::InvalidMetadata { index } => {
+                write!(f, "invalid metadata at entry {index}")
+            }
+            SnapshotError::InvalidTree(violation) => write!(f, "invalid tree: {violation}"),
+        }
+    }
+}
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            SnapshotError::InvalidTree(violation) => Some(violation),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+impl From<InvariantViolation> for SnapshotError {
+    fn from(violation: InvariantViolation) -> Self {
+        SnapshotError::InvalidTree(violation)
+    }
+}
+
+/**
+ * スナップショットに書き込めるキーと値
+ * 数値はリトルエンディアンの固定長、文字列や Vec は長さ (u64) に続けて中身を書く
+ */
+pub trait SnapshotValue: Sized {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self>;
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+macro_rules! impl_snapshot_value_for_number {
+    ($($t:ty),*) => {
+        $(
+            impl SnapshotValue for $t {
+                fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                    writer.write_all(&self.to_le_bytes())
+                }
+
+                fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+                    let mut bytes = [0; std::mem::size_of::<$t>()];
+                    reader.read_exact(&mut bytes)?;
+                    Ok(<$t>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+impl_snapshot_value_for_number!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+// 環境によって大きさが変わらないよう 64 ビットで書く
+impl SnapshotValue for usize {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u64).write_to(writer)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        usize::try_from(u64::read_from(reader)?).map_err(|_| invalid_data("usize out of range"))
+    }
+}
+impl SnapshotValue for isize {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as i64).write_to(writer)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        isize::try_from(i64::read_from(reader)?).map_err(|_| invalid_data("isize out of range"))
+    }
+}
+impl SnapshotValue for bool {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u8).write_to(writer)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        match u8::read_from(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("invalid bool")),
+        }
+    }
+}
+impl SnapshotValue for char {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u32).write_to(writer)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        char::from_u32(u32::read_from(reader)?).ok_or_else(|| invalid_data("invalid char"))
+    }
+}
+impl SnapshotValue for () {
+    fn write_to<W: Write>(&self, _writer: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn read_from<R: Read>(_reader: &mut R) -> io::Result<Self> {
+        Ok(())
+    }
+}
+impl SnapshotValue for String {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.len().write_to(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(reader)?;
+        let mut bytes = Vec::new();
+        reader.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(bytes).map_err(|_| invalid_data("invalid UTF-8"))
+    }
+}
+impl<T: SnapshotValue> SnapshotValue for Vec<T> {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.len().write_to(writer)?;
+        self.iter().try_for_each(|item| item.write_to(writer))
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(reader)?;
+        // 壊れた長さで巨大な領域を確保しないよう、先に確保する量は抑える
+        let mut vec = Vec::with_capacity(len.min(4096));
+        for _ in 0..len {
+            vec.push(T::read_from(reader)?);
+        }
+        Ok(vec)
+    }
+}
+impl<A: SnapshotValue, B: SnapshotValue> SnapshotValue for (A, B) {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.write_to(writer)?;
+        self.1.write_to(writer)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok((A::read_from(reader)?, B::read_from(reader)?))
+    }
+}
+
+/**
+ * 識別子、版、木の種類、要素数からなるヘッダを書く
+ */
+pub(crate) fn write_header<W: Write>(
+    writer: &mut W,
+    kind: SnapshotKind,
+    count: usize,
+) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    VERSION.write_to(writer)?;
+    (kind as u8).write_to(writer)?;
+    (count as u64).write_to(writer)
+}
+
+/**
+ * ヘッダを読んで検査し、要素数を返す
+ */
+pub(crate) fn read_header<R: Read>(
+    reader: &mut R,
+    kind: SnapshotKind,
+) -> Result<u64, SnapshotError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(SnapshotError::InvalidMagic);
+    }
+    let version = u16::read_from(reader)?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let found = u8::read_from(reader)?;
+    if found != kind as u8 {
+        return Err(SnapshotError::KindMismatch {
+            expected: kind,
+            found,
+        });
+    }
+    Ok(u64::read_from(reader)?)
+}
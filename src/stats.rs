@@ -0,0 +1,117 @@
+use crate::avl_tree::{AVLNode, ThreeWay};
+use crate::node::NodeTrait;
+
+/**
+ * 平衡状態ごとのノードの個数
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct BalanceCounts {
+    // 左の部分木の方が高いノード
+    pub left: usize,
+    // 左右の部分木の高さが等しいノード
+    pub equal: usize,
+    // 右の部分木の方が高いノード
+    pub right: usize,
+}
+impl BalanceCounts {
+    pub(crate) fn add(&mut self, state: ThreeWay) {
+        match state {
+            ThreeWay::Left => self.left += 1,
+            ThreeWay::Equal => self.equal += 1,
+            ThreeWay::Right => self.right += 1,
+        }
+    }
+}
+
+/**
+ * 木の形に関する統計
+ * 根の深さを 0、空の木の高さを 0 とし、高さは根から葉までの最長の経路に含まれるノードの個数で数える
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct TreeStats {
+    pub height: usize,
+    pub node_count: usize,
+    pub average_depth: f64,
+    pub max_depth: usize,
+    // AVL 木でない場合は None
+    pub balance: Option<BalanceCounts>,
+    // ノードに使っている領域の見積もり (バイト)
+    // キーや値が別に確保している領域やアロケータの管理領域は含まない
+    pub memory_bytes: usize,
+}
+impl TreeStats {
+    pub(crate) fn new(
+        node_count: usize,
+        depth_sum: usize,
+        height: usize,
+        balance: Option<BalanceCounts>,
+        memory_bytes: usize,
+    ) -> Self {
+        TreeStats {
+            height,
+            node_count,
+            average_depth: if node_count == 0 {
+                0.0
+            } else {
+                depth_sum as f64 / node_count as f64
+            },
+            max_depth: height.saturating_sub(1),
+            balance,
+            memory_bytes,
+        }
+    }
+}
+
+// 部分木の高さを返し、ノードの個数と深さの和を数えながら各ノードで visit を呼ぶ
+fn walk<N: NodeTrait>(
+    node: Option<&N>,
+    depth: usize,
+    count: &mut usize,
+    depth_sum: &mut usize,
+    visit: &mut impl FnMut(&N),
+) -> usize {
+    let node = match node {
+        Some(node) => node,
+        None => return 0,
+    };
+    *count += 1;
+    *depth_sum += depth;
+    visit(node);
+    let left = walk(node.left(), depth + 1, count, depth_sum, visit);
+    let right = walk(node.right(), depth + 1, count, depth_sum, visit);
+    left.max(right) + 1
+}
+
+/**
+ * 根から木全体をたどって統計を求める
+ * 領域はノード 1 個の大きさ × ノードの個数で見積もる
+ */
+pub(crate) fn tree_stats<N: NodeTrait>(root: Option<&N>) -> TreeStats {
+    let (mut count, mut depth_sum) = (0, 0);
+    let height = walk(root, 0, &mut count, &mut depth_sum, &mut |_| {});
+    TreeStats::new(
+        count,
+        depth_sum,
+        height,
+        None,
+        count * std::mem::size_of::<N>(),
+    )
+}
+
+/**
+ * tree_stats に加えて、平衡状態ごとのノードの個数を数える
+ */
+pub(crate) fn avl_tree_stats<N: AVLNode>(root: Option<&N>) -> TreeStats {
+    let (mut count, mut depth_sum) = (0, 0);
+    let mut balance = BalanceCounts::default();
+    let height = walk(root, 0, &mut count, &mut depth_sum, &mut |node: &N| {
+        balance.add(node.state())
+    });
+    TreeStats::new(
+        count,
+        depth_sum,
+        height,
+        Some(balance),
+        count * std::mem::size_of::<N>(),
+    )
+}
@@ -0,0 +1,117 @@
+use crate::avl_tree::AVLTreeMap;
+
+// 取り消しのために記録する操作
+enum Undo<K, V> {
+    // 新しく挿入したキー。取り消すときは取り除く
+    Inserted(K),
+    // 置き換えたり取り除いたりした値。取り消すときは元に戻す
+    Displaced(K, V),
+}
+
+/**
+ * AVLTreeMap に対する一連の挿入と削除をまとめて取り消せるようにするトランザクション
+ * 操作ごとに、取り消しに必要なキーと、置き換えや削除で木から外れた値を記録する
+ * commit せずに破棄した場合 (パニックによる巻き戻しを含む) は rollback と同じく元の内容に戻す
+ */
+pub struct Transaction<'a, K: Ord + Clone, V> {
+    tree: &'a mut AVLTreeMap<K, V>,
+    undo_log: Vec<Undo<K, V>>,
+}
+impl<'a, K: Ord + Clone, V> Transaction<'a, K, V> {
+    pub(crate) fn new(tree: &'a mut AVLTreeMap<K, V>) -> Self {
+        Transaction {
+            tree,
+            undo_log: Vec::new(),
+        }
+    }
+
+    /**
+     * AVLTreeMap::insert と同じ
+     * 置き換えた値は rollback に備えて保持する
+     */
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        let undo_key = key.clone();
+        match self.tree.replace(key, value) {
+            Some(old) => {
+                self.undo_log.push(Undo::Displaced(undo_key, old));
+                false
+            }
+            None => {
+                self.undo_log.push(Undo::Inserted(undo_key));
+                true
+            }
+        }
+    }
+
+    /**
+     * key を取り除き、取り除いた値への参照を返す
+     * 値は rollback に備えてトランザクションが保持する
+     */
+    pub fn remove(&mut self, key: &K) -> Option<&V> {
+        let value = self.tree.remove(key)?;
+        self.undo_log.push(Undo::Displaced(key.clone(), value));
+        match self.undo_log.last() {
+            Some(Undo::Displaced(_, value)) => Some(value),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.tree.get(key)
+    }
+
+    pub fn size(&self) -> usize {
+        self.tree.size()
+    }
+
+    /**
+     * 途中の状態の木を返す
+     * 検証などの読み出しに使う
+     */
+    pub fn tree(&self) -> &AVLTreeMap<K, V> {
+        self.tree
+    }
+
+    /**
+     * 記録した操作の個数を返す
+     */
+    pub fn len(&self) -> usize {
+        self.undo_log.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.undo_log.is_empty()
+    }
+
+    /**
+     * 変更を確定し、保持していた値を破棄する
+     */
+    pub fn commit(mut self) {
+        self.undo_log.clear();
+    }
+
+    /**
+     * 記録した操作を新しい順に取り消し、開始時の内容に戻す
+     */
+    pub fn rollback(mut self) {
+        self.undo_all();
+    }
+
+    fn undo_all(&mut self) {
+        while let Some(undo) = self.undo_log.pop() {
+            match undo {
+                Undo::Inserted(key) => {
+                    self.tree.remove(&key);
+                }
+                Undo::Displaced(key, value) => {
+                    self.tree.replace(key, value);
+                }
+            }
+        }
+    }
+}
+impl<K: Ord + Clone, V> Drop for Transaction<'_, K, V> {
+    fn drop(&mut self) {
+        self.undo_all();
+    }
+}
@@ -1,6 +1,14 @@
+use std::io::{self, Read, Write};
+use std::iter::FromIterator;
+
 use rand::random;
 
-use crate::node::NodeTrait;
+use crate::dot::to_dot;
+use crate::invariant::{validate_treap, InvariantViolation};
+use crate::node::{NodePointer, NodeTrait};
+use crate::pretty::fmt_tree;
+use crate::snapshot::{read_header, write_header, SnapshotError, SnapshotKind, SnapshotValue};
+use crate::stats::{tree_stats, TreeStats};
 
 struct Node<K, V> {
     key: K,
@@ -14,6 +22,8 @@ type NodePtrInner<K, V> = Option<Box<Node<K, V>>>;
 struct NodePtr<K, V>(NodePtrInner<K, V>);
 
 impl<K, V> NodeTrait for Node<K, V> {
+    type Ptr = Box<Self>;
+
     fn size(&self) -> usize {
         self.size
     }
@@ -30,6 +40,14 @@ impl<K, V> NodeTrait for Node<K, V> {
         self.right.0.as_deref()
     }
 
+    fn left_mut(&mut self) -> Option<&mut Self> {
+        self.left.0.as_deref_mut()
+    }
+
+    fn right_mut(&mut self) -> Option<&mut Self> {
+        self.right.0.as_deref_mut()
+    }
+
     fn take_left(&mut self) -> Option<Box<Self>> {
         self.left.0.take()
     }
@@ -46,6 +64,17 @@ impl<K, V> NodeTrait for Node<K, V> {
         self.right = NodePtr(node);
     }
 }
+impl<K: Ord, V> TreapNode for Node<K, V> {
+    type Key = K;
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn priority(&self) -> f64 {
+        self.priority
+    }
+}
 impl<K: Ord, V> Node<K, V> {
     fn new(key: K, value: V) -> Self {
         Node {
@@ -58,9 +87,378 @@ impl<K: Ord, V> Node<K, V> {
         }
     }
 }
+
+// to_dot や fmt_tree で各ノードに表示する内容
+// 最初の行がキーで、残りの行が補足の情報になる
+fn node_label<K: std::fmt::Debug, V>(node: &Node<K, V>) -> Vec<String> {
+    vec![
+        format!("{:?}", node.key),
+        format!("size: {}", node.size),
+        format!("priority: {:.4}", node.priority),
+    ]
+}
+
+// キーの昇順に並んだノードから木を O(n) で組み立てる
+// 根から右端までのノードを積み、右の子はまだつながない
+// 新しいノードより priority が小さいノードは新しいノードの左の部分木に入る
+fn push_sorted<K, V>(spine: &mut Vec<Box<Node<K, V>>>, mut node: Box<Node<K, V>>) {
+    let mut last = None;
+    while spine.last().is_some_and(|top| top.priority < node.priority) {
+        let mut top = spine.pop().unwrap();
+        top.set_right(last);
+        top.update();
+        last = Some(top);
+    }
+    node.set_left(last);
+    spine.push(node);
+}
+
+fn finish_sorted<K, V>(mut spine: Vec<Box<Node<K, V>>>) -> NodePtrInner<K, V> {
+    let mut last = None;
+    while let Some(mut top) = spine.pop() {
+        top.set_right(last);
+        top.update();
+        last = Some(top);
+    }
+    last
+}
+
+impl<K: SnapshotValue, V: SnapshotValue> NodePtr<K, V> {
+    // 間順に、priority に続けてキーと値を書く
+    fn write_snapshot_entries<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if let Some(node) = &self.0 {
+            node.left.write_snapshot_entries(writer)?;
+            node.priority.write_to(writer)?;
+            node.key.write_to(writer)?;
+            node.value.write_to(writer)?;
+            node.right.write_snapshot_entries(writer)?;
+        }
+        Ok(())
+    }
+
+    // 間順に並んだ要素とその priority から、木の形は一意に決まる
+    fn read_snapshot_entries<R: Read>(reader: &mut R, count: u64) -> Result<Self, SnapshotError> {
+        let mut spine = Vec::new();
+        for index in 0..count {
+            let priority = f64::read_from(reader)?;
+            if !priority.is_finite() {
+                return Err(SnapshotError::InvalidMetadata { index });
+            }
+            let node = Node {
+                key: K::read_from(reader)?,
+                value: V::read_from(reader)?,
+                left: NodePtr(None),
+                right: NodePtr(None),
+                size: 1,
+                priority,
+            };
+            push_sorted(&mut spine, Box::new(node));
+        }
+        Ok(NodePtr(finish_sorted(spine)))
+    }
+}
+
+/**
+ * Treap の操作に必要なノードの性質
+ * priority が大きいノードほど根に近くなる
+ */
+pub(crate) trait TreapNode: NodeTrait {
+    type Key: Ord;
+    fn key(&self) -> &Self::Key;
+    fn priority(&self) -> f64;
+}
+
+/**
+ * key 未満のキーを持つ木と key 以上のキーを持つ木に分割する
+ */
+pub(crate) fn split<P: NodePointer<N>, N: TreapNode<Ptr = P>>(
+    ptr: Option<P>,
+    key: &N::Key,
+) -> (Option<P>, Option<P>) {
+    let mut ptr = match ptr {
+        Some(ptr) => ptr,
+        None => return (None, None),
+    };
+    let node = P::make_mut(&mut ptr);
+    node.push();
+
+    if node.key() < key {
+        let (left, right) = split(node.take_right(), key);
+        node.set_right(left);
+        node.update();
+        (Some(ptr), right)
+    } else {
+        let (left, right) = split(node.take_left(), key);
+        node.set_left(right);
+        node.update();
+        (left, Some(ptr))
+    }
+}
+
+/**
+ * left のすべてのキーが right のすべてのキーより小さいときに、2 つの木を連結する
+ */
+pub(crate) fn merge<P: NodePointer<N>, N: TreapNode<Ptr = P>>(
+    left: Option<P>,
+    right: Option<P>,
+) -> Option<P> {
+    match (left, right) {
+        (None, right) => right,
+        (left, None) => left,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority() > right.priority() {
+                let node = P::make_mut(&mut left);
+                node.push();
+                let merged = merge(node.take_right(), Some(right));
+                node.set_right(merged);
+                node.update();
+                Some(left)
+            } else {
+                let node = P::make_mut(&mut right);
+                node.push();
+                let merged = merge(Some(left), node.take_left());
+                node.set_left(merged);
+                node.update();
+                Some(right)
+            }
+        }
+    }
+}
+
+/**
+ * key の位置を探し、見つかった場合はそのノードを、見つからなかった場合は None を渡して f を呼ぶ
+ * 見つからなかったときに f が返したノードが挿入され、回転によってヒープ条件が保たれる
+ * 返り値は挿入されたかどうか
+ */
+pub(crate) fn insert_rec<P, N, F>(ptr: &mut Option<P>, key: N::Key, f: F) -> bool
+where
+    P: NodePointer<N>,
+    N: TreapNode<Ptr = P>,
+    F: FnOnce(N::Key, Option<&mut N>) -> Option<N>,
+{
+    let node = match ptr {
+        Some(node) => P::make_mut(node),
+        None => {
+            return match f(key, None) {
+                Some(node) => {
+                    *ptr = Some(P::new(node));
+                    true
+                }
+                None => false,
+            };
+        }
+    };
+    node.push();
+
+    match key.cmp(node.key()) {
+        std::cmp::Ordering::Less => {
+            let mut left = node.take_left();
+            let inserted = insert_rec(&mut left, key, f);
+            node.set_left(left);
+            node.update();
+            if inserted && node.left().unwrap().priority() > node.priority() {
+                node.rotate_right();
+            }
+            inserted
+        }
+        std::cmp::Ordering::Equal => {
+            f(key, Some(node));
+            node.update();
+            false
+        }
+        std::cmp::Ordering::Greater => {
+            let mut right = node.take_right();
+            let inserted = insert_rec(&mut right, key, f);
+            node.set_right(right);
+            node.update();
+            if inserted && node.right().unwrap().priority() > node.priority() {
+                node.rotate_left();
+            }
+            inserted
+        }
+    }
+}
+
+/**
+ * key を持つノードを木から取り外して返す
+ */
+pub(crate) fn remove_rec<P: NodePointer<N>, N: TreapNode<Ptr = P>>(
+    ptr: &mut Option<P>,
+    key: &N::Key,
+) -> Option<P> {
+    let node = P::make_mut(ptr.as_mut()?);
+    node.push();
+
+    match key.cmp(node.key()) {
+        std::cmp::Ordering::Less => {
+            let mut left = node.take_left();
+            let removed = remove_rec(&mut left, key);
+            node.set_left(left);
+            node.update();
+            removed
+        }
+        std::cmp::Ordering::Greater => {
+            let mut right = node.take_right();
+            let removed = remove_rec(&mut right, key);
+            node.set_right(right);
+            node.update();
+            removed
+        }
+        std::cmp::Ordering::Equal => {
+            let mut removed = ptr.take().unwrap();
+            let removed_mut = P::make_mut(&mut removed);
+            *ptr = merge(removed_mut.take_left(), removed_mut.take_right());
+            removed_mut.update();
+            Some(removed)
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a NodePtr<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(&node.left);
+            vec.push((&node.key, &node.value));
+            vec.extend(&node.right);
+        }
+        vec.into_iter()
+    }
+}
+impl<K, V> NodePtr<K, V> {
+    fn size(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.size)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn keys(&self) -> Vec<&K> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.keys());
+            vec.push(&node.key);
+            vec.extend(node.right.keys());
+        }
+        vec
+    }
+
+    fn values(&self) -> Vec<&V> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.values());
+            vec.push(&node.value);
+            vec.extend(node.right.values());
+        }
+        vec
+    }
+}
 impl<K: Ord, V> NodePtr<K, V> {
-    fn new(key: K, value: V) -> Self {
-        NodePtr(Some(Box::new(Node::new(key, value))))
+    fn insert(&mut self, key: K, value: V) -> bool {
+        insert_rec(&mut self.0, key, |key, node| match node {
+            Some(node) => {
+                node.value = value;
+                None
+            }
+            None => Some(Node::new(key, value)),
+        })
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        remove_rec(&mut self.0, key).map(|node| node.value)
+    }
+
+    fn search(&self, key: &K) -> Option<&V> {
+        let mut node = self.0.as_ref()?;
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_ref()?,
+                std::cmp::Ordering::Greater => node.right.0.as_ref()?,
+                std::cmp::Ordering::Equal => break Some(&node.value),
+            };
+        }
+    }
+
+    fn search_mut(&mut self, key: &K) -> Option<&mut V> {
+        let mut node = self.0.as_mut()?;
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_mut()?,
+                std::cmp::Ordering::Greater => node.right.0.as_mut()?,
+                std::cmp::Ordering::Equal => break Some(&mut node.value),
+            };
+        }
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.left.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.right.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
+        let mut node = self.0.as_ref().unwrap();
+        loop {
+            match node.left.size().cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= node.left.size() + 1;
+                    node = node.right.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Greater => {
+                    node = node.left.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Equal => {
+                    break Some((&node.key, &node.value));
+                }
+            }
+        }
+    }
+
+    fn order_of_key(&self, key: &K) -> usize {
+        let mut order = 0;
+        let mut node = match self.0.as_ref() {
+            None => return 0,
+            Some(node) => node,
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    order += node.left.size() + 1;
+                    node = match node.right.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break order + node.left.size();
+                }
+            }
+        }
     }
 }
 
@@ -69,7 +467,170 @@ pub struct Treap<K: Ord, V> {
 }
 impl<K: Ord, V> Treap<K, V> {
     pub fn new() -> Self {
-        Treap { root: NodePtr(None) }
+        Treap {
+            root: NodePtr(None),
+        }
+    }
+
+    /**
+     * キーの昇順に並んだ重複のない要素から O(n) で木を作る
+     */
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_sorted(vec: Vec<(K, V)>) -> Self {
+        debug_assert!(vec.windows(2).all(|w| w[0].0 < w[1].0));
+        let mut spine = Vec::new();
+        for (key, value) in vec {
+            push_sorted(&mut spine, Box::new(Node::new(key, value)));
+        }
+        Treap {
+            root: NodePtr(finish_sorted(spine)),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        self.root.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.root.remove(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.search(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.root.search_mut(key)
+    }
+
+    pub fn min(&self) -> Option<(&K, &V)> {
+        self.root.min()
+    }
+
+    pub fn max(&self) -> Option<(&K, &V)> {
+        self.root.max()
+    }
+
+    pub fn size(&self) -> usize {
+        self.root.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    pub fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        self.root.find_by_order(order)
+    }
+
+    /**
+     * key 未満である要素の個数を返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        self.root.order_of_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<&K> {
+        self.root.keys()
+    }
+
+    pub fn values(&self) -> Vec<&V> {
+        self.root.values()
+    }
+
+    /**
+     * key 以上の要素を取り除き、それらからなる Treap を返す
+     */
+    pub fn split_off(&mut self, key: &K) -> Self {
+        let (left, right) = split(self.root.0.take(), key);
+        self.root = NodePtr(left);
+        Treap {
+            root: NodePtr(right),
+        }
+    }
+
+    /**
+     * other のすべての要素を末尾に連結する
+     * other のキーはすべて self のキーより大きくなければならず、そうでない場合は panic する
+     */
+    pub fn merge(&mut self, other: Self) {
+        assert!(
+            match (self.max(), other.min()) {
+                (Some((max, _)), Some((min, _))) => max < min,
+                _ => true,
+            },
+            "keys of other must be greater than all keys of self"
+        );
+        self.root = NodePtr(merge(self.root.0.take(), other.root.0));
+    }
+
+    /**
+     * 木の不変条件がすべて成り立っているかを確かめる
+     * 成り立っていない場合は、違反のあったノードの根からの経路とその内容を返す
+     */
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        validate_treap(self.root.0.as_deref())
+    }
+
+    /**
+     * 高さやノードの深さなど、木の形に関する統計を返す
+     */
+    pub fn stats(&self) -> TreeStats {
+        tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + SnapshotValue, V: SnapshotValue> Treap<K, V> {
+    /**
+     * 木の形と各ノードの priority を保ったまま、要素をキーの昇順に書き出す
+     */
+    pub fn write_snapshot<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_header(writer, SnapshotKind::Treap, self.size())?;
+        self.root.write_snapshot_entries(writer)
+    }
+
+    /**
+     * write_snapshot で書き出した木を、そのままの形で読み込む
+     * 読み込んだ木が不変条件を満たさない場合はエラーを返す
+     */
+    pub fn read_snapshot<R: Read>(mut reader: R) -> Result<Self, SnapshotError> {
+        let count = read_header(&mut reader, SnapshotKind::Treap)?;
+        let tree = Treap {
+            root: NodePtr::read_snapshot_entries(&mut reader, count)?,
+        };
+        tree.validate()?;
+        Ok(tree)
+    }
+}
+impl<K: Ord + std::fmt::Debug, V> Treap<K, V> {
+    /**
+     * 木の形を Graphviz の DOT 形式で返す
+     * 各ノードにはキー、部分木の大きさ、priority を表示する
+     */
+    pub fn to_dot(&self) -> String {
+        to_dot(self.root.0.as_deref(), node_label)
+    }
+
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに部分木の大きさと priority を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
+}
+impl<'a, K: Ord, V> IntoIterator for &'a Treap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.root.into_iter()
     }
 }
 impl<K: Ord, V> Default for Treap<K, V> {
@@ -77,3 +638,12 @@ impl<K: Ord, V> Default for Treap<K, V> {
         Self::new()
     }
 }
+impl<K: Ord, V> FromIterator<(K, V)> for Treap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
@@ -0,0 +1,519 @@
+use std::iter::FromIterator;
+
+use crate::avl_tree::{insert_rec, remove_rec, AVLNode, ThreeWay};
+use crate::node::NodeTrait;
+use crate::pretty::fmt_tree;
+use crate::stats::{avl_tree_stats, TreeStats};
+
+type NodePtrInner<K, V> = Option<Box<Node<K, V>>>;
+struct NodePtr<K, V>(NodePtrInner<K, V>);
+struct Node<K, V> {
+    key: K,
+    value: V,
+    weight: u64,
+    left: NodePtr<K, V>,
+    right: NodePtr<K, V>,
+    size: usize,
+    // 部分木に含まれる要素の重みの総和
+    weight_sum: u64,
+    state: ThreeWay,
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V, weight: u64) -> Self {
+        Node {
+            key,
+            value,
+            weight,
+            left: NodePtr(None),
+            right: NodePtr(None),
+            size: 1,
+            weight_sum: weight,
+            state: ThreeWay::Equal,
+        }
+    }
+}
+
+// fmt_tree で各ノードに表示する内容
+// 最初の行がキーで、残りの行が補足の情報になる
+fn node_label<K: std::fmt::Debug, V>(node: &Node<K, V>) -> Vec<String> {
+    vec![
+        format!("{:?}", node.key),
+        format!("weight: {}", node.weight),
+        format!("size: {}", node.size),
+        format!("state: {:?}", node.state),
+    ]
+}
+
+impl<K, V> NodeTrait for Node<K, V> {
+    type Ptr = Box<Self>;
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn size_mut(&mut self) -> &mut usize {
+        &mut self.size
+    }
+
+    fn left(&self) -> Option<&Self> {
+        self.left.0.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.0.as_deref()
+    }
+
+    fn left_mut(&mut self) -> Option<&mut Self> {
+        self.left.0.as_deref_mut()
+    }
+
+    fn right_mut(&mut self) -> Option<&mut Self> {
+        self.right.0.as_deref_mut()
+    }
+
+    fn take_left(&mut self) -> Option<Box<Self>> {
+        self.left.0.take()
+    }
+
+    fn take_right(&mut self) -> Option<Box<Self>> {
+        self.right.0.take()
+    }
+
+    fn set_left(&mut self, node: Option<Box<Self>>) {
+        self.left.0 = node;
+    }
+
+    fn set_right(&mut self, node: Option<Box<Self>>) {
+        self.right.0 = node;
+    }
+
+    fn update(&mut self) {
+        self.size = self.left.size() + self.right.size() + 1;
+        self.weight_sum = self.left.weight_sum() + self.right.weight_sum() + self.weight;
+    }
+}
+impl<K: Ord, V> AVLNode for Node<K, V> {
+    type Key = K;
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn state(&self) -> ThreeWay {
+        self.state
+    }
+
+    fn state_mut(&mut self) -> &mut ThreeWay {
+        &mut self.state
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a NodePtr<K, V> {
+    type Item = (&'a K, &'a V, u64);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(&node.left);
+            vec.push((&node.key, &node.value, node.weight));
+            vec.extend(&node.right);
+        }
+        vec.into_iter()
+    }
+}
+impl<K, V> NodePtr<K, V> {
+    fn size(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.size)
+    }
+
+    fn weight_sum(&self) -> u64 {
+        self.0.as_ref().map_or(0, |node| node.weight_sum)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn keys(&self) -> Vec<&K> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.keys());
+            vec.push(&node.key);
+            vec.extend(node.right.keys());
+        }
+        vec
+    }
+
+    fn values(&self) -> Vec<&V> {
+        let mut vec = Vec::new();
+        if let Some(node) = &self.0 {
+            vec.extend(node.left.values());
+            vec.push(&node.value);
+            vec.extend(node.right.values());
+        }
+        vec
+    }
+}
+
+impl<K: Ord, V> NodePtr<K, V> {
+    fn insert(&mut self, key: K, value: V, weight: u64) -> bool {
+        insert_rec(&mut self.0, key, |key, node| match node {
+            Some(node) => {
+                node.value = value;
+                node.weight = weight;
+                None
+            }
+            None => Some(Node::new(key, value, weight)),
+        })
+        .0
+    }
+
+    fn remove(&mut self, key: &K) -> Option<(V, u64)> {
+        remove_rec(&mut self.0, key)
+            .0
+            .map(|node| (node.value, node.weight))
+    }
+
+    // 重みを書き換えたあと、根までの重みの総和を計算し直す
+    fn set_weight(&mut self, key: &K, weight: u64) -> Option<u64> {
+        let node = self.0.as_mut()?;
+        let ret = match key.cmp(&node.key) {
+            std::cmp::Ordering::Less => node.left.set_weight(key, weight),
+            std::cmp::Ordering::Greater => node.right.set_weight(key, weight),
+            std::cmp::Ordering::Equal => Some(std::mem::replace(&mut node.weight, weight)),
+        };
+        node.update();
+        ret
+    }
+
+    fn search(&self, key: &K) -> Option<&Node<K, V>> {
+        let mut node = self.0.as_ref()?;
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_ref()?,
+                std::cmp::Ordering::Greater => node.right.0.as_ref()?,
+                std::cmp::Ordering::Equal => break Some(node),
+            };
+        }
+    }
+
+    fn search_mut(&mut self, key: &K) -> Option<&mut V> {
+        let mut node = self.0.as_mut()?;
+        loop {
+            node = match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => node.left.0.as_mut()?,
+                std::cmp::Ordering::Greater => node.right.0.as_mut()?,
+                std::cmp::Ordering::Equal => break Some(&mut node.value),
+            };
+        }
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.left.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        let mut node = self.0.as_ref()?;
+        while let Some(nxt) = node.right.0.as_ref() {
+            node = nxt;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        if self.size() <= order {
+            return None;
+        }
+
+        let mut rest = order;
+        let mut node = self.0.as_ref().unwrap();
+        loop {
+            match node.left.size().cmp(&rest) {
+                std::cmp::Ordering::Less => {
+                    rest -= node.left.size() + 1;
+                    node = node.right.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Greater => {
+                    node = node.left.0.as_ref().unwrap();
+                }
+                std::cmp::Ordering::Equal => {
+                    break Some((&node.key, &node.value));
+                }
+            }
+        }
+    }
+
+    fn order_of_key(&self, key: &K) -> usize {
+        let mut order = 0;
+        let mut node = match self.0.as_ref() {
+            None => return 0,
+            Some(node) => node,
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    order += node.left.size() + 1;
+                    node = match node.right.0.as_ref() {
+                        Some(node) => node,
+                        None => break order,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break order + node.left.size();
+                }
+            }
+        }
+    }
+
+    fn find_by_weight(&self, weight: u64) -> Option<(&K, &V)> {
+        if self.weight_sum() <= weight {
+            return None;
+        }
+
+        let mut rest = weight;
+        let mut node = self.0.as_ref().unwrap();
+        loop {
+            if rest < node.left.weight_sum() {
+                node = node.left.0.as_ref().unwrap();
+                continue;
+            }
+            rest -= node.left.weight_sum();
+            if rest < node.weight {
+                break Some((&node.key, &node.value));
+            }
+            rest -= node.weight;
+            node = node.right.0.as_ref().unwrap();
+        }
+    }
+
+    fn weighted_rank(&self, key: &K) -> u64 {
+        let mut rank = 0;
+        let mut node = match self.0.as_ref() {
+            None => return 0,
+            Some(node) => node,
+        };
+        loop {
+            match key.cmp(&node.key) {
+                std::cmp::Ordering::Less => {
+                    node = match node.left.0.as_ref() {
+                        Some(node) => node,
+                        None => break rank,
+                    };
+                }
+                std::cmp::Ordering::Greater => {
+                    rank += node.left.weight_sum() + node.weight;
+                    node = match node.right.0.as_ref() {
+                        Some(node) => node,
+                        None => break rank,
+                    };
+                }
+                std::cmp::Ordering::Equal => {
+                    break rank + node.left.weight_sum();
+                }
+            }
+        }
+    }
+}
+
+/**
+ * 重みの総和が u64 の範囲を超えるため、挿入や重みの変更を行わなかったことを表す
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WeightOverflow;
+impl std::fmt::Display for WeightOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "total weight exceeds u64::MAX")
+    }
+}
+impl std::error::Error for WeightOverflow {}
+
+/**
+ * 各要素に整数の重みを持たせた AVL 木
+ * 重みの累積和に関する順序統計量を O(log n) で求められる
+ */
+pub struct WeightedAVLTreeMap<K: Ord, V> {
+    root: NodePtr<K, V>,
+}
+impl<K: Ord, V> WeightedAVLTreeMap<K, V> {
+    pub fn new() -> Self {
+        WeightedAVLTreeMap {
+            root: NodePtr(None),
+        }
+    }
+
+    /**
+     * key が既に存在する場合は値と重みを上書きし、false を返す
+     * 重みの総和が u64 に収まらなくなる場合は panic する
+     */
+    pub fn insert(&mut self, key: K, value: V, weight: u64) -> bool {
+        self.try_insert(key, value, weight)
+            .expect("total weight overflow")
+    }
+
+    /**
+     * insert と同じだが、重みの総和が u64 に収まらなくなる場合は何も変更せずに Err を返す
+     */
+    pub fn try_insert(&mut self, key: K, value: V, weight: u64) -> Result<bool, WeightOverflow> {
+        self.check_total(&key, weight)?;
+        Ok(self.root.insert(key, value, weight))
+    }
+
+    /**
+     * 削除した要素の値と重みを返す
+     */
+    pub fn remove(&mut self, key: &K) -> Option<(V, u64)> {
+        self.root.remove(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.search(key).map(|node| &node.value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.root.search_mut(key)
+    }
+
+    pub fn weight(&self, key: &K) -> Option<u64> {
+        self.root.search(key).map(|node| node.weight)
+    }
+
+    /**
+     * key の重みを書き換え、元の重みを返す
+     * key が存在しない場合は None を返す
+     * 重みの総和が u64 に収まらなくなる場合は panic する
+     */
+    pub fn set_weight(&mut self, key: &K, weight: u64) -> Option<u64> {
+        self.try_set_weight(key, weight)
+            .expect("total weight overflow")
+    }
+
+    /**
+     * set_weight と同じだが、重みの総和が u64 に収まらなくなる場合は何も変更せずに Err を返す
+     */
+    pub fn try_set_weight(&mut self, key: &K, weight: u64) -> Result<Option<u64>, WeightOverflow> {
+        if self.weight(key).is_none() {
+            return Ok(None);
+        }
+        self.check_total(key, weight)?;
+        Ok(self.root.set_weight(key, weight))
+    }
+
+    // key の重みを weight にしたときに重みの総和が u64 に収まるかを調べる
+    // 部分木の重みの総和は全体の総和以下なので、全体だけを調べれば足りる
+    fn check_total(&self, key: &K, weight: u64) -> Result<(), WeightOverflow> {
+        let rest = self.total_weight() - self.weight(key).unwrap_or(0);
+        rest.checked_add(weight).map(|_| ()).ok_or(WeightOverflow)
+    }
+
+    pub fn min(&self) -> Option<(&K, &V)> {
+        self.root.min()
+    }
+
+    pub fn max(&self) -> Option<(&K, &V)> {
+        self.root.max()
+    }
+
+    pub fn size(&self) -> usize {
+        self.root.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        self.root.weight_sum()
+    }
+
+    pub fn find_by_order(&self, order: usize) -> Option<(&K, &V)> {
+        self.root.find_by_order(order)
+    }
+
+    /**
+     * key 未満である要素の個数を返す
+     */
+    pub fn order_of_key(&self, key: &K) -> usize {
+        self.root.order_of_key(key)
+    }
+
+    /**
+     * キーの昇順に重みを並べたとき、累積の重み weight を含む要素を返す
+     * つまり、自身より小さいキーの重みの総和が weight 以下で、
+     * それに自身の重みを足すと weight より大きくなる要素を返す
+     */
+    pub fn find_by_weight(&self, weight: u64) -> Option<(&K, &V)> {
+        self.root.find_by_weight(weight)
+    }
+
+    /**
+     * key 未満である要素の重みの総和を返す
+     */
+    pub fn weighted_rank(&self, key: &K) -> u64 {
+        self.root.weighted_rank(key)
+    }
+
+    pub fn keys(&self) -> Vec<&K> {
+        self.root.keys()
+    }
+
+    pub fn values(&self) -> Vec<&V> {
+        self.root.values()
+    }
+
+    /**
+     * 高さ、ノードの深さ、平衡状態ごとのノードの個数など、木の形に関する統計を返す
+     */
+    pub fn stats(&self) -> TreeStats {
+        avl_tree_stats(self.root.0.as_deref())
+    }
+}
+impl<K: Ord + std::fmt::Debug, V> WeightedAVLTreeMap<K, V> {
+    /**
+     * 木を右の部分木が上、左の部分木が下になるように横向きに描いた文字列を返す
+     * annotated が true なら、各ノードに重み、部分木の大きさと平衡状態を添える
+     */
+    pub fn fmt_tree(&self, annotated: bool) -> String {
+        fmt_tree(self.root.0.as_deref(), annotated, node_label)
+    }
+
+    /**
+     * fmt_tree で描いた木を標準出力に書き出す
+     */
+    pub fn print_tree(&self, annotated: bool) {
+        print!("{}", self.fmt_tree(annotated));
+    }
+}
+impl<'a, K: Ord, V> IntoIterator for &'a WeightedAVLTreeMap<K, V> {
+    type Item = (&'a K, &'a V, u64);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.root.into_iter()
+    }
+}
+impl<K: Ord, V> Default for WeightedAVLTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord, V> FromIterator<(K, V, u64)> for WeightedAVLTreeMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V, u64)>>(iter: T) -> Self {
+        let mut tree = Self::new();
+        for (key, value, weight) in iter {
+            tree.insert(key, value, weight);
+        }
+        tree
+    }
+}
@@ -0,0 +1,221 @@
+use binary_search_tree::arena_avl_tree::ArenaAVLTreeMap;
+use binary_search_tree::avl_tree::AVLTreeMap;
+
+#[test]
+fn insert_test() {
+    let mut tree = ArenaAVLTreeMap::new();
+    assert!(tree.insert(3, 'c'));
+    assert!(tree.insert(1, 'a'));
+    assert!(tree.insert(2, 'b'));
+    assert!(!tree.insert(2, 'x'));
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(1, 'a'), (2, 'x'), (3, 'c')]
+    );
+}
+
+#[test]
+fn remove_test() {
+    let mut tree: ArenaAVLTreeMap<_, _> = (0..10).map(|i| (i, i * i)).collect();
+    assert_eq!(tree.remove(&3), Some(9));
+    assert_eq!(tree.remove(&3), None);
+    assert_eq!(tree.size(), 9);
+    assert_eq!(tree.get(&3), None);
+    assert_eq!(tree.get(&4), Some(&16));
+}
+
+#[test]
+fn get_mut_test() {
+    let mut tree: ArenaAVLTreeMap<_, _> = (0..10).map(|i| (i, i)).collect();
+    *tree.get_mut(&5).unwrap() = 50;
+    assert_eq!(tree.get(&5), Some(&50));
+    assert_eq!(tree.get_mut(&10), None);
+}
+
+#[test]
+fn min_max_test() {
+    let mut tree = ArenaAVLTreeMap::new();
+    assert_eq!(tree.min(), None);
+    tree.insert(5, 'a');
+    tree.insert(1, 'b');
+    tree.insert(9, 'c');
+    assert_eq!(tree.min(), Some((&1, &'b')));
+    assert_eq!(tree.max(), Some((&9, &'c')));
+}
+
+#[test]
+fn order_statistics_test() {
+    let tree: ArenaAVLTreeMap<_, _> = (0..50).map(|i| (i * 2, i)).collect();
+    assert_eq!(tree.find_by_order(10), Some((&20, &10)));
+    assert_eq!(tree.find_by_order(50), None);
+    assert_eq!(tree.order_of_key(&21), 11);
+    assert_eq!(tree.order_of_key(&20), 10);
+    assert_eq!(tree.order_of_key(&1000), 50);
+}
+
+#[test]
+fn capacity_test() {
+    let mut tree = ArenaAVLTreeMap::with_capacity(100);
+    assert!(tree.capacity() >= 100);
+    for i in 0..100 {
+        tree.insert(i, i);
+    }
+    for i in 0..50 {
+        tree.remove(&i);
+    }
+    // 空いた場所が再利用される
+    assert!(tree.capacity() >= 100);
+    tree.reserve(50);
+    assert!(tree.capacity() >= 100);
+    tree.shrink_to_fit();
+    assert!(tree.capacity() < 100);
+    assert_eq!(
+        tree.keys(),
+        (50..100).collect::<Vec<_>>().iter().collect::<Vec<_>>()
+    );
+    tree.insert(0, 0);
+    assert_eq!(tree.size(), 51);
+    assert_eq!(tree.min(), Some((&0, &0)));
+}
+
+#[test]
+fn order_statistics_test_many_operations() {
+    let mut tree = ArenaAVLTreeMap::new();
+    let mut model = std::collections::BTreeMap::new();
+    let mut x: u64 = 1;
+    for i in 0..5000u64 {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let key = (x >> 33) % 512;
+        if x.is_multiple_of(3) {
+            assert_eq!(tree.remove(&key), model.remove(&key));
+        } else {
+            assert_eq!(tree.insert(key, i), model.insert(key, i).is_none());
+        }
+        if i.is_multiple_of(1000) {
+            tree.shrink_to_fit();
+        }
+    }
+    assert_eq!(tree.size(), model.len());
+    for (i, (k, v)) in model.iter().enumerate() {
+        assert_eq!(tree.find_by_order(i), Some((k, v)));
+        assert_eq!(tree.order_of_key(k), i);
+    }
+}
+
+#[test]
+fn stats_test() {
+    let mut tree = ArenaAVLTreeMap::with_capacity(16);
+    for i in 1..=7 {
+        tree.insert(i, i);
+    }
+    let stats = tree.stats();
+    assert_eq!(stats.height, 3);
+    assert_eq!(stats.node_count, 7);
+    assert_eq!(stats.average_depth, 10.0 / 7.0);
+    assert_eq!(stats.balance.map(|balance| balance.equal), Some(7));
+    // 未使用の容量も含めて数える
+    let used = tree.stats().memory_bytes;
+    tree.shrink_to_fit();
+    assert!(tree.stats().memory_bytes < used);
+}
+
+// AVL 木の高さは 1.4405 log2(n + 2) 未満になる
+fn max_avl_height(n: usize) -> usize {
+    (1.4405 * ((n + 2) as f64).log2()) as usize
+}
+
+#[test]
+fn balance_test_many_operations() {
+    let mut tree = ArenaAVLTreeMap::new();
+    let mut model = std::collections::BTreeMap::new();
+    let mut x: u64 = 5;
+    for i in 0..20_000u64 {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let key = (x >> 33) % 2048;
+        if x % 5 < 2 {
+            assert_eq!(tree.remove(&key), model.remove(&key));
+        } else {
+            assert_eq!(tree.insert(key, i), model.insert(key, i).is_none());
+        }
+        if i % 97 == 0 {
+            assert_eq!(tree.validate(), Ok(()));
+            assert!(tree.stats().height <= max_avl_height(tree.size()));
+        }
+    }
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        model.into_iter().collect::<Vec<_>>()
+    );
+
+    // 昇順に挿入しても高さは対数に収まる
+    let sorted: ArenaAVLTreeMap<_, _> = (0..10_000).map(|i| (i, ())).collect();
+    assert_eq!(sorted.validate(), Ok(()));
+    assert!(sorted.stats().height <= max_avl_height(10_000));
+}
+
+#[test]
+fn shrink_to_fit_test_reuse() {
+    let mut tree: ArenaAVLTreeMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+    for i in (0..1000).step_by(2) {
+        tree.remove(&i);
+    }
+    tree.shrink_to_fit();
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(tree.capacity(), 500);
+
+    // 詰めたあとの木から削除して空いた場所は、次の挿入で再利用する
+    for i in (1..1000).step_by(4) {
+        tree.remove(&i);
+    }
+    assert_eq!(tree.validate(), Ok(()));
+    for i in (0..1000).step_by(4) {
+        assert!(tree.insert(i, i));
+        assert_eq!(tree.validate(), Ok(()));
+    }
+    assert_eq!(tree.capacity(), 500);
+    assert_eq!(tree.size(), 500);
+    assert!(tree.stats().height <= max_avl_height(500));
+    let expected: Vec<_> = (0..1000).filter(|i| i % 4 == 0 || i % 4 == 3).collect();
+    assert_eq!(tree.keys(), expected.iter().collect::<Vec<_>>());
+    for (i, key) in expected.iter().enumerate() {
+        assert_eq!(tree.find_by_order(i), Some((key, key)));
+        assert_eq!(tree.order_of_key(key), i);
+    }
+}
+
+// 回転と平衡状態の調整は AVLTreeMap と別に書いているので、同じ操作列で同じ形の木になることを確かめる
+#[test]
+fn same_shape_as_avl_tree_map_test() {
+    let mut arena = ArenaAVLTreeMap::new();
+    let mut tree = AVLTreeMap::new();
+    let mut x: u64 = 11;
+    for i in 0..20_000u64 {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let key = (x >> 33) % 1024;
+        // 存在しないキーの削除や、子を 2 つ持つノードの削除も含む
+        if x % 5 < 2 {
+            assert_eq!(arena.remove(&key), tree.remove(&key));
+        } else {
+            assert_eq!(arena.insert(key, i), tree.insert(key, i));
+        }
+        if i % 97 == 0 {
+            assert_eq!(arena.validate(), Ok(()));
+            let (a, b) = (arena.stats(), tree.stats());
+            assert_eq!(a.height, b.height);
+            assert_eq!(a.node_count, b.node_count);
+            assert_eq!(a.average_depth, b.average_depth);
+            assert_eq!(a.balance, b.balance);
+        }
+    }
+    assert_eq!(
+        arena.into_iter().collect::<Vec<_>>(),
+        tree.into_iter().collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,131 @@
+use binary_search_tree::avl_multimap::AVLTreeMultiMap;
+
+#[test]
+fn insert_test() {
+    let mut tree = AVLTreeMultiMap::new();
+    assert!(tree.insert(2, 'a'));
+    assert!(tree.insert(1, 'b'));
+    assert!(!tree.insert(2, 'c'));
+    assert!(!tree.insert(1, 'd'));
+    assert!(!tree.insert(2, 'e'));
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(1, 'b'), (1, 'd'), (2, 'a'), (2, 'c'), (2, 'e')]
+    );
+    assert_eq!(tree.size(), 5);
+    assert_eq!(tree.keys(), vec![&1, &2]);
+}
+
+#[test]
+fn get_all_test() {
+    let tree: AVLTreeMultiMap<_, _> = vec![(10, "x"), (20, "y"), (10, "z"), (10, "w")]
+        .into_iter()
+        .collect();
+    assert_eq!(tree.get_all(&10), vec![&"x", &"z", &"w"]);
+    assert_eq!(tree.get_all(&20), vec![&"y"]);
+    assert!(tree.get_all(&30).is_empty());
+    assert_eq!(tree.get_first(&10), Some(&"x"));
+    assert_eq!(tree.count(&10), 3);
+    assert!(tree.contains_key(&20));
+    assert!(!tree.contains_key(&30));
+}
+
+#[test]
+fn remove_first_test() {
+    let mut tree: AVLTreeMultiMap<_, _> = vec![(10, 1), (20, 2), (10, 3)].into_iter().collect();
+    assert_eq!(tree.remove_first(&10), Some(1));
+    assert_eq!(tree.get_all(&10), vec![&3]);
+    assert_eq!(tree.size(), 2);
+    assert_eq!(tree.remove_first(&10), Some(3));
+    assert!(!tree.contains_key(&10));
+    assert_eq!(tree.remove_first(&10), None);
+    assert_eq!(tree.size(), 1);
+}
+
+#[test]
+fn remove_all_test() {
+    let mut tree: AVLTreeMultiMap<_, _> = vec![(10, 1), (20, 2), (10, 3)].into_iter().collect();
+    assert_eq!(tree.remove_all(&10), vec![1, 3]);
+    assert!(tree.remove_all(&10).is_empty());
+    assert_eq!(tree.size(), 1);
+}
+
+#[test]
+fn min_max_test() {
+    let tree: AVLTreeMultiMap<_, _> = vec![(5, 'a'), (1, 'b'), (9, 'c'), (1, 'd'), (9, 'e')]
+        .into_iter()
+        .collect();
+    assert_eq!(tree.min(), Some((&1, &'b')));
+    assert_eq!(tree.max(), Some((&9, &'e')));
+}
+
+#[test]
+fn range_test() {
+    let tree: AVLTreeMultiMap<_, _> = vec![(3, 'a'), (1, 'b'), (3, 'c'), (5, 'd'), (4, 'e')]
+        .into_iter()
+        .collect();
+    assert_eq!(tree.range(2..5), vec![(&3, &'a'), (&3, &'c'), (&4, &'e')]);
+    assert_eq!(tree.range(..=3).len(), 3);
+    assert_eq!(tree.range(4..).len(), 2);
+    assert!(tree.range(6..).is_empty());
+}
+
+#[test]
+fn find_by_order_test() {
+    let tree: AVLTreeMultiMap<_, _> = vec![(3, 'a'), (1, 'b'), (3, 'c'), (5, 'd'), (3, 'e')]
+        .into_iter()
+        .collect();
+    assert_eq!(tree.find_by_order(0), Some((&1, &'b')));
+    assert_eq!(tree.find_by_order(1), Some((&3, &'a')));
+    assert_eq!(tree.find_by_order(2), Some((&3, &'c')));
+    assert_eq!(tree.find_by_order(3), Some((&3, &'e')));
+    assert_eq!(tree.find_by_order(4), Some((&5, &'d')));
+    assert_eq!(tree.find_by_order(5), None);
+}
+
+#[test]
+fn order_of_key_test() {
+    let tree: AVLTreeMultiMap<_, _> = vec![(3, 'a'), (1, 'b'), (3, 'c'), (5, 'd'), (3, 'e')]
+        .into_iter()
+        .collect();
+    assert_eq!(tree.order_of_key(&1), 0);
+    assert_eq!(tree.order_of_key(&2), 1);
+    assert_eq!(tree.order_of_key(&3), 1);
+    assert_eq!(tree.order_of_key(&4), 4);
+    assert_eq!(tree.order_of_key(&6), 5);
+}
+
+#[test]
+fn insertion_order_test_many_operations() {
+    let mut tree = AVLTreeMultiMap::new();
+    for i in 0..600 {
+        tree.insert(i % 20, i);
+    }
+    for key in 0..20 {
+        assert_eq!(tree.remove_first(&key), Some(key));
+    }
+    assert_eq!(tree.size(), 580);
+    for key in 0..20 {
+        assert_eq!(
+            tree.get_all(&key),
+            (1..30)
+                .map(|j| key + 20 * j)
+                .collect::<Vec<_>>()
+                .iter()
+                .collect::<Vec<_>>()
+        );
+    }
+    assert_eq!(tree.find_by_order(29), Some((&1, &21)));
+}
+
+#[test]
+fn fmt_tree_test() {
+    let mut tree = AVLTreeMultiMap::new();
+    tree.insert(2, 'a');
+    tree.insert(2, 'b');
+    tree.insert(1, 'c');
+    assert_eq!(
+        tree.fmt_tree(true),
+        "2 (values: 2, size: 3, state: Left)\n└── 1 (values: 1, size: 1, state: Equal)\n"
+    );
+}
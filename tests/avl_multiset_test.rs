@@ -0,0 +1,193 @@
+use binary_search_tree::avl_multiset::{AVLTreeMultiSet, CountOverflow};
+
+#[test]
+fn insert_test() {
+    let mut tree = AVLTreeMultiSet::new();
+    assert!(tree.insert(3));
+    assert!(tree.insert(1));
+    assert!(!tree.insert(3));
+    assert!(tree.insert(2));
+    assert!(!tree.insert(3));
+    assert_eq!(
+        tree.into_iter().copied().collect::<Vec<_>>(),
+        vec![1, 2, 3, 3, 3]
+    );
+    assert_eq!(tree.size(), 5);
+    assert_eq!(tree.distinct_size(), 3);
+}
+
+#[test]
+fn insert_many_test() {
+    let mut tree = AVLTreeMultiSet::new();
+    assert!(tree.insert_many(5, 3));
+    assert!(!tree.insert_many(5, 2));
+    assert!(!tree.insert_many(7, 0));
+    assert_eq!(tree.count(&5), 5);
+    assert_eq!(tree.count(&7), 0);
+    assert_eq!(tree.size(), 5);
+}
+
+#[test]
+fn insert_many_test_overflow() {
+    let mut tree = AVLTreeMultiSet::new();
+    tree.insert_many(1, usize::MAX - 10);
+    tree.insert_many(2, 5);
+    assert_eq!(tree.try_insert_many(3, 6), Err(CountOverflow));
+    assert!(!tree.contains(&3));
+    assert_eq!(tree.try_insert_many(2, 6), Err(CountOverflow));
+    assert_eq!(tree.count(&2), 5);
+    assert_eq!(tree.size(), usize::MAX - 5);
+    assert_eq!(tree.try_insert_many(2, 5), Ok(false));
+    assert_eq!(tree.size(), usize::MAX);
+    assert_eq!(tree.distinct_size(), 2);
+}
+
+#[test]
+#[should_panic(expected = "total count overflow")]
+fn insert_many_test_overflow_panic() {
+    let mut tree = AVLTreeMultiSet::new();
+    tree.insert_many(1, usize::MAX);
+    tree.insert(2);
+}
+
+#[test]
+fn count_test() {
+    let tree: AVLTreeMultiSet<_> = vec![1, 2, 2, 3, 3, 3].into_iter().collect();
+    assert_eq!(tree.count(&0), 0);
+    assert_eq!(tree.count(&1), 1);
+    assert_eq!(tree.count(&2), 2);
+    assert_eq!(tree.count(&3), 3);
+    assert!(tree.contains(&2));
+    assert!(!tree.contains(&4));
+    assert_eq!(tree.counts(), vec![(&1, 1), (&2, 2), (&3, 3)]);
+}
+
+#[test]
+fn remove_one_test() {
+    let mut tree: AVLTreeMultiSet<_> = vec![1, 2, 2, 3].into_iter().collect();
+    assert!(tree.remove_one(&2));
+    assert_eq!(tree.count(&2), 1);
+    assert_eq!(tree.size(), 3);
+    assert!(tree.remove_one(&2));
+    assert_eq!(tree.count(&2), 0);
+    assert_eq!(tree.distinct_size(), 2);
+    assert!(!tree.remove_one(&2));
+    assert_eq!(tree.into_iter().copied().collect::<Vec<_>>(), vec![1, 3]);
+}
+
+#[test]
+fn remove_all_test() {
+    let mut tree: AVLTreeMultiSet<_> = vec![1, 2, 2, 2, 3].into_iter().collect();
+    assert_eq!(tree.remove_all(&2), 3);
+    assert_eq!(tree.remove_all(&2), 0);
+    assert_eq!(tree.size(), 2);
+    assert_eq!(tree.distinct_size(), 2);
+    assert_eq!(tree.remove_all(&1), 1);
+    assert_eq!(tree.remove_all(&3), 1);
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn min_max_test() {
+    let mut tree: AVLTreeMultiSet<_> = vec![4, 1, 1, 9, 9].into_iter().collect();
+    assert_eq!(tree.min(), Some(&1));
+    assert_eq!(tree.max(), Some(&9));
+    tree.remove_one(&9);
+    assert_eq!(tree.max(), Some(&9));
+    tree.remove_one(&9);
+    assert_eq!(tree.max(), Some(&4));
+}
+
+#[test]
+fn find_by_order_test() {
+    let tree: AVLTreeMultiSet<_> = vec![5, 1, 3, 3, 5, 5].into_iter().collect();
+    assert_eq!(tree.find_by_order(0), Some(&1));
+    assert_eq!(tree.find_by_order(1), Some(&3));
+    assert_eq!(tree.find_by_order(2), Some(&3));
+    assert_eq!(tree.find_by_order(3), Some(&5));
+    assert_eq!(tree.find_by_order(5), Some(&5));
+    assert_eq!(tree.find_by_order(6), None);
+}
+
+#[test]
+fn order_of_key_test() {
+    let tree: AVLTreeMultiSet<_> = vec![5, 1, 3, 3, 5, 5].into_iter().collect();
+    assert_eq!(tree.order_of_key(&0), 0);
+    assert_eq!(tree.order_of_key(&1), 0);
+    assert_eq!(tree.order_of_key(&2), 1);
+    assert_eq!(tree.order_of_key(&3), 1);
+    assert_eq!(tree.order_of_key(&4), 3);
+    assert_eq!(tree.order_of_key(&5), 3);
+    assert_eq!(tree.order_of_key(&6), 6);
+}
+
+#[test]
+fn sliding_window_median_test() {
+    let values = [5, 2, 8, 2, 9, 1, 7, 7, 3, 6];
+    let window = 4;
+    let mut tree = AVLTreeMultiSet::new();
+    let mut medians = Vec::new();
+    for (i, &value) in values.iter().enumerate() {
+        tree.insert(value);
+        if i >= window {
+            assert!(tree.remove_one(&values[i - window]));
+        }
+        if i + 1 >= window {
+            medians.push(*tree.find_by_order((window - 1) / 2).unwrap());
+        }
+    }
+    assert_eq!(medians, vec![2, 2, 2, 2, 7, 3, 6]);
+}
+
+#[test]
+fn order_statistics_test_many_operations() {
+    let mut tree = AVLTreeMultiSet::new();
+    let mut expected = Vec::new();
+    for i in 0..500 {
+        let key = (i * 37) % 50;
+        tree.insert(key);
+        expected.push(key);
+    }
+    for i in 0..200 {
+        let key = (i * 13) % 50;
+        if let Some(pos) = expected.iter().position(|&k| k == key) {
+            expected.remove(pos);
+            assert!(tree.remove_one(&key));
+        }
+    }
+    expected.sort_unstable();
+    assert_eq!(tree.size(), expected.len());
+    for (order, key) in expected.iter().enumerate() {
+        assert_eq!(tree.find_by_order(order), Some(key));
+    }
+    for key in 0..51 {
+        assert_eq!(
+            tree.order_of_key(&key),
+            expected.iter().filter(|&&k| k < key).count()
+        );
+    }
+}
+
+#[test]
+fn stats_test_counts_distinct_keys() {
+    let mut set = AVLTreeMultiSet::new();
+    for i in 0..10 {
+        set.insert_many(i, 3);
+    }
+    let stats = set.stats();
+    assert_eq!(set.size(), 30);
+    assert_eq!(stats.node_count, 10);
+    assert_eq!(stats.height, 4);
+}
+
+#[test]
+fn fmt_tree_test() {
+    let mut tree = AVLTreeMultiSet::new();
+    tree.insert(2);
+    tree.insert(2);
+    tree.insert(1);
+    assert_eq!(
+        tree.fmt_tree(true),
+        "2 (count: 2, size: 3, state: Left)\n└── 1 (count: 1, size: 1, state: Equal)\n"
+    );
+}
@@ -0,0 +1,42 @@
+use std::collections::BTreeMap;
+
+use binary_search_tree::avl_tree::AVLTreeMap;
+
+#[test]
+fn recursive_test_random_operations() {
+    // 再帰版 (insert_recursive / remove_recursive) と非再帰版に同じ操作を行い、結果と木の形が一致することを確かめる
+    let mut tree = AVLTreeMap::new();
+    let mut recursive = AVLTreeMap::new();
+    let mut expected = BTreeMap::new();
+    let mut x: u64 = 11;
+    for step in 0..20000u64 {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let key = (x >> 33) % 512;
+        // 前半は挿入を、後半は削除を多くして、木が育ってから縮むようにする
+        let insert_ratio = if step < 10000 { 3 } else { 1 };
+        if (x >> 20) % 4 < insert_ratio {
+            let inserted = expected.insert(key, step).is_none();
+            assert_eq!(tree.insert(key, step), inserted);
+            assert_eq!(recursive.insert_recursive(key, step), inserted);
+        } else {
+            let removed = expected.remove(&key);
+            assert_eq!(tree.remove(&key), removed);
+            assert_eq!(recursive.remove_recursive(&key), removed);
+        }
+        assert_eq!(recursive.validate(), Ok(()));
+        assert_eq!(recursive.size(), expected.len());
+        if step % 97 == 0 {
+            assert_eq!(recursive.stats().height, tree.stats().height);
+            assert_eq!(recursive.stats().balance, tree.stats().balance);
+        }
+    }
+    assert_eq!(
+        recursive
+            .into_iter()
+            .map(|(&k, &v)| (k, v))
+            .collect::<Vec<_>>(),
+        expected.into_iter().collect::<Vec<_>>()
+    );
+}
@@ -1,4 +1,6 @@
-use binary_search_tree::avl_tree::AVLTreeMap;
+use std::collections::BTreeMap;
+
+use binary_search_tree::avl_tree::{AVLTreeMap, AVLTreeSet};
 
 #[test]
 fn into_iter_test_empty() {
@@ -390,3 +392,475 @@ fn order_of_key_test_all_removed() {
     assert_eq!(tree.order_of_key(&16), 0);
     assert_eq!(tree.order_of_key(&17), 0);
 }
+
+#[test]
+fn order_statistics_test_many_operations() {
+    let mut tree = AVLTreeMap::new();
+    for i in 0..1000 {
+        tree.insert((i * 37) % 1000, i);
+    }
+    for i in (0..1000).step_by(3) {
+        assert!(tree.remove(&i).is_some());
+    }
+    let expected = (0..1000).filter(|i| i % 3 != 0).collect::<Vec<_>>();
+    assert_eq!(tree.size(), expected.len());
+    for (order, key) in expected.iter().enumerate() {
+        assert_eq!(tree.find_by_order(order).map(|(k, _)| k), Some(key));
+        assert_eq!(tree.order_of_key(key), order);
+    }
+}
+
+#[test]
+fn cursor_test_lower_bound() {
+    let tree: AVLTreeMap<_, _> = vec![(10, 'a'), (20, 'b'), (30, 'c')].into_iter().collect();
+    let cursor = tree.lower_bound(&15);
+    assert_eq!(cursor.key(), Some(&20));
+    assert_eq!(cursor.value(), Some(&'b'));
+    assert_eq!(cursor.index(), 1);
+    let cursor = tree.lower_bound(&10);
+    assert_eq!(cursor.key(), Some(&10));
+    assert_eq!(cursor.index(), 0);
+    let cursor = tree.lower_bound(&31);
+    assert_eq!(cursor.key(), None);
+    assert_eq!(cursor.index(), 3);
+}
+
+#[test]
+fn cursor_test_move() {
+    let tree: AVLTreeMap<_, _> = (0..100).map(|i| (i * 2, i)).collect();
+    let mut cursor = tree.lower_bound(&0);
+    for i in 0..100 {
+        assert_eq!(cursor.index(), i);
+        assert_eq!(cursor.key(), Some(&(i * 2)));
+        assert_eq!(
+            cursor.peek_prev().map(|(k, _)| *k),
+            i.checked_sub(1).map(|j| j * 2)
+        );
+        cursor.move_next();
+    }
+    assert_eq!(cursor.key(), None);
+    assert_eq!(cursor.index(), 100);
+    assert_eq!(cursor.peek_next(), Some((&0, &0)));
+    assert_eq!(cursor.peek_prev(), Some((&198, &99)));
+    for i in (0..100).rev() {
+        cursor.move_prev();
+        assert_eq!(cursor.index(), i);
+        assert_eq!(cursor.key(), Some(&(i * 2)));
+        assert_eq!(
+            cursor.peek_next().map(|(k, _)| *k),
+            if i == 99 { None } else { Some(i * 2 + 2) }
+        );
+    }
+    cursor.move_prev();
+    assert_eq!(cursor.key(), None);
+    cursor.move_next();
+    assert_eq!(cursor.key(), Some(&0));
+}
+
+#[test]
+fn cursor_test_empty() {
+    let tree: AVLTreeMap<i32, i32> = AVLTreeMap::new();
+    let mut cursor = tree.lower_bound(&0);
+    assert_eq!(cursor.key(), None);
+    cursor.move_next();
+    assert_eq!(cursor.key(), None);
+    cursor.move_prev();
+    assert_eq!(cursor.key(), None);
+    assert_eq!(cursor.peek_next(), None);
+    assert_eq!(cursor.peek_prev(), None);
+}
+
+#[test]
+fn cursor_mut_test_value_mut() {
+    let mut tree: AVLTreeMap<_, _> = (0..10).map(|i| (i, i)).collect();
+    let mut cursor = tree.lower_bound_mut(&3);
+    while let Some(value) = cursor.value_mut() {
+        *value *= 10;
+        cursor.move_next();
+    }
+    assert_eq!(
+        tree.values().into_iter().copied().collect::<Vec<_>>(),
+        vec![0, 1, 2, 30, 40, 50, 60, 70, 80, 90]
+    );
+}
+
+#[test]
+fn cursor_mut_test_remove_current() {
+    let mut tree: AVLTreeMap<_, _> = (0..10).map(|i| (i, i)).collect();
+    let mut cursor = tree.lower_bound_mut(&0);
+    while cursor.key().is_some() {
+        if cursor.key().unwrap() % 3 == 0 {
+            assert!(cursor.remove_current().is_some());
+        } else {
+            cursor.move_next();
+        }
+    }
+    assert_eq!(cursor.remove_current(), None);
+    assert_eq!(tree.keys(), vec![&1, &2, &4, &5, &7, &8]);
+}
+
+#[test]
+fn cursor_mut_test_insert() {
+    let mut tree: AVLTreeMap<_, _> = vec![(10, 'a'), (30, 'c')].into_iter().collect();
+    let mut cursor = tree.lower_bound_mut(&30);
+    cursor.insert_before(20, 'b');
+    assert_eq!(cursor.key(), Some(&30));
+    assert_eq!(cursor.index(), 2);
+    cursor.insert_after(40, 'd');
+    assert_eq!(cursor.peek_next(), Some((&40, &'d')));
+    cursor.move_next();
+    cursor.move_next();
+    assert_eq!(cursor.key(), None);
+    cursor.insert_before(50, 'e');
+    cursor.insert_after(0, 'z');
+    assert_eq!(cursor.index(), 6);
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![
+            (0, 'z'),
+            (10, 'a'),
+            (20, 'b'),
+            (30, 'c'),
+            (40, 'd'),
+            (50, 'e')
+        ]
+    );
+}
+
+#[test]
+#[should_panic]
+fn cursor_mut_test_insert_out_of_order() {
+    let mut tree: AVLTreeMap<_, _> = vec![(10, 'a'), (30, 'c')].into_iter().collect();
+    let mut cursor = tree.lower_bound_mut(&30);
+    cursor.insert_before(5, 'x');
+}
+
+#[test]
+fn set_cursor_test() {
+    let mut set: AVLTreeSet<_> = vec![1, 3, 5, 7].into_iter().collect();
+    let mut cursor = set.lower_bound(&4);
+    assert_eq!(cursor.key(), Some(&5));
+    assert_eq!(cursor.peek_prev(), Some(&3));
+    cursor.move_next();
+    assert_eq!(cursor.key(), Some(&7));
+    assert_eq!(cursor.peek_next(), None);
+
+    let mut cursor = set.lower_bound_mut(&4);
+    cursor.insert_before(4);
+    assert_eq!(cursor.remove_current(), Some(5));
+    cursor.insert_before(6);
+    assert_eq!(cursor.key(), Some(&7));
+    assert_eq!(set.keys(), vec![&1, &3, &4, &6, &7]);
+}
+
+#[test]
+fn validate_test_many_operations() {
+    let mut tree = AVLTreeMap::new();
+    assert_eq!(tree.validate(), Ok(()));
+    for i in 0..1000 {
+        tree.insert((i * 37) % 1000, i);
+        if i % 50 == 0 {
+            assert_eq!(tree.validate(), Ok(()));
+        }
+    }
+    for i in (0..1000).step_by(3) {
+        tree.remove(&i);
+        if i % 50 == 0 {
+            assert_eq!(tree.validate(), Ok(()));
+        }
+    }
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn validate_test_set() {
+    let mut set: AVLTreeSet<_> = (0..100).rev().collect();
+    assert_eq!(set.validate(), Ok(()));
+    for i in (0..100).step_by(2) {
+        set.remove(&i);
+    }
+    assert_eq!(set.validate(), Ok(()));
+}
+
+#[test]
+fn validate_test_random_operations() {
+    // 挿入と削除を混ぜ、1 回ごとに不変条件と BTreeMap との一致を確かめる
+    // キーの範囲を狭くして、存在しないキーの削除や既存キーへの挿入、子を 2 つ持つノードの削除を起こす
+    let mut tree = AVLTreeMap::new();
+    let mut expected = BTreeMap::new();
+    let mut x: u64 = 11;
+    for step in 0..20000u64 {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let key = (x >> 33) % 512;
+        // 前半は挿入を、後半は削除を多くして、木が育ってから縮むようにする
+        let insert_ratio = if step < 10000 { 3 } else { 1 };
+        if (x >> 20) % 4 < insert_ratio {
+            let inserted = expected.insert(key, step).is_none();
+            assert_eq!(tree.insert(key, step), inserted);
+        } else {
+            let removed = expected.remove(&key);
+            assert_eq!(tree.remove(&key), removed);
+        }
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.size(), expected.len());
+    }
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        expected.into_iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn stats_test_empty() {
+    let tree: AVLTreeMap<i32, i32> = AVLTreeMap::new();
+    let stats = tree.stats();
+    assert_eq!(stats.height, 0);
+    assert_eq!(stats.node_count, 0);
+    assert_eq!(stats.max_depth, 0);
+    assert_eq!(stats.average_depth, 0.0);
+    assert_eq!(stats.memory_bytes, 0);
+}
+
+#[test]
+fn stats_test_perfect() {
+    let tree: AVLTreeMap<_, _> = (1..=7).map(|i| (i, i)).collect();
+    let stats = tree.stats();
+    assert_eq!(stats.height, 3);
+    assert_eq!(stats.node_count, 7);
+    assert_eq!(stats.max_depth, 2);
+    assert_eq!(stats.average_depth, 10.0 / 7.0);
+    let balance = stats.balance.unwrap();
+    assert_eq!((balance.left, balance.equal, balance.right), (0, 7, 0));
+    assert!(stats.memory_bytes >= 7 * 2 * std::mem::size_of::<i32>());
+}
+
+#[test]
+fn stats_test_set_height_bound() {
+    let set: AVLTreeSet<_> = (0..1000).collect();
+    let stats = set.stats();
+    assert_eq!(stats.node_count, 1000);
+    // AVL 木の高さは 1.44 log2(n + 2) 未満
+    assert!(stats.height >= 10 && stats.height <= 14);
+    let balance = stats.balance.unwrap();
+    assert_eq!(balance.left + balance.equal + balance.right, 1000);
+}
+
+#[test]
+fn to_dot_test() {
+    let tree: AVLTreeMap<_, _> = (1..=3).map(|i| (i, i)).collect();
+    let dot = tree.to_dot();
+    assert!(dot.starts_with("digraph {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("n0 [label=\"2\\nsize: 3\\nstate: Equal\"];"));
+    assert!(dot.contains("[label=\"1\\nsize: 1\\nstate: Equal\"];"));
+    // 各ノードから左右の子 (存在しない子を含む) への辺が出る
+    assert_eq!(dot.matches("->").count(), 6);
+    assert_eq!(dot.matches("shape=point").count(), 4);
+}
+
+#[test]
+fn to_dot_test_escape() {
+    let set: AVLTreeSet<_> = ["a\"b"].into_iter().collect();
+    assert!(set
+        .to_dot()
+        .contains("[label=\"\\\"a\\\\\\\"b\\\"\\nsize: 1"));
+}
+
+#[test]
+fn to_dot_test_empty() {
+    let set: AVLTreeSet<i32> = AVLTreeSet::new();
+    assert_eq!(set.to_dot(), "digraph {\n    node [shape=box];\n}\n");
+}
+
+#[test]
+fn fmt_tree_test() {
+    let set: AVLTreeSet<_> = (1..=7).collect();
+    assert_eq!(
+        set.fmt_tree(false),
+        [
+            "    ┌── 7",
+            "┌── 6",
+            "│   └── 5",
+            "4",
+            "│   ┌── 3",
+            "└── 2",
+            "    └── 1",
+            "",
+        ]
+        .join("\n")
+    );
+}
+
+#[test]
+fn fmt_tree_test_annotated() {
+    let mut tree = AVLTreeMap::new();
+    tree.insert(2, ());
+    tree.insert(1, ());
+    assert_eq!(
+        tree.fmt_tree(true),
+        "2 (size: 2, state: Left)\n└── 1 (size: 1, state: Equal)\n"
+    );
+}
+
+#[test]
+fn fmt_tree_test_empty() {
+    let tree: AVLTreeMap<i32, i32> = AVLTreeMap::new();
+    assert_eq!(tree.fmt_tree(true), "(empty)\n");
+}
+
+#[test]
+fn retain_test() {
+    let mut tree: AVLTreeMap<_, _> = (0..100).map(|i| (i, i)).collect();
+    tree.retain(|k, v| {
+        *v *= 10;
+        k % 3 == 0
+    });
+    let expected = (0..100).step_by(3).collect::<Vec<_>>();
+    assert_eq!(tree.size(), expected.len());
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(tree.keys(), expected.iter().collect::<Vec<_>>());
+    assert_eq!(tree.get(&99), Some(&990));
+
+    tree.retain(|_, _| false);
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn retain_test_panic() {
+    // f が途中で panic しても要素は失われない
+    let mut tree: AVLTreeMap<_, _> = (0..100).map(|i| (i, i)).collect();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        tree.retain(|k, _| {
+            assert!(*k < 50, "abort");
+            k % 2 == 0
+        });
+    }));
+    assert!(result.is_err());
+    assert_eq!(tree.size(), 100);
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(
+        tree.keys(),
+        (0..100).collect::<Vec<_>>().iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn merge_with_test() {
+    let mut tree: AVLTreeMap<_, _> = [(1, "a"), (3, "c"), (5, "e")].into_iter().collect();
+    let other: AVLTreeMap<_, _> = [(2, "B"), (3, "C"), (6, "F")].into_iter().collect();
+    tree.merge_with(other, |k, ours, theirs| {
+        assert_eq!(*k, 3);
+        assert_eq!((ours, theirs), ("c", "C"));
+        "merged"
+    });
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(1, "a"), (2, "B"), (3, "merged"), (5, "e"), (6, "F")]
+    );
+}
+
+#[test]
+fn merge_with_test_random_sizes() {
+    let mut x = 3u64;
+    let mut next = || {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        x >> 33
+    };
+    for (n, m) in [
+        (0, 5),
+        (5, 0),
+        (1, 1000),
+        (1000, 1),
+        (30, 3000),
+        (3000, 30),
+        (500, 500),
+    ] {
+        let tree: AVLTreeMap<_, _> = (0..n).map(|_| (next() % 5000, next() % 100)).collect();
+        let other: AVLTreeMap<_, _> = (0..m).map(|_| (next() % 5000, next() % 100)).collect();
+        // 値の和で衝突を解決する
+        let mut expected: BTreeMap<_, _> = tree.into_iter().map(|(&k, &v)| (k, v)).collect();
+        for (&k, &v) in &other {
+            *expected.entry(k).or_insert(0) += v;
+        }
+
+        let mut tree = tree;
+        tree.merge_with(other, |_, a, b| a + b);
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(
+            tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            expected.into_iter().collect::<Vec<_>>()
+        );
+        for order in (0..tree.size()).step_by(7) {
+            let (key, _) = tree.find_by_order(order).unwrap();
+            assert_eq!(tree.order_of_key(key), order);
+        }
+    }
+}
+
+#[test]
+fn cursor_mut_test_random_walk() {
+    // 移動、読み書き、挿入、削除を混ぜて、位置と要素を Vec と突き合わせる
+    let mut tree: AVLTreeMap<u64, u64> = (0..200).map(|i| (i * 4, i)).collect();
+    let mut expected: Vec<_> = (0..200).map(|i| (i * 4, i)).collect();
+    let mut cursor = tree.lower_bound_mut(&301);
+    let mut index = expected.partition_point(|&(k, _)| k < 301);
+    let mut x: u64 = 7;
+    for _ in 0..5000 {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let n = expected.len();
+        match (x >> 33) % 6 {
+            0 | 1 => {
+                cursor.move_next();
+                index = if index == n { 0 } else { index + 1 };
+            }
+            2 => {
+                cursor.move_prev();
+                index = if index == 0 { n } else { index - 1 };
+            }
+            3 => {
+                if let Some(value) = cursor.value_mut() {
+                    *value += 1;
+                    expected[index].1 += 1;
+                }
+            }
+            4 => {
+                let removed = cursor.remove_current();
+                assert_eq!(removed, (index < n).then(|| expected.remove(index)));
+            }
+            _ => {
+                // 前の要素との間に空きがあれば挿入する
+                let low = if index == 0 {
+                    0
+                } else {
+                    expected[index - 1].0 + 1
+                };
+                let high = expected.get(index).map_or(u64::MAX, |&(k, _)| k);
+                if low < high {
+                    cursor.insert_before(low, 0);
+                    expected.insert(index, (low, 0));
+                    index += 1;
+                }
+            }
+        }
+        let n = expected.len();
+        assert_eq!(cursor.index(), index);
+        assert_eq!(cursor.key(), expected.get(index).map(|(k, _)| k));
+        assert_eq!(cursor.value(), expected.get(index).map(|(_, v)| v));
+        let next = if index == n { 0 } else { index + 1 };
+        let prev = if index == 0 { n } else { index - 1 };
+        assert_eq!(cursor.peek_next(), expected.get(next).map(|(k, v)| (k, v)));
+        assert_eq!(cursor.peek_prev(), expected.get(prev).map(|(k, v)| (k, v)));
+    }
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        expected
+    );
+}
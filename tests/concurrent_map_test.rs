@@ -0,0 +1,270 @@
+use binary_search_tree::concurrent_map::ConcurrentAVLMap;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+fn random_keys(seed: u64, n: usize, range: u64) -> Vec<u64> {
+    let mut x = seed;
+    (0..n)
+        .map(|_| {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (x >> 33) % range
+        })
+        .collect()
+}
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn send_sync_test() {
+    assert_send_sync::<ConcurrentAVLMap<String, Vec<u8>>>();
+}
+
+#[test]
+fn single_thread_test() {
+    for boundaries in [vec![], vec![500], vec![100, 200, 300, 400, 700]] {
+        let map = ConcurrentAVLMap::with_boundaries(boundaries.clone());
+        assert_eq!(map.shard_count(), boundaries.len() + 1);
+        let mut expected = BTreeMap::new();
+        let ops = random_keys(1, 3000, 1000);
+        for (i, &key) in ops.iter().enumerate() {
+            if i % 3 == 2 {
+                assert_eq!(map.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, i), expected.insert(key, i).is_none());
+            }
+        }
+        assert_eq!(map.size(), expected.len());
+        for key in [0, 99, 100, 101, 450, 500, 999, 1000] {
+            assert_eq!(map.get(&key), expected.get(&key).copied());
+            assert_eq!(map.contains_key(&key), expected.contains_key(&key));
+            assert_eq!(map.order_of_key(&key), expected.range(..key).count());
+        }
+        for (start, end) in [(0, 1000), (150, 250), (100, 200), (300, 301), (450, 120)] {
+            let collect = |range: (Bound<u64>, Bound<u64>)| -> Vec<(u64, usize)> {
+                expected.range(range).map(|(&k, &v)| (k, v)).collect()
+            };
+            let bounds = [
+                (Bound::Included(start), Bound::Excluded(end)),
+                (Bound::Excluded(start), Bound::Included(end)),
+                (Bound::Unbounded, Bound::Excluded(end)),
+                (Bound::Included(start), Bound::Unbounded),
+            ];
+            for range in bounds {
+                let reversed = match range {
+                    (
+                        Bound::Included(s) | Bound::Excluded(s),
+                        Bound::Included(e) | Bound::Excluded(e),
+                    ) => s > e,
+                    _ => false,
+                };
+                let want = if reversed { Vec::new() } else { collect(range) };
+                assert_eq!(map.range(range), want);
+            }
+        }
+        assert_eq!(map.range(..).len(), expected.len());
+
+        let tree = map.into_inner();
+        assert_eq!(tree.validate(), Ok(()));
+        let entries: Vec<_> = tree.into_iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(entries, expected.into_iter().collect::<Vec<_>>());
+    }
+}
+
+#[test]
+#[should_panic(expected = "strictly increasing")]
+fn with_boundaries_test_unsorted() {
+    ConcurrentAVLMap::<u32, ()>::with_boundaries(vec![10, 5]);
+}
+
+#[test]
+fn concurrent_insert_test() {
+    const THREADS: u64 = 8;
+    const PER_THREAD: u64 = 5000;
+    let map = ConcurrentAVLMap::with_boundaries(vec![10_000, 20_000, 30_000]);
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let map = &map;
+            s.spawn(move || {
+                for i in 0..PER_THREAD {
+                    assert!(map.insert(i * THREADS + t, t));
+                }
+            });
+        }
+    });
+    let n = (THREADS * PER_THREAD) as usize;
+    assert_eq!(map.size(), n);
+    assert_eq!(map.order_of_key(&25_000), 25_000);
+    assert_eq!(map.get(&12_345), Some(12_345 % THREADS));
+    let tree = map.into_inner();
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(tree.size(), n);
+}
+
+#[test]
+fn concurrent_remove_test() {
+    // 同じキーを複数のスレッドが取り除こうとしても、値を受け取るのはちょうど 1 つのスレッドだけ
+    const THREADS: usize = 8;
+    const N: u64 = 4000;
+    let map = ConcurrentAVLMap::with_boundaries(vec![1000, 2000, 3000]);
+    for key in 0..N {
+        map.insert(key, key * 10);
+    }
+    let received: Vec<Vec<(u64, u64)>> = thread::scope(|s| {
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let map = &map;
+                s.spawn(move || {
+                    // スレッドごとに異なる順序で取り除く
+                    let mut keys: Vec<_> = (0..N).collect();
+                    keys.rotate_left(t * N as usize / THREADS);
+                    keys.into_iter()
+                        .filter_map(|key| map.remove(&key).map(|value| (key, value)))
+                        .collect()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let mut all: Vec<_> = received.into_iter().flatten().collect();
+    all.sort();
+    assert_eq!(all, (0..N).map(|key| (key, key * 10)).collect::<Vec<_>>());
+    assert!(map.is_empty());
+}
+
+// 書き込み側のスレッド t が k 番目に書き込むキー
+// 連続する書き込みは区間をまたぎ、キーの順序と書き込みの順序は一致しない
+fn writer_key(t: u64, k: u64, threads: u64) -> u64 {
+    (3 - k % 4) * 1000 + (k / 4) * threads + t
+}
+
+// 読み出した内容について、各スレッドが書き込んだ k の集合を返す
+fn written_by_thread(entries: &[(u64, (u64, u64))], threads: u64) -> Vec<Vec<u64>> {
+    let mut seen = vec![Vec::new(); threads as usize];
+    for &(_, (t, k)) in entries {
+        seen[t as usize].push(k);
+    }
+    for ks in &mut seen {
+        ks.sort();
+    }
+    seen
+}
+
+#[test]
+fn range_linearizable_test() {
+    // 各スレッドの書き込みは順に起こるので、ある時点の内容には各スレッドの書き込みの先頭部分だけが含まれる
+    // 区間を 1 つずつロックして読むと、後の区間にある新しい書き込みだけが見えることがある
+    const THREADS: u64 = 4;
+    const N: u64 = 400;
+    let map = ConcurrentAVLMap::with_boundaries(vec![1000, 2000, 3000]);
+    let done = AtomicUsize::new(0);
+    let snapshots = AtomicUsize::new(0);
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let (map, done) = (&map, &done);
+            s.spawn(move || {
+                for k in 0..N {
+                    map.insert(writer_key(t, k, THREADS), (t, k));
+                }
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        for _ in 0..2 {
+            let (map, done, snapshots) = (&map, &done, &snapshots);
+            s.spawn(move || loop {
+                let finished = done.load(Ordering::SeqCst) == THREADS as usize;
+                let entries = map.range(..);
+                for ks in written_by_thread(&entries, THREADS) {
+                    assert_eq!(ks, (0..ks.len() as u64).collect::<Vec<_>>());
+                }
+                snapshots.fetch_add(1, Ordering::Relaxed);
+                if finished {
+                    break;
+                }
+            });
+        }
+    });
+    assert!(snapshots.load(Ordering::Relaxed) >= 2);
+    assert_eq!(map.size(), (THREADS * N) as usize);
+
+    // 取り除く場合は、各スレッドの書き込みの末尾部分だけが残る
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        let writers: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let map = &map;
+                s.spawn(move || {
+                    for k in 0..N {
+                        assert_eq!(map.remove(&writer_key(t, k, THREADS)), Some((t, k)));
+                    }
+                })
+            })
+            .collect();
+        let (map, done_ref) = (&map, &done);
+        let reader = s.spawn(move || loop {
+            let finished = done_ref.load(Ordering::SeqCst);
+            for ks in written_by_thread(&map.range(..), THREADS) {
+                let first = N - ks.len() as u64;
+                assert_eq!(ks, (first..N).collect::<Vec<_>>());
+            }
+            if finished {
+                break;
+            }
+        });
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+    });
+    assert!(map.is_empty());
+}
+
+#[test]
+fn order_of_key_linearizable_test() {
+    // 挿入しかしない間は、同じキーについての order_of_key は単調に増え、size 以下に収まる
+    const THREADS: u64 = 4;
+    const N: u64 = 2000;
+    let map = ConcurrentAVLMap::with_boundaries(vec![2000, 4000, 6000]);
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        let writers: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let map = &map;
+                s.spawn(move || {
+                    for k in 0..N {
+                        map.insert(k * THREADS + t, ());
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = [3000, 5000, u64::MAX]
+            .into_iter()
+            .map(|key| {
+                let (map, done) = (&map, &done);
+                s.spawn(move || {
+                    let mut last = 0;
+                    loop {
+                        let finished = done.load(Ordering::SeqCst);
+                        let order = map.order_of_key(&key);
+                        assert!(order >= last);
+                        assert!(order <= map.size());
+                        last = order;
+                        if finished {
+                            return last;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        let orders: Vec<_> = readers.into_iter().map(|r| r.join().unwrap()).collect();
+        assert_eq!(orders, vec![3000, 5000, (THREADS * N) as usize]);
+    });
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use binary_search_tree::avl_tree::AVLTreeMap;
+use binary_search_tree::diff::DiffItem;
+use binary_search_tree::persistent_avl_tree::PersistentAVLMap;
+
+// BTreeMap を使って素朴に求めた差分
+fn naive_diff(
+    old: &BTreeMap<u32, u32>,
+    new: &BTreeMap<u32, u32>,
+) -> Vec<(u32, Option<u32>, Option<u32>)> {
+    let mut keys: Vec<_> = old.keys().chain(new.keys()).copied().collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .map(|k| (k, old.get(&k).copied(), new.get(&k).copied()))
+        .filter(|(_, a, b)| a != b)
+        .collect()
+}
+
+fn flatten(items: Vec<DiffItem<'_, u32, u32>>) -> Vec<(u32, Option<u32>, Option<u32>)> {
+    items
+        .into_iter()
+        .map(|item| match item {
+            DiffItem::Added(k, v) => (*k, None, Some(*v)),
+            DiffItem::Removed(k, v) => (*k, Some(*v), None),
+            DiffItem::Changed { key, old, new } => (*key, Some(*old), Some(*new)),
+        })
+        .collect()
+}
+
+#[test]
+fn diff_test() {
+    let old: AVLTreeMap<_, _> = [(1, "a"), (2, "b"), (3, "c"), (5, "e")]
+        .into_iter()
+        .collect();
+    let new: AVLTreeMap<_, _> = [(2, "b"), (3, "C"), (4, "d"), (5, "e")]
+        .into_iter()
+        .collect();
+    assert_eq!(
+        old.diff(&new).collect::<Vec<_>>(),
+        vec![
+            DiffItem::Removed(&1, &"a"),
+            DiffItem::Changed {
+                key: &3,
+                old: &"c",
+                new: &"C"
+            },
+            DiffItem::Added(&4, &"d"),
+        ]
+    );
+    assert_eq!(old.diff(&old).count(), 0);
+    assert_eq!(old.diff(&AVLTreeMap::new()).count(), 4);
+    assert_eq!(AVLTreeMap::new().diff(&new).count(), 4);
+}
+
+#[test]
+fn diff_test_random() {
+    let mut x = 1u32;
+    let mut next = || {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        x >> 16
+    };
+    for _ in 0..20 {
+        let mut old = BTreeMap::new();
+        let mut new = BTreeMap::new();
+        for _ in 0..300 {
+            let key = next() % 500;
+            old.insert(key, next() % 3);
+            let key = next() % 500;
+            new.insert(key, next() % 3);
+        }
+        let old_tree: AVLTreeMap<_, _> = old.iter().map(|(&k, &v)| (k, v)).collect();
+        let new_tree: AVLTreeMap<_, _> = new.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(
+            flatten(old_tree.diff(&new_tree).collect()),
+            naive_diff(&old, &new)
+        );
+    }
+}
+
+#[test]
+fn diff_test_persistent_versions() {
+    let mut x = 7u32;
+    let mut next = || {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        x >> 16
+    };
+    let mut reference = BTreeMap::new();
+    let mut version = PersistentAVLMap::new();
+    for i in 0..2000 {
+        reference.insert(i, i);
+        version = version.insert(i, i);
+    }
+    let base = version.clone();
+    let base_reference = reference.clone();
+    for _ in 0..30 {
+        let key = next() % 2500;
+        match next() % 3 {
+            0 => {
+                reference.remove(&key);
+                version = version.remove(&key);
+            }
+            _ => {
+                let value = next() % 2;
+                reference.insert(key, value);
+                version = version.insert(key, value);
+            }
+        }
+    }
+    assert_eq!(
+        flatten(base.diff(&version).collect()),
+        naive_diff(&base_reference, &reference)
+    );
+    assert_eq!(
+        flatten(version.diff(&base).collect()),
+        naive_diff(&reference, &base_reference)
+    );
+    assert_eq!(version.diff(&version.clone()).count(), 0);
+}
@@ -0,0 +1,161 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use binary_search_tree::durable_map::DurableMap;
+
+// テストごとに空のディレクトリを用意する
+fn temp_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("durable_map_test_{}_{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn key(i: u32) -> Vec<u8> {
+    i.to_be_bytes().to_vec()
+}
+
+#[test]
+fn reopen_test() {
+    let dir = temp_dir("reopen");
+    {
+        let mut map = DurableMap::open(&dir).unwrap();
+        assert!(map.is_empty());
+        for i in 0..100 {
+            assert!(map.insert(key(i), vec![i as u8]).unwrap());
+        }
+        assert!(!map.insert(key(5), b"five".to_vec()).unwrap());
+        assert_eq!(map.remove(&key(7)).unwrap(), Some(vec![7]));
+        assert_eq!(map.remove(&key(1000)).unwrap(), None);
+        assert_eq!(map.log_records(), 102);
+        map.sync().unwrap();
+    }
+    let map = DurableMap::open(&dir).unwrap();
+    assert_eq!(map.size(), 99);
+    assert_eq!(map.get(&key(5)), Some(&b"five".to_vec()));
+    assert!(!map.contains_key(&key(7)));
+    assert_eq!(map.map().validate(), Ok(()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checkpoint_test() {
+    let dir = temp_dir("checkpoint");
+    {
+        let mut map = DurableMap::open(&dir).unwrap();
+        for i in 0..50 {
+            map.insert(key(i), vec![1]).unwrap();
+        }
+        map.checkpoint().unwrap();
+        assert_eq!(map.log_records(), 0);
+        assert_eq!(fs::metadata(dir.join("wal")).unwrap().len(), 0);
+        for i in 0..10 {
+            map.remove(&key(i)).unwrap();
+        }
+        map.insert(key(100), vec![2]).unwrap();
+    }
+    let map = DurableMap::open(&dir).unwrap();
+    assert_eq!(map.size(), 41);
+    assert_eq!(map.log_records(), 11);
+    assert!(!map.contains_key(&key(0)));
+    assert_eq!(map.get(&key(100)), Some(&vec![2]));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checkpoint_test_automatic() {
+    let dir = temp_dir("automatic");
+    {
+        let mut map = DurableMap::open(&dir).unwrap();
+        map.set_checkpoint_every(Some(10));
+        for i in 0..25 {
+            map.insert(key(i), vec![]).unwrap();
+        }
+        assert_eq!(map.log_records(), 5);
+        // 自動の checkpoint の直前の操作もスナップショットに入る
+        for i in 0..5 {
+            map.remove(&key(i)).unwrap();
+        }
+        assert_eq!(map.log_records(), 0);
+    }
+    let map = DurableMap::open(&dir).unwrap();
+    assert_eq!(map.size(), 20);
+    assert!(!map.contains_key(&key(4)));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recover_test_torn_last_record() {
+    let dir = temp_dir("torn");
+    {
+        let mut map = DurableMap::open(&dir).unwrap();
+        for i in 0..10 {
+            map.insert(key(i), vec![i as u8; 8]).unwrap();
+        }
+    }
+    // 最後のレコードを書きかけの状態にする
+    let wal = dir.join("wal");
+    let len = fs::metadata(&wal).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&wal)
+        .unwrap()
+        .set_len(len - 3)
+        .unwrap();
+    {
+        let mut map = DurableMap::open(&dir).unwrap();
+        assert_eq!(map.size(), 9);
+        assert!(!map.contains_key(&key(9)));
+        // 切り詰めた後ろに追記したレコードも読み直せる
+        map.insert(key(42), vec![42]).unwrap();
+    }
+    let map = DurableMap::open(&dir).unwrap();
+    assert_eq!(map.size(), 10);
+    assert_eq!(map.get(&key(42)), Some(&vec![42]));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recover_test_garbage_tail() {
+    let dir = temp_dir("garbage");
+    {
+        let mut map = DurableMap::open(&dir).unwrap();
+        map.insert(key(1), vec![1]).unwrap();
+    }
+    let wal = dir.join("wal");
+    let len = fs::metadata(&wal).unwrap().len();
+    OpenOptions::new()
+        .append(true)
+        .open(&wal)
+        .unwrap()
+        .write_all(&[5, 0, 0, 0, 1, 2, 3, 4, 1, 0])
+        .unwrap();
+    let map = DurableMap::open(&dir).unwrap();
+    assert_eq!(map.size(), 1);
+    assert_eq!(fs::metadata(&wal).unwrap().len(), len);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recover_test_log_kept_after_checkpoint() {
+    let dir = temp_dir("kept");
+    {
+        let mut map = DurableMap::open(&dir).unwrap();
+        map.insert(key(1), vec![1]).unwrap();
+        map.insert(key(2), vec![2]).unwrap();
+        map.remove(&key(1)).unwrap();
+    }
+    // スナップショットを置き換えた後、ログを空にする前に止まった場合を再現する
+    let wal = dir.join("wal");
+    let log = fs::read(&wal).unwrap();
+    {
+        let mut map = DurableMap::open(&dir).unwrap();
+        map.checkpoint().unwrap();
+    }
+    fs::write(&wal, log).unwrap();
+    let map = DurableMap::open(&dir).unwrap();
+    assert_eq!(map.size(), 1);
+    assert_eq!(map.get(&key(2)), Some(&vec![2]));
+    fs::remove_dir_all(&dir).unwrap();
+}
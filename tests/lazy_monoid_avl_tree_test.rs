@@ -0,0 +1,181 @@
+use binary_search_tree::monoid_avl_tree::{LazyMonoidAVLTreeMap, MapMonoid, Monoid};
+
+// 区間加算・区間和
+struct AddSum;
+impl Monoid<i32, i64> for AddSum {
+    type Aggregate = i64;
+
+    fn identity() -> i64 {
+        0
+    }
+
+    fn combine(left: &i64, right: &i64) -> i64 {
+        left + right
+    }
+
+    fn lift(_key: &i32, value: &i64) -> i64 {
+        *value
+    }
+}
+impl MapMonoid<i32, i64> for AddSum {
+    type Map = i64;
+
+    fn compose(f: &i64, g: &i64) -> i64 {
+        f + g
+    }
+
+    fn apply(f: &i64, _key: &i32, value: &mut i64) {
+        *value += f;
+    }
+
+    fn map_aggregate(f: &i64, aggregate: &i64, size: usize) -> i64 {
+        aggregate + f * size as i64
+    }
+}
+
+// 区間アフィン変換・区間和 (作用の合成順序を確かめる)
+const MOD: i64 = 998_244_353;
+struct AffineSum;
+impl Monoid<i32, i64> for AffineSum {
+    type Aggregate = i64;
+
+    fn identity() -> i64 {
+        0
+    }
+
+    fn combine(left: &i64, right: &i64) -> i64 {
+        (left + right) % MOD
+    }
+
+    fn lift(_key: &i32, value: &i64) -> i64 {
+        *value
+    }
+}
+impl MapMonoid<i32, i64> for AffineSum {
+    type Map = (i64, i64);
+
+    fn compose(f: &(i64, i64), g: &(i64, i64)) -> (i64, i64) {
+        (f.0 * g.0 % MOD, (f.0 * g.1 + f.1) % MOD)
+    }
+
+    fn apply(f: &(i64, i64), _key: &i32, value: &mut i64) {
+        *value = (f.0 * *value + f.1) % MOD;
+    }
+
+    fn map_aggregate(f: &(i64, i64), aggregate: &i64, size: usize) -> i64 {
+        (f.0 * aggregate + f.1 * size as i64) % MOD
+    }
+}
+
+struct Lcg(u64);
+impl Lcg {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
+
+#[test]
+fn apply_range_test() {
+    let mut tree: LazyMonoidAVLTreeMap<i32, i64, AddSum> = (0..10).map(|i| (i, 0)).collect();
+    tree.apply_range(2..5, 10);
+    assert_eq!(tree.fold_all(), 30);
+    assert_eq!(tree.fold_range(0..3), 10);
+    assert_eq!(tree.fold_range(4..), 10);
+    assert_eq!(tree.get(&3), Some(&10));
+    assert_eq!(tree.get(&5), Some(&0));
+}
+
+#[test]
+fn apply_range_test_overlapping() {
+    let mut tree: LazyMonoidAVLTreeMap<i32, i64, AddSum> = (0..10).map(|i| (i, i as i64)).collect();
+    tree.apply_range(..5, 1);
+    tree.apply_range(3..=7, 100);
+    assert_eq!(
+        tree.values(),
+        vec![&1, &2, &3, &104, &105, &105, &106, &107, &8, &9]
+    );
+    assert_eq!(tree.fold_all(), 550);
+}
+
+#[test]
+fn apply_range_test_empty_range() {
+    let mut tree: LazyMonoidAVLTreeMap<i32, i64, AddSum> = (0..10).map(|i| (2 * i, 1)).collect();
+    tree.apply_range(3..4, 100);
+    tree.apply_range(100.., 100);
+    assert_eq!(tree.fold_all(), 10);
+}
+
+#[test]
+fn apply_range_test_insert_and_remove_after_apply() {
+    let mut tree: LazyMonoidAVLTreeMap<i32, i64, AddSum> = (0..20).map(|i| (2 * i, 0)).collect();
+    tree.apply_range(10..30, 5);
+    // 新しく挿入した要素には作用しない
+    tree.insert(11, 0);
+    tree.insert(13, 0);
+    assert_eq!(tree.remove(&12), Some(5));
+    assert_eq!(tree.fold_range(10..30), 5 * 9);
+    assert_eq!(tree.get(&11), Some(&0));
+    assert_eq!(tree.get(&28), Some(&5));
+    assert_eq!(tree.min(), Some((&0, &0)));
+    assert_eq!(tree.max(), Some((&38, &0)));
+    assert_eq!(tree.find_by_order(6), Some((&11, &0)));
+}
+
+#[test]
+fn max_right_test_after_apply() {
+    let mut tree: LazyMonoidAVLTreeMap<i32, i64, AddSum> = (0..10).map(|i| (i, 1)).collect();
+    tree.apply_range(5.., 9);
+    // 1 * 5 + 10 = 15 で次の要素を加えると 25 になる
+    assert_eq!(tree.max_right(&0, |&sum| sum <= 20), Some((&6, &10)));
+    assert_eq!(tree.min_left(&10, |&sum| sum <= 20), Some((&7, &10)));
+}
+
+#[test]
+fn apply_range_test_many_operations() {
+    let mut rng = Lcg(42);
+    let mut tree: LazyMonoidAVLTreeMap<i32, i64, AffineSum> = LazyMonoidAVLTreeMap::new();
+    let mut expected: Vec<Option<i64>> = vec![None; 200];
+    for _ in 0..2000 {
+        let a = rng.next(200) as i32;
+        let b = rng.next(200) as i32;
+        let (lower, upper) = (a.min(b), a.max(b));
+        match rng.next(4) {
+            0 => {
+                let value = rng.next(MOD as u64) as i64;
+                tree.insert(a, value);
+                expected[a as usize] = Some(value);
+            }
+            1 => {
+                assert_eq!(tree.remove(&a), expected[a as usize].take());
+            }
+            2 => {
+                let f = (rng.next(MOD as u64) as i64, rng.next(MOD as u64) as i64);
+                tree.apply_range(lower..upper, f);
+                for value in expected[lower as usize..upper as usize]
+                    .iter_mut()
+                    .flatten()
+                {
+                    *value = (f.0 * *value + f.1) % MOD;
+                }
+            }
+            _ => {
+                let sum = expected[lower as usize..upper as usize]
+                    .iter()
+                    .flatten()
+                    .fold(0, |acc, value| (acc + value) % MOD);
+                assert_eq!(tree.fold_range(lower..upper), sum);
+            }
+        }
+    }
+    assert_eq!(tree.values(), expected.iter().flatten().collect::<Vec<_>>());
+}
+
+#[test]
+fn fmt_tree_test() {
+    let tree: LazyMonoidAVLTreeMap<i32, i64, AddSum> = (1..=3).map(|i| (i, 0)).collect();
+    assert_eq!(tree.fmt_tree(false), "┌── 3\n2\n└── 1\n");
+}
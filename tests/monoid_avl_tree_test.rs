@@ -0,0 +1,248 @@
+use binary_search_tree::monoid_avl_tree::{Monoid, MonoidAVLTreeMap};
+
+struct Sum;
+impl Monoid<i32, i64> for Sum {
+    type Aggregate = i64;
+
+    fn identity() -> i64 {
+        0
+    }
+
+    fn combine(left: &i64, right: &i64) -> i64 {
+        left + right
+    }
+
+    fn lift(_key: &i32, value: &i64) -> i64 {
+        *value
+    }
+}
+
+struct Max;
+impl Monoid<i32, i64> for Max {
+    type Aggregate = Option<i64>;
+
+    fn identity() -> Option<i64> {
+        None
+    }
+
+    fn combine(left: &Option<i64>, right: &Option<i64>) -> Option<i64> {
+        match (left, right) {
+            (Some(l), Some(r)) => Some(*l.max(r)),
+            _ => left.or(*right),
+        }
+    }
+
+    fn lift(_key: &i32, value: &i64) -> Option<i64> {
+        Some(*value)
+    }
+}
+
+// 結合順序を確かめるための非可換なモノイド
+struct Concat;
+impl Monoid<i32, char> for Concat {
+    type Aggregate = String;
+
+    fn identity() -> String {
+        String::new()
+    }
+
+    fn combine(left: &String, right: &String) -> String {
+        format!("{}{}", left, right)
+    }
+
+    fn lift(_key: &i32, value: &char) -> String {
+        value.to_string()
+    }
+}
+
+#[test]
+fn fold_all_test() {
+    let mut tree: MonoidAVLTreeMap<i32, i64, Sum> = MonoidAVLTreeMap::new();
+    assert_eq!(tree.fold_all(), 0);
+    tree.insert(1, 10);
+    tree.insert(2, 20);
+    tree.insert(3, 30);
+    assert_eq!(tree.fold_all(), 60);
+}
+
+#[test]
+fn fold_all_test_after_overwrite_and_remove() {
+    let mut tree: MonoidAVLTreeMap<i32, i64, Sum> = MonoidAVLTreeMap::new();
+    tree.insert(1, 10);
+    tree.insert(2, 20);
+    tree.insert(3, 30);
+    assert!(!tree.insert(2, 200));
+    assert_eq!(tree.fold_all(), 240);
+    assert_eq!(tree.remove(&1), Some(10));
+    assert_eq!(tree.fold_all(), 230);
+}
+
+#[test]
+fn fold_range_test() {
+    let tree: MonoidAVLTreeMap<i32, i64, Sum> = (0..100).map(|i| (i, i as i64)).collect();
+    assert_eq!(tree.fold_range(10..20), (10..20).sum::<i64>());
+    assert_eq!(tree.fold_range(10..=20), (10..=20).sum::<i64>());
+    assert_eq!(tree.fold_range(..50), (0..50).sum::<i64>());
+    assert_eq!(tree.fold_range(90..), (90..100).sum::<i64>());
+    assert_eq!(tree.fold_range(..), (0..100).sum::<i64>());
+    assert_eq!(tree.fold_range(20..20), 0);
+    assert_eq!(tree.fold_range(200..300), 0);
+}
+
+#[test]
+fn fold_range_test_sparse_keys() {
+    let tree: MonoidAVLTreeMap<i32, i64, Max> = vec![(1, 5), (4, 3), (9, 8), (16, 1), (25, 7)]
+        .into_iter()
+        .collect();
+    assert_eq!(tree.fold_range(2..9), Some(3));
+    assert_eq!(tree.fold_range(2..=9), Some(8));
+    assert_eq!(tree.fold_range(10..25), Some(1));
+    assert_eq!(tree.fold_range(5..9), None);
+}
+
+#[test]
+fn fold_range_test_order() {
+    let tree: MonoidAVLTreeMap<i32, char, Concat> = "abcdefghij"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| (i as i32, c))
+        .collect();
+    assert_eq!(tree.fold_all(), "abcdefghij");
+    assert_eq!(tree.fold_range(2..7), "cdefg");
+    assert_eq!(tree.fold_prefix(4), "abcd");
+}
+
+#[test]
+fn fold_prefix_test() {
+    let tree: MonoidAVLTreeMap<i32, i64, Sum> = (0..100).map(|i| (2 * i, i as i64)).collect();
+    for rank in 0..=100 {
+        assert_eq!(tree.fold_prefix(rank), (0..rank as i64).sum::<i64>());
+    }
+    assert_eq!(tree.fold_prefix(1000), tree.fold_all());
+}
+
+#[test]
+fn modify_test() {
+    let mut tree: MonoidAVLTreeMap<i32, i64, Sum> = (0..10).map(|i| (i, 1)).collect();
+    assert_eq!(tree.modify(&5, |v| *v += 10), Some(()));
+    assert_eq!(tree.get(&5), Some(&11));
+    assert_eq!(tree.fold_range(5..6), 11);
+    assert_eq!(tree.fold_all(), 20);
+    assert_eq!(tree.modify(&50, |v| *v += 10), None);
+}
+
+#[test]
+fn fold_range_test_many_operations() {
+    let mut tree: MonoidAVLTreeMap<i32, i64, Sum> = MonoidAVLTreeMap::new();
+    let mut expected = vec![None; 500];
+    for i in 0..500 {
+        let key = (i * 173) % 500;
+        tree.insert(key, i as i64);
+        expected[key as usize] = Some(i as i64);
+    }
+    for key in (0..500).step_by(7) {
+        tree.remove(&key);
+        expected[key as usize] = None;
+    }
+    for (lower, upper) in [(0, 500), (13, 77), (100, 101), (250, 499)] {
+        let sum = expected[lower..upper].iter().flatten().sum::<i64>();
+        assert_eq!(tree.fold_range(lower as i32..upper as i32), sum);
+    }
+    assert_eq!(tree.size(), expected.iter().flatten().count());
+}
+
+#[test]
+fn max_right_test() {
+    // キー i に値 i を持つ
+    let tree: MonoidAVLTreeMap<i32, i64, Sum> = (1..=10).map(|i| (i, i as i64)).collect();
+    // 1 + 2 + 3 + 4 = 10 で 5 を加えると 15 になる
+    assert_eq!(tree.max_right(&1, |&sum| sum <= 10), Some((&5, &5)));
+    assert_eq!(tree.max_right(&1, |&sum| sum < 10), Some((&4, &4)));
+    assert_eq!(tree.max_right(&3, |&sum| sum <= 7), Some((&5, &5)));
+    assert_eq!(tree.max_right(&1, |&sum| sum <= 55), None);
+    assert_eq!(tree.max_right(&11, |&sum| sum <= 0), None);
+    assert_eq!(tree.max_right(&1, |&sum| sum <= 0), Some((&1, &1)));
+}
+
+#[test]
+fn max_right_test_start_not_exists() {
+    let tree: MonoidAVLTreeMap<i32, i64, Sum> = (0..10).map(|i| (2 * i, 1)).collect();
+    // 5 以上のキーは 6, 8, 10, ...
+    assert_eq!(tree.max_right(&5, |&sum| sum < 3), Some((&10, &1)));
+}
+
+#[test]
+fn max_right_test_many() {
+    let tree: MonoidAVLTreeMap<i32, i64, Sum> = (0..200).map(|i| (i, (i % 7) as i64)).collect();
+    for start in (0..200).step_by(13) {
+        for threshold in [0, 5, 40, 300] {
+            let mut sum = 0;
+            let expected = (start..200).find(|&i| {
+                sum += (i % 7) as i64;
+                sum > threshold
+            });
+            assert_eq!(
+                tree.max_right(&start, |&sum| sum <= threshold)
+                    .map(|(k, _)| *k),
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn min_left_test() {
+    let tree: MonoidAVLTreeMap<i32, i64, Sum> = (1..=10).map(|i| (i, i as i64)).collect();
+    // 10 + 9 = 19 で 8 を加えると 27 になる
+    assert_eq!(tree.min_left(&11, |&sum| sum <= 20), Some((&8, &8)));
+    assert_eq!(tree.min_left(&5, |&sum| sum <= 7), Some((&2, &2)));
+    assert_eq!(tree.min_left(&11, |&sum| sum <= 55), None);
+    assert_eq!(tree.min_left(&1, |&sum| sum <= 0), None);
+}
+
+#[test]
+fn min_left_test_many() {
+    let tree: MonoidAVLTreeMap<i32, i64, Sum> = (0..200).map(|i| (i, (i % 5) as i64)).collect();
+    for end in (0..=200).step_by(11) {
+        for threshold in [0, 3, 50, 500] {
+            let mut sum = 0;
+            let expected = (0..end).rev().find(|&i| {
+                sum += (i % 5) as i64;
+                sum > threshold
+            });
+            assert_eq!(
+                tree.min_left(&end, |&sum| sum <= threshold)
+                    .map(|(k, _)| *k),
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn max_right_test_order() {
+    let tree: MonoidAVLTreeMap<i32, char, Concat> = "abcdefghij"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| (i as i32, c))
+        .collect();
+    assert_eq!(
+        tree.max_right(&2, |s| "cdefg".starts_with(s.as_str())),
+        Some((&7, &'h'))
+    );
+    assert_eq!(
+        tree.min_left(&8, |s| "efgh".ends_with(s.as_str())),
+        Some((&3, &'d'))
+    );
+}
+
+#[test]
+fn fmt_tree_test() {
+    let mut tree: MonoidAVLTreeMap<i32, i64, Sum> = MonoidAVLTreeMap::new();
+    tree.insert(2, 20);
+    tree.insert(1, 10);
+    assert_eq!(
+        tree.fmt_tree(true),
+        "2 (size: 2, state: Left)\n└── 1 (size: 1, state: Equal)\n"
+    );
+}
@@ -0,0 +1,169 @@
+use binary_search_tree::avl_tree::AVLTreeMap;
+use binary_search_tree::observed_map::{MapObserver, ObservedMap};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Insert(i32, String),
+    Update(i32, String, String),
+    Remove(i32, String),
+}
+
+#[derive(Default)]
+struct Recorder(Vec<Event>);
+impl MapObserver<i32, String> for Recorder {
+    fn on_insert(&mut self, key: &i32, value: &String) {
+        self.0.push(Event::Insert(*key, value.clone()));
+    }
+
+    fn on_update(&mut self, key: &i32, old: &String, new: &String) {
+        self.0.push(Event::Update(*key, old.clone(), new.clone()));
+    }
+
+    fn on_remove(&mut self, key: &i32, value: &String) {
+        self.0.push(Event::Remove(*key, value.clone()));
+    }
+}
+
+// on_remove だけを受け取る
+struct RemoveCounter(usize);
+impl<K, V> MapObserver<K, V> for RemoveCounter {
+    fn on_remove(&mut self, _key: &K, _value: &V) {
+        self.0 += 1;
+    }
+}
+
+#[test]
+fn observer_test_insert_update_remove() {
+    let mut map = ObservedMap::new(Recorder::default());
+    assert!(map.insert(1, "a".to_string()));
+    assert!(map.insert(2, "b".to_string()));
+    assert!(!map.insert(1, "c".to_string()));
+    assert!(map.update(&2, |v| v.push('!')));
+    assert!(!map.update(&3, |v| v.push('!')));
+    assert_eq!(map.remove(&1), Some("c".to_string()));
+    assert_eq!(map.remove(&1), None);
+    assert_eq!(
+        map.observer().0,
+        vec![
+            Event::Insert(1, "a".to_string()),
+            Event::Insert(2, "b".to_string()),
+            Event::Update(1, "a".to_string(), "c".to_string()),
+            Event::Update(2, "b".to_string(), "b!".to_string()),
+            Event::Remove(1, "c".to_string()),
+        ]
+    );
+    assert_eq!(map.get(&2), Some(&"b!".to_string()));
+}
+
+#[test]
+fn observer_test_insert_after_rotation() {
+    // 昇順・降順・交互に挿入して単回転と二重回転を起こし、通知される要素が挿入したものと一致するかを見る
+    let keys = (0..100)
+        .chain((200..300).rev())
+        .chain((0..50).flat_map(|i| [100 + i, 199 - i]))
+        .collect::<Vec<i32>>();
+    let mut map = ObservedMap::new(Recorder::default());
+    for &key in &keys {
+        assert!(map.insert(key, key.to_string()));
+    }
+    assert_eq!(
+        map.observer().0,
+        keys.iter()
+            .map(|&key| Event::Insert(key, key.to_string()))
+            .collect::<Vec<_>>()
+    );
+    let (tree, _) = map.into_parts();
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn observer_test_retain() {
+    let tree: AVLTreeMap<_, _> = (0..6).map(|i| (i, i.to_string())).collect();
+    let mut map = ObservedMap::with_map(tree, Recorder::default());
+    map.retain(|k, _| k % 2 == 0);
+    assert_eq!(
+        map.observer().0,
+        vec![
+            Event::Remove(1, "1".to_string()),
+            Event::Remove(3, "3".to_string()),
+            Event::Remove(5, "5".to_string()),
+        ]
+    );
+    let (tree, _) = map.into_parts();
+    assert_eq!(tree.keys(), vec![&0, &2, &4]);
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn observer_test_retain_panic() {
+    // on_remove が panic しても、木は取り除いたあとの正しい状態になっている
+    struct Panicking;
+    impl MapObserver<i32, i32> for Panicking {
+        fn on_remove(&mut self, _key: &i32, _value: &i32) {
+            panic!("abort");
+        }
+    }
+
+    let tree: AVLTreeMap<_, _> = (0..10).map(|i| (i, i)).collect();
+    let mut map = ObservedMap::with_map(tree, Panicking);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        map.retain(|k, _| k % 2 == 0);
+    }));
+    assert!(result.is_err());
+    let (tree, _) = map.into_parts();
+    assert_eq!(tree.keys(), vec![&0, &2, &4, &6, &8]);
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn observer_test_mirror() {
+    // 通知だけから組み立てた写しが元の木と一致する
+    struct Mirror(AVLTreeMap<u32, u32>);
+    impl MapObserver<u32, u32> for Mirror {
+        fn on_insert(&mut self, key: &u32, value: &u32) {
+            self.0.insert(*key, *value);
+        }
+
+        fn on_update(&mut self, key: &u32, _old: &u32, new: &u32) {
+            *self.0.get_mut(key).unwrap() = *new;
+        }
+
+        fn on_remove(&mut self, key: &u32, _value: &u32) {
+            self.0.remove(key);
+        }
+    }
+
+    let mut map = ObservedMap::new(Mirror(AVLTreeMap::new()));
+    let mut x = 12345u32;
+    for _ in 0..2000 {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        let key = (x >> 16) % 200;
+        match x % 4 {
+            0 => {
+                map.remove(&key);
+            }
+            1 => {
+                map.update(&key, |v| *v += 1);
+            }
+            _ => {
+                map.insert(key, x);
+            }
+        }
+    }
+    map.retain(|k, _| k % 5 != 0);
+    let (tree, Mirror(mirror)) = map.into_parts();
+    assert_eq!(tree.keys(), mirror.keys());
+    assert_eq!(tree.values(), mirror.values());
+}
+
+#[test]
+fn observer_test_default_methods() {
+    let mut map = ObservedMap::new(RemoveCounter(0));
+    for i in 0..10 {
+        map.insert(i, ());
+    }
+    map.remove(&3);
+    map.retain(|k, _| *k < 5);
+    assert_eq!(map.observer().0, 6);
+    assert_eq!(map.size(), 4);
+}
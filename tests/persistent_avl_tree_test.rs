@@ -0,0 +1,152 @@
+use binary_search_tree::persistent_avl_tree::PersistentAVLMap;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// このスレッドで行われたメモリ確保の回数を数える
+struct CountingAlloc;
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[test]
+fn insert_test() {
+    let v0 = PersistentAVLMap::new();
+    let v1 = v0.insert(2, 'b');
+    let v2 = v1.insert(1, 'a');
+    let v3 = v2.insert(2, 'c');
+    assert!(v0.is_empty());
+    assert_eq!(
+        v1.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(2, 'b')]
+    );
+    assert_eq!(
+        v2.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(1, 'a'), (2, 'b')]
+    );
+    assert_eq!(
+        v3.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(1, 'a'), (2, 'c')]
+    );
+}
+
+#[test]
+fn remove_test() {
+    let v1: PersistentAVLMap<_, _> = (0..10).map(|i| (i, i * i)).collect();
+    let v2 = v1.remove(&3);
+    let v3 = v2.remove(&3);
+    assert_eq!(v1.size(), 10);
+    assert_eq!(v1.get(&3), Some(&9));
+    assert_eq!(v2.size(), 9);
+    assert_eq!(v2.get(&3), None);
+    assert_eq!(v3.size(), 9);
+}
+
+#[test]
+fn clone_test() {
+    let v1: PersistentAVLMap<_, _> = (0..10).map(|i| (i, i)).collect();
+    let v2 = v1.clone();
+    let v3 = v2.insert(100, 100);
+    assert_eq!(v1.keys(), v2.keys());
+    assert_eq!(v3.size(), 11);
+    assert_eq!(v2.size(), 10);
+}
+
+#[test]
+fn min_max_test() {
+    let tree: PersistentAVLMap<_, _> = vec![(5, 'a'), (1, 'b'), (9, 'c')].into_iter().collect();
+    assert_eq!(tree.min(), Some((&1, &'b')));
+    assert_eq!(tree.max(), Some((&9, &'c')));
+    assert_eq!(PersistentAVLMap::<i32, i32>::new().min(), None);
+}
+
+#[test]
+fn order_statistics_test() {
+    let tree: PersistentAVLMap<_, _> = (0..50).map(|i| (i * 2, i)).collect();
+    assert_eq!(tree.find_by_order(10), Some((&20, &10)));
+    assert_eq!(tree.find_by_order(50), None);
+    assert_eq!(tree.order_of_key(&21), 11);
+    assert_eq!(tree.order_of_key(&20), 10);
+    assert_eq!(tree.order_of_key(&1000), 50);
+}
+
+#[test]
+fn versions_test_many_operations() {
+    let mut versions = vec![PersistentAVLMap::new()];
+    let mut models = vec![std::collections::BTreeMap::new()];
+    let mut x: u64 = 1;
+    for _ in 0..1000 {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let base = (x >> 33) as usize % versions.len();
+        let key = (x >> 40) % 64;
+        let (tree, mut model) = (&versions[base], models[base].clone());
+        let next = if x.is_multiple_of(3) {
+            model.remove(&key);
+            tree.remove(&key)
+        } else {
+            model.insert(key, x);
+            tree.insert(key, x)
+        };
+        versions.push(next);
+        models.push(model);
+    }
+    for (tree, model) in versions.iter().zip(&models) {
+        assert_eq!(tree.size(), model.len());
+        assert_eq!(
+            tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            model.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>()
+        );
+        for (i, (k, _)) in model.iter().enumerate() {
+            assert_eq!(tree.order_of_key(k), i);
+        }
+    }
+}
+
+#[test]
+fn path_copy_allocation_test() {
+    // 複製するのは根からの経路上のノードと、回転で書き換える経路の隣のノードだけで、それぞれ 1 回ずつ確保する
+    let v1: PersistentAVLMap<_, _> = (0..1000).map(|i| (i * 2, i)).collect();
+    let height = v1.stats().height;
+    for key in [-1, 1, 999, 1001, 1999] {
+        let (v2, allocations) = count_allocations(|| v1.insert(key, 0));
+        assert!(allocations <= height + 3, "{allocations} allocations");
+        assert_eq!(v2.size(), 1001);
+    }
+    for key in [0, 500, 998, 1998] {
+        let (v2, allocations) = count_allocations(|| v1.remove(&key));
+        assert!(allocations <= height + 3, "{allocations} allocations");
+        assert_eq!(v2.size(), 999);
+    }
+    assert_eq!(v1.size(), 1000);
+}
+
+#[test]
+fn fmt_tree_test() {
+    let v1 = PersistentAVLMap::new().insert(2, 'b');
+    let v2 = v1.insert(1, 'a');
+    // 古い版は新しい版の影響を受けない
+    assert_eq!(v1.fmt_tree(false), "2\n");
+    assert_eq!(
+        v2.fmt_tree(true),
+        "2 (size: 2, state: Left)\n└── 1 (size: 1, state: Equal)\n"
+    );
+}
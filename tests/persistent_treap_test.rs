@@ -0,0 +1,169 @@
+use binary_search_tree::persistent_treap::PersistentTreap;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// このスレッドで行われたメモリ確保の回数を数える
+struct CountingAlloc;
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+fn pairs(tree: &PersistentTreap<i32, char>) -> Vec<(i32, char)> {
+    tree.into_iter().map(|(&k, &v)| (k, v)).collect()
+}
+
+#[test]
+fn insert_test() {
+    let v0 = PersistentTreap::new();
+    let v1 = v0.insert(2, 'b');
+    let v2 = v1.insert(1, 'a');
+    let v3 = v2.insert(2, 'c');
+    assert!(v0.is_empty());
+    assert_eq!(pairs(&v1), vec![(2, 'b')]);
+    assert_eq!(pairs(&v2), vec![(1, 'a'), (2, 'b')]);
+    assert_eq!(pairs(&v3), vec![(1, 'a'), (2, 'c')]);
+}
+
+#[test]
+fn remove_test() {
+    let v1: PersistentTreap<_, _> = vec![(1, 'a'), (2, 'b'), (3, 'c')].into_iter().collect();
+    let v2 = v1.remove(&2);
+    let v3 = v2.remove(&2);
+    assert_eq!(pairs(&v1), vec![(1, 'a'), (2, 'b'), (3, 'c')]);
+    assert_eq!(pairs(&v2), vec![(1, 'a'), (3, 'c')]);
+    assert_eq!(pairs(&v3), vec![(1, 'a'), (3, 'c')]);
+}
+
+#[test]
+fn split_test() {
+    let tree: PersistentTreap<_, _> = (0..10).map(|i| (i, (b'a' + i as u8) as char)).collect();
+    let (left, right) = tree.split(&4);
+    assert_eq!(left.keys(), vec![&0, &1, &2, &3]);
+    assert_eq!(right.keys(), vec![&4, &5, &6, &7, &8, &9]);
+    assert_eq!(tree.size(), 10);
+    assert_eq!(right.find_by_order(0), Some((&4, &'e')));
+    assert_eq!(right.order_of_key(&6), 2);
+}
+
+#[test]
+fn merge_test() {
+    let tree: PersistentTreap<_, _> = (0..10).map(|i| (i, 'x')).collect();
+    let (left, right) = tree.split(&5);
+    let edited = left.insert(3, 'y').merge(&right.remove(&7));
+    assert_eq!(edited.size(), 9);
+    assert_eq!(edited.get(&3), Some(&'y'));
+    assert!(!edited.contains_key(&7));
+    assert_eq!(tree.get(&3), Some(&'x'));
+    assert!(tree.contains_key(&7));
+    assert_eq!(left.merge(&right).keys(), tree.keys());
+}
+
+#[test]
+#[should_panic(expected = "keys of other must be greater")]
+fn merge_test_overlapping_keys() {
+    let tree: PersistentTreap<_, _> = (0..10).map(|i| (i, 'x')).collect();
+    let (left, right) = tree.split(&5);
+    let _ = right.merge(&left);
+}
+
+#[test]
+fn min_max_test() {
+    let tree: PersistentTreap<_, _> = vec![(5, 'a'), (1, 'b'), (9, 'c')].into_iter().collect();
+    assert_eq!(tree.min(), Some((&1, &'b')));
+    assert_eq!(tree.max(), Some((&9, &'c')));
+    let (left, _) = tree.split(&0);
+    assert_eq!(left.min(), None);
+}
+
+#[test]
+fn versions_test_many_operations() {
+    let mut versions = vec![PersistentTreap::new()];
+    let mut models = vec![std::collections::BTreeMap::new()];
+    let mut x: u64 = 1;
+    for _ in 0..1000 {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let base = (x >> 33) as usize % versions.len();
+        let key = (x >> 40) % 64;
+        let (tree, mut model) = (&versions[base], models[base].clone());
+        let next = match x % 4 {
+            0 => {
+                model.remove(&key);
+                tree.remove(&key)
+            }
+            1 => {
+                // 分割して片方を編集し、連結し直す
+                let (left, right) = tree.split(&key);
+                model.insert(key, x);
+                left.merge(&right.insert(key, x))
+            }
+            _ => {
+                model.insert(key, x);
+                tree.insert(key, x)
+            }
+        };
+        versions.push(next);
+        models.push(model);
+    }
+    for (tree, model) in versions.iter().zip(&models) {
+        assert_eq!(tree.size(), model.len());
+        assert_eq!(
+            tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+            model.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn path_copy_allocation_test() {
+    // 複製するのは根からたどる経路上のノードだけで、それぞれ 1 回ずつ確保する
+    let tree: PersistentTreap<_, _> = (0..1000).map(|i| (i * 2, 'x')).collect();
+    let height = tree.stats().height;
+    for key in [-1, 1, 999, 1999] {
+        let (inserted, allocations) = count_allocations(|| tree.insert(key, 'y'));
+        assert!(allocations <= height + 1, "{allocations} allocations");
+        assert_eq!(inserted.size(), 1001);
+    }
+    for key in [0, 1000, 1998] {
+        let (removed, allocations) = count_allocations(|| tree.remove(&key));
+        assert!(allocations <= height, "{allocations} allocations");
+        assert_eq!(removed.size(), 999);
+    }
+    let ((left, right), allocations) = count_allocations(|| tree.split(&1001));
+    assert!(allocations <= height, "{allocations} allocations");
+    let (merged, allocations) = count_allocations(|| left.merge(&right));
+    assert!(allocations <= left.stats().height + right.stats().height);
+    assert_eq!(merged.keys(), tree.keys());
+}
+
+#[test]
+fn fmt_tree_test() {
+    let tree: PersistentTreap<_, _> = (0..10).map(|i| (i, i)).collect();
+    let text = tree.fmt_tree(true);
+    assert_eq!(text.lines().count(), 10);
+    assert_eq!(text.matches("priority: ").count(), 10);
+    assert_eq!(text.lines().filter(|line| !line.contains("── ")).count(), 1);
+    assert_eq!(
+        PersistentTreap::<i32, i32>::new().fmt_tree(false),
+        "(empty)\n"
+    );
+}
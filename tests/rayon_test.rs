@@ -0,0 +1,126 @@
+use binary_search_tree::avl_tree::{AVLTreeMap, AVLTreeSet};
+use rayon::prelude::*;
+
+fn random_keys(seed: u64, n: usize, range: u64) -> Vec<u64> {
+    let mut x = seed;
+    (0..n)
+        .map(|_| {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (x >> 33) % range
+        })
+        .collect()
+}
+
+#[test]
+fn par_iter_test() {
+    let tree: AVLTreeMap<_, _> = (0..100_000u64).map(|i| (i, i * 2)).collect();
+    let collected: Vec<_> = tree.par_iter().map(|(&k, &v)| (k, v)).collect();
+    let expected: Vec<_> = tree.into_iter().map(|(&k, &v)| (k, v)).collect();
+    assert_eq!(collected, expected);
+    assert_eq!(
+        tree.par_iter().map(|(_, &v)| v).sum::<u64>(),
+        99_999 * 100_000
+    );
+    assert_eq!(tree.par_iter().len(), 100_000);
+}
+
+#[test]
+fn par_iter_test_every_split() {
+    // 要素数の小さい木でも、どの位置で分割しても順序が保たれる
+    for n in 0..40u32 {
+        let tree: AVLTreeMap<_, _> = (0..n).map(|i| (i, ())).collect();
+        let keys: Vec<_> = tree.par_iter().with_max_len(1).map(|(&k, _)| k).collect();
+        assert_eq!(keys, (0..n).collect::<Vec<_>>());
+        let reversed: Vec<_> = tree.par_iter().rev().map(|(&k, _)| k).collect();
+        assert_eq!(reversed, (0..n).rev().collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn par_iter_test_set() {
+    let set: AVLTreeSet<_> = random_keys(1, 10_000, 50_000).into_iter().collect();
+    let keys: Vec<_> = set.par_iter().collect();
+    assert_eq!(keys, set.keys());
+    let (index, key) = set
+        .par_iter()
+        .enumerate()
+        .find_any(|(_, &key)| key == *set.max().unwrap())
+        .unwrap();
+    assert_eq!((index, key), (set.size() - 1, set.max().unwrap()));
+}
+
+#[test]
+fn from_sorted_par_test() {
+    for n in [0, 1, 2, 3, 100, 4095, 4096, 50_000] {
+        let tree = AVLTreeMap::from_sorted_par((0..n).map(|i| (i, i + 1)).collect());
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.size(), n);
+        if n > 0 {
+            assert_eq!(tree.find_by_order(n / 2), Some((&(n / 2), &(n / 2 + 1))));
+        }
+        let set = AVLTreeSet::from_sorted_par((0..n).collect());
+        assert_eq!(set.validate(), Ok(()));
+        assert_eq!(set.size(), n);
+    }
+}
+
+#[test]
+#[should_panic(expected = "strictly increasing")]
+fn from_sorted_par_test_unsorted() {
+    AVLTreeSet::from_sorted_par(vec![1, 3, 2]);
+}
+
+#[test]
+fn par_union_test() {
+    for (seed, n, m) in [
+        (1, 0, 10),
+        (2, 10, 0),
+        (3, 20_000, 50),
+        (4, 50, 20_000),
+        (5, 30_000, 30_000),
+    ] {
+        let a_keys = random_keys(seed, n, 60_000);
+        let b_keys = random_keys(seed + 100, m, 60_000);
+        let a: AVLTreeSet<_> = a_keys.iter().copied().collect();
+        let b: AVLTreeSet<_> = b_keys.iter().copied().collect();
+        let mut expected: Vec<_> = a_keys.iter().chain(&b_keys).copied().collect();
+        expected.sort();
+        expected.dedup();
+
+        let union = a.par_union(b);
+        assert_eq!(union.validate(), Ok(()));
+        assert_eq!(
+            union.keys().into_iter().copied().collect::<Vec<_>>(),
+            expected
+        );
+    }
+}
+
+#[test]
+fn par_intersection_test() {
+    for (seed, n, m) in [
+        (1, 0, 10),
+        (2, 10, 0),
+        (3, 20_000, 50),
+        (4, 50, 20_000),
+        (5, 30_000, 30_000),
+    ] {
+        let a: AVLTreeSet<_> = random_keys(seed, n, 60_000).into_iter().collect();
+        let b: AVLTreeSet<_> = random_keys(seed + 100, m, 60_000).into_iter().collect();
+        let expected: Vec<_> = a
+            .keys()
+            .into_iter()
+            .filter(|key| b.contains(key))
+            .copied()
+            .collect();
+
+        let intersection = a.par_intersection(b);
+        assert_eq!(intersection.validate(), Ok(()));
+        assert_eq!(
+            intersection.keys().into_iter().copied().collect::<Vec<_>>(),
+            expected
+        );
+    }
+}
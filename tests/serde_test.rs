@@ -0,0 +1,99 @@
+use binary_search_tree::avl_tree::{AVLTreeMap, AVLTreeSet};
+use binary_search_tree::serde_support::DuplicateKeys;
+use binary_search_tree::treap::Treap;
+
+fn map_with(json: &str, duplicate_keys: DuplicateKeys) -> serde_json::Result<AVLTreeMap<i32, i32>> {
+    AVLTreeMap::deserialize_with(
+        &mut serde_json::Deserializer::from_str(json),
+        duplicate_keys,
+    )
+}
+
+#[test]
+fn serialize_test_map() {
+    let tree: AVLTreeMap<_, _> = [(3, "c"), (1, "a"), (2, "b")].into_iter().collect();
+    assert_eq!(
+        serde_json::to_string(&tree).unwrap(),
+        r#"{"1":"a","2":"b","3":"c"}"#
+    );
+}
+
+#[test]
+fn serialize_test_set() {
+    let set: AVLTreeSet<_> = [3, 1, 2].into_iter().collect();
+    assert_eq!(serde_json::to_string(&set).unwrap(), "[1,2,3]");
+}
+
+#[test]
+fn deserialize_test_sorted() {
+    let tree: AVLTreeMap<_, _> = (0..1000).map(|i| (i, i * 2)).collect();
+    let json = serde_json::to_string(&tree).unwrap();
+    let restored: AVLTreeMap<i32, i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.validate(), Ok(()));
+    assert_eq!(restored.size(), 1000);
+    assert_eq!(restored.stats().height, 10);
+    assert_eq!(restored.keys(), tree.keys());
+    assert_eq!(restored.values(), tree.values());
+}
+
+#[test]
+fn deserialize_test_unsorted() {
+    let tree: AVLTreeMap<i32, i32> = serde_json::from_str(r#"{"1":1,"3":3,"2":2,"0":0}"#).unwrap();
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(tree.keys(), vec![&0, &1, &2, &3]);
+
+    let set: AVLTreeSet<i32> = serde_json::from_str("[5,4,3,2,1,1]").unwrap();
+    assert_eq!(set.validate(), Ok(()));
+    assert_eq!(set.keys(), vec![&1, &2, &3, &4, &5]);
+}
+
+#[test]
+fn deserialize_test_duplicate_keys() {
+    let json = r#"{"1":1,"2":2,"1":10}"#;
+    assert_eq!(
+        map_with(json, DuplicateKeys::KeepLast).unwrap().get(&1),
+        Some(&10)
+    );
+    assert_eq!(
+        map_with(json, DuplicateKeys::KeepFirst).unwrap().get(&1),
+        Some(&1)
+    );
+    assert!(map_with(json, DuplicateKeys::Error).is_err());
+
+    // 隣り合った重複
+    let json = r#"{"1":1,"1":10,"2":2}"#;
+    assert_eq!(
+        map_with(json, DuplicateKeys::KeepLast).unwrap().get(&1),
+        Some(&10)
+    );
+    assert_eq!(
+        map_with(json, DuplicateKeys::KeepFirst).unwrap().get(&1),
+        Some(&1)
+    );
+    assert!(map_with(json, DuplicateKeys::Error).is_err());
+    assert!(map_with(r#"{"1":1,"2":2}"#, DuplicateKeys::Error).is_ok());
+}
+
+#[test]
+fn deserialize_test_treap() {
+    let tree: Treap<_, _> = (0..1000).map(|i| (i, i)).collect();
+    let json = serde_json::to_string(&tree).unwrap();
+    let restored: Treap<i32, i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.validate(), Ok(()));
+    assert_eq!(restored.size(), 1000);
+    assert_eq!(restored.find_by_order(500), Some((&500, &500)));
+
+    let unsorted: Treap<i32, i32> = serde_json::from_str(r#"{"2":2,"1":1,"3":3}"#).unwrap();
+    assert_eq!(unsorted.validate(), Ok(()));
+    assert_eq!(unsorted.keys(), vec![&1, &2, &3]);
+}
+
+#[test]
+fn deserialize_test_sorted_every_size() {
+    for n in 0..70 {
+        let json = serde_json::to_string(&(0..n).collect::<Vec<_>>()).unwrap();
+        let set: AVLTreeSet<i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(set.validate(), Ok(()));
+        assert_eq!(set.size(), n as usize);
+    }
+}
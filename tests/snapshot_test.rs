@@ -0,0 +1,134 @@
+use binary_search_tree::avl_tree::{AVLTreeMap, AVLTreeSet};
+use binary_search_tree::invariant::ViolationKind;
+use binary_search_tree::snapshot::{SnapshotError, SnapshotKind};
+use binary_search_tree::treap::Treap;
+
+// ヘッダは識別子 4 バイト、版 2 バイト、種類 1 バイト、要素数 8 バイト
+const HEADER_LEN: usize = 15;
+// AVLTreeMap<i32, i32> の各要素は深さ、平衡状態、キー、値の 10 バイト
+const ENTRY_LEN: usize = 10;
+
+fn snapshot_of(tree: &AVLTreeMap<i32, i32>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    tree.write_snapshot(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn round_trip_test_map() {
+    let mut tree = AVLTreeMap::new();
+    for i in 0..1000 {
+        tree.insert((i * 37) % 1000, i);
+    }
+    for i in (0..1000).step_by(3) {
+        tree.remove(&i);
+    }
+    let bytes = snapshot_of(&tree);
+    assert_eq!(bytes.len(), HEADER_LEN + ENTRY_LEN * tree.size());
+    let restored = AVLTreeMap::<i32, i32>::read_snapshot(bytes.as_slice()).unwrap();
+    assert_eq!(restored.fmt_tree(true), tree.fmt_tree(true));
+    assert_eq!(restored.values(), tree.values());
+}
+
+#[test]
+fn round_trip_test_set_of_strings() {
+    let set: AVLTreeSet<_> = ["pear", "apple", "fig", "kiwi"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let mut bytes = Vec::new();
+    set.write_snapshot(&mut bytes).unwrap();
+    let restored = AVLTreeSet::<String>::read_snapshot(bytes.as_slice()).unwrap();
+    assert_eq!(restored.fmt_tree(true), set.fmt_tree(true));
+}
+
+#[test]
+fn round_trip_test_treap() {
+    let tree: Treap<_, _> = (0..1000).map(|i| (i, i * 2)).collect();
+    let mut bytes = Vec::new();
+    tree.write_snapshot(&mut bytes).unwrap();
+    let restored = Treap::<i32, i32>::read_snapshot(bytes.as_slice()).unwrap();
+    assert_eq!(restored.fmt_tree(true), tree.fmt_tree(true));
+    assert_eq!(restored.find_by_order(123), Some((&123, &246)));
+}
+
+#[test]
+fn round_trip_test_empty() {
+    let tree = AVLTreeMap::new();
+    let restored = AVLTreeMap::<i32, i32>::read_snapshot(snapshot_of(&tree).as_slice()).unwrap();
+    assert!(restored.is_empty());
+}
+
+#[test]
+fn read_snapshot_test_header_errors() {
+    let tree: AVLTreeMap<_, _> = (1..=3).map(|i| (i, i)).collect();
+    let bytes = snapshot_of(&tree);
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = b'X';
+    assert!(matches!(
+        AVLTreeMap::<i32, i32>::read_snapshot(bad_magic.as_slice()),
+        Err(SnapshotError::InvalidMagic)
+    ));
+
+    let mut bad_version = bytes.clone();
+    bad_version[4] = 9;
+    assert!(matches!(
+        AVLTreeMap::<i32, i32>::read_snapshot(bad_version.as_slice()),
+        Err(SnapshotError::UnsupportedVersion(9))
+    ));
+
+    assert!(matches!(
+        Treap::<i32, i32>::read_snapshot(bytes.as_slice()),
+        Err(SnapshotError::KindMismatch {
+            expected: SnapshotKind::Treap,
+            found: 0
+        })
+    ));
+
+    let truncated = &bytes[..bytes.len() - 1];
+    assert!(matches!(
+        AVLTreeMap::<i32, i32>::read_snapshot(truncated),
+        Err(SnapshotError::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof
+    ));
+}
+
+#[test]
+fn read_snapshot_test_corrupted_entries() {
+    // 間順に 1 (深さ 1), 2 (深さ 0), 3 (深さ 1) が並ぶ
+    let tree: AVLTreeMap<_, _> = (1..=3).map(|i| (i, i)).collect();
+    let bytes = snapshot_of(&tree);
+
+    let mut bad_state = bytes.clone();
+    bad_state[HEADER_LEN + 1] = 7;
+    assert!(matches!(
+        AVLTreeMap::<i32, i32>::read_snapshot(bad_state.as_slice()),
+        Err(SnapshotError::InvalidMetadata { index: 0 })
+    ));
+
+    let mut bad_depth = bytes.clone();
+    bad_depth[HEADER_LEN] = 2;
+    assert!(matches!(
+        AVLTreeMap::<i32, i32>::read_snapshot(bad_depth.as_slice()),
+        Err(SnapshotError::InvalidMetadata { index: 1 })
+    ));
+
+    let mut wrong_state = bytes.clone();
+    wrong_state[HEADER_LEN + ENTRY_LEN + 1] = 1;
+    match AVLTreeMap::<i32, i32>::read_snapshot(wrong_state.as_slice()) {
+        Err(SnapshotError::InvalidTree(violation)) => {
+            assert!(violation.path().is_empty());
+            assert!(matches!(violation.kind(), ViolationKind::Balance { .. }));
+        }
+        _ => panic!("expected an invalid tree"),
+    }
+
+    let mut unordered = bytes.clone();
+    unordered[HEADER_LEN + 2] = 5;
+    match AVLTreeMap::<i32, i32>::read_snapshot(unordered.as_slice()) {
+        Err(SnapshotError::InvalidTree(violation)) => {
+            assert_eq!(violation.kind(), &ViolationKind::Order);
+        }
+        _ => panic!("expected an invalid tree"),
+    }
+}
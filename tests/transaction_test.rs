@@ -0,0 +1,101 @@
+use binary_search_tree::avl_tree::AVLTreeMap;
+
+fn contents(tree: &AVLTreeMap<i32, String>) -> Vec<(i32, String)> {
+    tree.into_iter().map(|(k, v)| (*k, v.clone())).collect()
+}
+
+fn sample() -> AVLTreeMap<i32, String> {
+    (0..20).map(|i| (i, format!("v{i}"))).collect()
+}
+
+#[test]
+fn commit_test() {
+    let mut tree = sample();
+    let mut tx = tree.begin();
+    assert!(tx.insert(100, "new".to_string()));
+    assert!(!tx.insert(3, "replaced".to_string()));
+    assert_eq!(tx.remove(&5), Some(&"v5".to_string()));
+    assert_eq!(tx.remove(&50), None);
+    assert_eq!(tx.len(), 3);
+    assert_eq!(tx.get(&3), Some(&"replaced".to_string()));
+    tx.commit();
+    assert_eq!(tree.size(), 20);
+    assert_eq!(tree.get(&3), Some(&"replaced".to_string()));
+    assert_eq!(tree.get(&5), None);
+    assert_eq!(tree.get(&100), Some(&"new".to_string()));
+}
+
+#[test]
+fn rollback_test() {
+    let mut tree = sample();
+    let before = contents(&tree);
+    let mut tx = tree.begin();
+    for i in 10..40 {
+        tx.insert(i, format!("w{i}"));
+    }
+    for i in (0..30).step_by(2) {
+        tx.remove(&i);
+    }
+    // 同じキーへの操作が何度あっても最初の値に戻る
+    tx.insert(4, "again".to_string());
+    tx.remove(&4);
+    tx.insert(4, "last".to_string());
+    assert_eq!(tx.tree().validate(), Ok(()));
+    tx.rollback();
+    assert_eq!(contents(&tree), before);
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn rollback_test_on_drop() {
+    let mut tree = sample();
+    let before = contents(&tree);
+    {
+        let mut tx = tree.begin();
+        tx.insert(1, "x".to_string());
+        tx.remove(&2);
+    }
+    assert_eq!(contents(&tree), before);
+}
+
+#[test]
+fn transaction_test() {
+    let mut tree = sample();
+    let before = contents(&tree);
+
+    // 途中の検査に失敗したら、それまでの操作も含めて取り消す
+    let result = tree.transaction(|tx| {
+        for i in 0..10 {
+            if tx.get(&i).is_none() {
+                return Err(i);
+            }
+            tx.insert(i, "checked".to_string());
+            tx.remove(&(i + 1));
+        }
+        Ok(())
+    });
+    assert_eq!(result, Err(1));
+    assert_eq!(contents(&tree), before);
+
+    let result: Result<usize, ()> = tree.transaction(|tx| {
+        tx.remove(&0);
+        tx.insert(-1, "m".to_string());
+        Ok(tx.size())
+    });
+    assert_eq!(result, Ok(20));
+    assert_eq!(tree.min(), Some((&-1, &"m".to_string())));
+}
+
+#[test]
+fn transaction_test_panic() {
+    let mut tree = sample();
+    let before = contents(&tree);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _: Result<(), ()> = tree.transaction(|tx| {
+            tx.insert(7, "x".to_string());
+            panic!("abort");
+        });
+    }));
+    assert!(result.is_err());
+    assert_eq!(contents(&tree), before);
+}
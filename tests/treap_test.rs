@@ -0,0 +1,239 @@
+use binary_search_tree::treap::Treap;
+
+#[test]
+fn into_iter_test_empty() {
+    let tree: Treap<i32, i32> = Treap::new();
+    let mut iter = tree.into_iter();
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn insert_test() {
+    let mut tree = Treap::new();
+    tree.insert(1, 1);
+    tree.insert(2, 2);
+    tree.insert(3, 3);
+    tree.insert(4, 4);
+    tree.insert(5, 5);
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(1, 1), (2, 2), (3, 3), (4, 4), (5, 5)]
+    );
+}
+
+#[test]
+fn insert_test_confused() {
+    let mut tree = Treap::new();
+    tree.insert(4, 4);
+    tree.insert(1, 1);
+    tree.insert(3, 3);
+    tree.insert(2, 2);
+    tree.insert(5, 5);
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(1, 1), (2, 2), (3, 3), (4, 4), (5, 5)]
+    );
+}
+
+#[test]
+fn insert_test_overwrite() {
+    let mut tree = Treap::new();
+    assert!(tree.insert(1, 1));
+    assert!(tree.insert(2, 2));
+    assert!(!tree.insert(1, 10));
+    assert_eq!(tree.get(&1), Some(&10));
+    assert_eq!(tree.size(), 2);
+}
+
+#[test]
+fn remove_test() {
+    let mut tree = Treap::new();
+    tree.insert(1, 1);
+    tree.insert(2, 2);
+    tree.insert(3, 3);
+    tree.insert(4, 4);
+    tree.insert(5, 5);
+    assert_eq!(tree.remove(&3), Some(3));
+    assert_eq!(
+        tree.into_iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+        vec![(1, 1), (2, 2), (4, 4), (5, 5)]
+    );
+}
+
+#[test]
+fn remove_test_all() {
+    let mut tree = Treap::new();
+    tree.insert(1, 1);
+    tree.insert(2, 2);
+    tree.insert(3, 3);
+    tree.insert(4, 4);
+    tree.insert(5, 5);
+    assert_eq!(tree.remove(&1), Some(1));
+    assert_eq!(tree.remove(&2), Some(2));
+    assert_eq!(tree.remove(&3), Some(3));
+    assert_eq!(tree.remove(&4), Some(4));
+    assert_eq!(tree.remove(&5), Some(5));
+    assert!(tree.is_empty())
+}
+
+#[test]
+fn remove_test_not_exists() {
+    let mut tree = Treap::new();
+    tree.insert(1, 1);
+    tree.insert(2, 2);
+    tree.insert(3, 3);
+    assert_eq!(tree.remove(&6), None);
+    assert_eq!(tree.size(), 3);
+}
+
+#[test]
+fn get_test() {
+    let mut tree = Treap::new();
+    tree.insert(4, 4);
+    tree.insert(1, 1);
+    tree.insert(3, 3);
+    tree.insert(2, 2);
+    tree.insert(5, 5);
+    assert_eq!(tree.get(&4), Some(&4));
+    assert_eq!(tree.get(&6), None);
+}
+
+#[test]
+fn min_max_test() {
+    let mut tree = Treap::new();
+    tree.insert(4, 4);
+    tree.insert(1, 1);
+    tree.insert(3, 3);
+    tree.insert(2, 2);
+    tree.insert(5, 5);
+    assert_eq!(tree.min(), Some((&1, &1)));
+    assert_eq!(tree.max(), Some((&5, &5)));
+}
+
+#[test]
+fn min_max_test_empty() {
+    let tree: Treap<i32, i32> = Treap::new();
+    assert_eq!(tree.min(), None);
+    assert_eq!(tree.max(), None);
+}
+
+#[test]
+fn find_by_order_test() {
+    let mut tree = Treap::new();
+    tree.insert(4, 4);
+    tree.insert(1, 1);
+    tree.insert(3, 3);
+    tree.insert(2, 2);
+    tree.insert(5, 5);
+    assert_eq!(tree.find_by_order(0), Some((&1, &1)));
+    assert_eq!(tree.find_by_order(1), Some((&2, &2)));
+    assert_eq!(tree.find_by_order(2), Some((&3, &3)));
+    assert_eq!(tree.find_by_order(3), Some((&4, &4)));
+    assert_eq!(tree.find_by_order(4), Some((&5, &5)));
+    assert_eq!(tree.find_by_order(5), None);
+}
+
+#[test]
+fn find_by_order_test_after_removed() {
+    let mut tree: Treap<_, _> = (0..100).map(|i| (i, i)).collect();
+    for i in (0..100).step_by(2) {
+        assert_eq!(tree.remove(&i), Some(i));
+    }
+    for i in 0..50 {
+        assert_eq!(tree.find_by_order(i), Some((&(2 * i + 1), &(2 * i + 1))));
+    }
+    assert_eq!(tree.find_by_order(50), None);
+}
+
+#[test]
+fn order_of_key_test() {
+    let mut tree = Treap::new();
+    tree.insert(1, 1);
+    tree.insert(2, 2);
+    tree.insert(4, 3);
+    tree.insert(8, 4);
+    tree.insert(16, 5);
+    assert_eq!(tree.order_of_key(&1), 0);
+    assert_eq!(tree.order_of_key(&2), 1);
+    assert_eq!(tree.order_of_key(&3), 2);
+    assert_eq!(tree.order_of_key(&4), 2);
+    assert_eq!(tree.order_of_key(&5), 3);
+    assert_eq!(tree.order_of_key(&16), 4);
+    assert_eq!(tree.order_of_key(&17), 5);
+}
+
+#[test]
+fn split_off_test() {
+    let mut tree: Treap<_, _> = (0..10).map(|i| (i, i)).collect();
+    let right = tree.split_off(&6);
+    assert_eq!(tree.keys(), vec![&0, &1, &2, &3, &4, &5]);
+    assert_eq!(right.keys(), vec![&6, &7, &8, &9]);
+    assert_eq!(tree.size(), 6);
+    assert_eq!(right.size(), 4);
+    assert_eq!(right.find_by_order(0), Some((&6, &6)));
+}
+
+#[test]
+fn merge_test() {
+    let mut left: Treap<_, _> = (0..6).map(|i| (i, i)).collect();
+    let right: Treap<_, _> = (6..10).map(|i| (i, i)).collect();
+    left.merge(right);
+    assert_eq!(left.size(), 10);
+    for i in 0..10 {
+        assert_eq!(left.find_by_order(i), Some((&i, &i)));
+        assert_eq!(left.order_of_key(&i), i);
+    }
+}
+
+#[test]
+#[should_panic(expected = "keys of other must be greater")]
+fn merge_test_overlapping_keys() {
+    let mut left: Treap<_, _> = (0..6).map(|i| (i, i)).collect();
+    let right: Treap<_, _> = (5..10).map(|i| (i, i)).collect();
+    left.merge(right);
+}
+
+#[test]
+fn validate_test() {
+    let mut tree: Treap<_, _> = (0..1000).map(|i| ((i * 37) % 1000, i)).collect();
+    assert_eq!(tree.validate(), Ok(()));
+    for i in (0..1000).step_by(3) {
+        tree.remove(&i);
+    }
+    assert_eq!(tree.validate(), Ok(()));
+    let right = tree.split_off(&500);
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(right.validate(), Ok(()));
+    tree.merge(right);
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn stats_test() {
+    let tree: Treap<_, _> = (0..1000).map(|i| (i, i)).collect();
+    let stats = tree.stats();
+    assert_eq!(stats.node_count, 1000);
+    assert_eq!(stats.balance, None);
+    assert!(stats.height >= 10);
+    assert_eq!(stats.max_depth, stats.height - 1);
+    assert!(stats.average_depth < stats.max_depth as f64);
+}
+
+#[test]
+fn to_dot_test() {
+    let tree: Treap<_, _> = (0..10).map(|i| (i, i)).collect();
+    let dot = tree.to_dot();
+    assert!(dot.starts_with("digraph {\n"));
+    assert_eq!(dot.matches("priority: ").count(), 10);
+    assert_eq!(dot.matches("->").count(), 20);
+    assert_eq!(dot.matches("shape=point").count(), 11);
+}
+
+#[test]
+fn fmt_tree_test() {
+    let tree: Treap<_, _> = (0..10).map(|i| (i, i)).collect();
+    let text = tree.fmt_tree(true);
+    assert_eq!(text.lines().count(), 10);
+    assert_eq!(text.matches("priority: ").count(), 10);
+    assert_eq!(text.lines().filter(|line| !line.contains("── ")).count(), 1);
+}
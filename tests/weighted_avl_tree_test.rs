@@ -0,0 +1,152 @@
+use binary_search_tree::weighted_avl_tree::{WeightOverflow, WeightedAVLTreeMap};
+
+#[test]
+fn total_weight_test() {
+    let mut tree = WeightedAVLTreeMap::new();
+    assert_eq!(tree.total_weight(), 0);
+    tree.insert(1, 'a', 3);
+    tree.insert(2, 'b', 5);
+    tree.insert(3, 'c', 2);
+    assert_eq!(tree.total_weight(), 10);
+    assert_eq!(tree.size(), 3);
+}
+
+#[test]
+fn total_weight_test_after_overwrite_and_remove() {
+    let mut tree = WeightedAVLTreeMap::new();
+    tree.insert(1, 'a', 3);
+    tree.insert(2, 'b', 5);
+    tree.insert(3, 'c', 2);
+    assert!(!tree.insert(2, 'B', 7));
+    assert_eq!(tree.get(&2), Some(&'B'));
+    assert_eq!(tree.total_weight(), 12);
+    assert_eq!(tree.remove(&1), Some(('a', 3)));
+    assert_eq!(tree.total_weight(), 9);
+}
+
+#[test]
+fn set_weight_test() {
+    let mut tree: WeightedAVLTreeMap<_, _> = (0..10).map(|i| (i, i, 1)).collect();
+    assert_eq!(tree.set_weight(&4, 10), Some(1));
+    assert_eq!(tree.weight(&4), Some(10));
+    assert_eq!(tree.total_weight(), 19);
+    assert_eq!(tree.weighted_rank(&5), 14);
+    assert_eq!(tree.set_weight(&40, 10), None);
+}
+
+#[test]
+fn weighted_rank_test() {
+    let mut tree = WeightedAVLTreeMap::new();
+    tree.insert(10, 'a', 3);
+    tree.insert(20, 'b', 5);
+    tree.insert(30, 'c', 2);
+    assert_eq!(tree.weighted_rank(&5), 0);
+    assert_eq!(tree.weighted_rank(&10), 0);
+    assert_eq!(tree.weighted_rank(&15), 3);
+    assert_eq!(tree.weighted_rank(&20), 3);
+    assert_eq!(tree.weighted_rank(&30), 8);
+    assert_eq!(tree.weighted_rank(&31), 10);
+}
+
+#[test]
+fn find_by_weight_test() {
+    let mut tree = WeightedAVLTreeMap::new();
+    tree.insert(10, 'a', 3);
+    tree.insert(20, 'b', 5);
+    tree.insert(30, 'c', 2);
+    assert_eq!(tree.find_by_weight(0), Some((&10, &'a')));
+    assert_eq!(tree.find_by_weight(2), Some((&10, &'a')));
+    assert_eq!(tree.find_by_weight(3), Some((&20, &'b')));
+    assert_eq!(tree.find_by_weight(7), Some((&20, &'b')));
+    assert_eq!(tree.find_by_weight(8), Some((&30, &'c')));
+    assert_eq!(tree.find_by_weight(9), Some((&30, &'c')));
+    assert_eq!(tree.find_by_weight(10), None);
+}
+
+#[test]
+fn find_by_weight_test_zero_weight() {
+    let mut tree = WeightedAVLTreeMap::new();
+    tree.insert(1, 'a', 0);
+    tree.insert(2, 'b', 2);
+    tree.insert(3, 'c', 0);
+    tree.insert(4, 'd', 1);
+    assert_eq!(tree.find_by_weight(0), Some((&2, &'b')));
+    assert_eq!(tree.find_by_weight(2), Some((&4, &'d')));
+    assert_eq!(tree.find_by_weight(3), None);
+}
+
+#[test]
+fn find_by_weight_test_many_operations() {
+    let mut tree = WeightedAVLTreeMap::new();
+    for i in 0..300u64 {
+        tree.insert((i * 71) % 300, (), i % 4);
+    }
+    for key in (0..300).step_by(5) {
+        tree.remove(&key);
+    }
+    let mut cumulative = 0;
+    for key in (0..300u64).filter(|key| key % 5 != 0) {
+        let weight = tree.weight(&key).unwrap();
+        assert_eq!(tree.weighted_rank(&key), cumulative);
+        for w in cumulative..cumulative + weight {
+            assert_eq!(tree.find_by_weight(w).map(|(k, _)| *k), Some(key));
+        }
+        cumulative += weight;
+    }
+    assert_eq!(tree.total_weight(), cumulative);
+    assert_eq!(tree.find_by_weight(cumulative), None);
+}
+
+#[test]
+fn find_by_order_test() {
+    let tree: WeightedAVLTreeMap<_, _> = vec![(4, 4, 9), (1, 1, 2), (3, 3, 1), (2, 2, 5)]
+        .into_iter()
+        .collect();
+    assert_eq!(tree.find_by_order(0), Some((&1, &1)));
+    assert_eq!(tree.find_by_order(3), Some((&4, &4)));
+    assert_eq!(tree.order_of_key(&3), 2);
+    assert_eq!(
+        tree.into_iter()
+            .map(|(&k, _, w)| (k, w))
+            .collect::<Vec<_>>(),
+        vec![(1, 2), (2, 5), (3, 1), (4, 9)]
+    );
+}
+
+#[test]
+fn weight_overflow_test() {
+    let mut tree = WeightedAVLTreeMap::new();
+    tree.insert(1, 'a', u64::MAX - 10);
+    tree.insert(2, 'b', 5);
+    assert_eq!(tree.try_insert(3, 'c', 6), Err(WeightOverflow));
+    assert_eq!(tree.get(&3), None);
+    assert_eq!(tree.total_weight(), u64::MAX - 5);
+    // 上書きする場合は元の重みを除いて数える
+    assert_eq!(tree.try_insert(2, 'B', 10), Ok(false));
+    assert_eq!(tree.total_weight(), u64::MAX);
+    assert_eq!(tree.try_set_weight(&1, u64::MAX - 9), Err(WeightOverflow));
+    assert_eq!(tree.weight(&1), Some(u64::MAX - 10));
+    assert_eq!(tree.try_set_weight(&1, 0), Ok(Some(u64::MAX - 10)));
+    assert_eq!(tree.try_set_weight(&4, u64::MAX), Ok(None));
+    assert_eq!(tree.find_by_weight(9), Some((&2, &'B')));
+    assert_eq!(tree.weighted_rank(&3), 10);
+}
+
+#[test]
+#[should_panic(expected = "total weight overflow")]
+fn weight_overflow_test_insert_panics() {
+    let mut tree = WeightedAVLTreeMap::new();
+    tree.insert(1, (), u64::MAX);
+    tree.insert(2, (), 1);
+}
+
+#[test]
+fn fmt_tree_test() {
+    let mut tree = WeightedAVLTreeMap::new();
+    tree.insert(2, 'b', 5);
+    tree.insert(1, 'a', 3);
+    assert_eq!(
+        tree.fmt_tree(true),
+        "2 (weight: 5, size: 2, state: Left)\n└── 1 (weight: 3, size: 1, state: Equal)\n"
+    );
+}